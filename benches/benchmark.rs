@@ -9,30 +9,30 @@ pub fn criterion_benchmark(c: &mut Criterion) {
     let mut group = c.benchmark_group("simple benchmark");
     let path = PathBuf::from("./benches/output/insert");
 
-    let mut db = DB::new(&path);
+    let db = DB::new(&path).unwrap();
     let mut rng = rand::thread_rng();
     group.bench_function("insert", |b| {
         b.iter(|| {
-            black_box(
-                db.set(rng.gen::<u32>().to_string(), rng.gen::<u32>().to_string())
-                    .unwrap(),
-            )
+            db.set(rng.gen::<u32>().to_string(), rng.gen::<u32>().to_string())
+                .unwrap()
         })
     });
 
     group.bench_function("get un-matched", |b| {
-        b.iter(|| black_box(db.get(&"b".to_string())))
+        b.iter(|| black_box(db.get(&"b".to_string()).unwrap()))
     });
 
     db.set("a".to_string(), "b".to_string()).unwrap();
     group.bench_function("get matched - in memtable", |b| {
-        b.iter(|| black_box(db.get(&"a".to_string())))
+        b.iter(|| black_box(db.get(&"a".to_string()).unwrap()))
     });
 
-    let db = DB::new(&path);
+    db.close().unwrap();
+    let db = DB::new(&path).unwrap();
     group.bench_function("get matched - disk", |b| {
-        b.iter(|| black_box(db.get(&"a".to_string())))
+        b.iter(|| black_box(db.get(&"a".to_string()).unwrap()))
     });
+    drop(db);
 
     remove_dir_all(path).unwrap();
 }
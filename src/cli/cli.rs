@@ -1,50 +1,301 @@
-#![allow(dead_code)]
-use std::io::stdin;
-use std::io::{Error, ErrorKind};
-
-#[derive(Copy, Clone)]
-enum InputCommandType {
-    Get,
-    Set,
-}
-
-pub struct InputCommand {
-    command: InputCommandType,
-    key: String,
-    value: String,
-}
-pub fn get_input() -> Result<InputCommand, Error> {
-    let mut input_key = String::new();
-    stdin().read_line(&mut input_key)?;
-    let input_key = input_key.to_lowercase();
-    let mut iter = input_key.split_whitespace();
-    let command = match iter.next() {
-        Some("set") => InputCommandType::Set,
-        Some("get") => InputCommandType::Get,
-        other => {
-            return Err(Error::new(
-                ErrorKind::InvalidInput,
-                format!(
-                    "Could not parse command. Expected 'set' or 'get' but got '{0}'",
-                    other.unwrap_or_default()
-                ),
-            ))
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::db::db::DB;
+use crate::db::options::{FlushOptions, VerifyOptions};
+use crate::error::{Error, Result};
+use crate::storage::sstable::SSTable;
+
+pub const HELP: &str = "\
+get <key>                 print the value of a key
+set <key> <value>         set a key, also called put
+delete <key>              delete a key
+scan [<start> [<end>]]    print the keys from start (inclusive) to end (exclusive)
+prefix <prefix>           print the keys starting with prefix
+count [<prefix>]          count the keys, or the keys starting with prefix
+flush                     write the memtable to an SSTable
+compact [<level>]         compact the SSTables of a level, 0 by default
+stats                     print the number of SSTables and entries per level
+verify [--no-checksums]   check the SSTables and the manifest for corruption
+help                      print this message
+quit                      leave the shell
+
+Keys and values containing spaces can be quoted like in a shell: set name \"Jane Doe\"";
+
+/// A command typed into the shell
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputCommand {
+    Get {
+        key: String,
+    },
+    Set {
+        key: String,
+        value: String,
+    },
+    Delete {
+        key: String,
+    },
+    Scan {
+        start: Option<String>,
+        end: Option<String>,
+    },
+    Prefix {
+        prefix: String,
+    },
+    Count {
+        prefix: Option<String>,
+    },
+    Flush,
+    Compact {
+        level: u8,
+    },
+    Stats,
+    Verify {
+        verify_checksums: bool,
+    },
+    Help,
+    Quit,
+}
+
+impl InputCommand {
+    /// Parses a line split into words like a shell would, so quoted words may contain spaces.
+    /// Returns `None` for a blank line
+    pub fn parse(line: &str) -> Result<Option<InputCommand>> {
+        let words = shlex::split(line)
+            .ok_or_else(|| Error::InvalidArgument("unterminated quote or escape".to_owned()))?;
+        if words.is_empty() {
+            return Ok(None);
+        }
+        InputCommand::from_words(&words).map(Some)
+    }
+
+    /// Parses a command given as separate words, e.g. command line arguments
+    pub fn from_words(words: &[String]) -> Result<InputCommand> {
+        let (name, args) = match words.split_first() {
+            Some((name, args)) => (name.to_lowercase(), args),
+            None => return Err(Error::InvalidArgument("missing command".to_owned())),
+        };
+
+        let command = match (name.as_str(), args) {
+            ("get", [key]) => InputCommand::Get { key: key.clone() },
+            ("set" | "put", [key, value]) => InputCommand::Set {
+                key: key.clone(),
+                value: value.clone(),
+            },
+            ("delete", [key]) => InputCommand::Delete { key: key.clone() },
+            ("scan", start) if start.len() <= 2 => InputCommand::Scan {
+                start: start.first().cloned(),
+                end: start.get(1).cloned(),
+            },
+            ("prefix", [prefix]) => InputCommand::Prefix {
+                prefix: prefix.clone(),
+            },
+            ("count", []) => InputCommand::Count { prefix: None },
+            ("count", [prefix]) => InputCommand::Count {
+                prefix: Some(prefix.clone()),
+            },
+            ("flush", []) => InputCommand::Flush,
+            ("compact", []) => InputCommand::Compact { level: 0 },
+            ("compact", [level]) => InputCommand::Compact {
+                level: level.parse().map_err(|_| {
+                    Error::InvalidArgument(format!("'{}' is not a valid level", level))
+                })?,
+            },
+            ("stats", []) => InputCommand::Stats,
+            ("verify", []) => InputCommand::Verify {
+                verify_checksums: true,
+            },
+            ("verify", [flag]) if flag == "--no-checksums" => InputCommand::Verify {
+                verify_checksums: false,
+            },
+            ("help", []) => InputCommand::Help,
+            ("quit" | "exit", []) => InputCommand::Quit,
+            (
+                "get" | "set" | "put" | "delete" | "scan" | "prefix" | "count" | "flush"
+                | "compact" | "stats" | "verify" | "help" | "quit" | "exit",
+                _,
+            ) => {
+                return Err(Error::InvalidArgument(format!(
+                    "wrong number of arguments for '{}', see 'help'",
+                    name
+                )))
+            }
+            _ => {
+                return Err(Error::InvalidArgument(format!(
+                    "unknown command '{}', see 'help'",
+                    name
+                )))
+            }
+        };
+        Ok(command)
+    }
+}
+
+/// Runs `command` against `db`, writing what it prints to `out`. Does nothing for `Quit`,
+/// which is up to the caller
+pub fn execute(db: &DB, command: &InputCommand, out: &mut dyn Write) -> Result<()> {
+    match command {
+        InputCommand::Get { key } => match db.get(key)? {
+            Some(value) => writeln!(out, "{}", value)?,
+            None => writeln!(out, "(not found)")?,
+        },
+        InputCommand::Set { key, value } => db.set(key.clone(), value.clone())?,
+        InputCommand::Delete { key } => db.delete(key.clone())?,
+        InputCommand::Scan { start, end } => {
+            let entries = match (start, end) {
+                (None, _) => db.scan(..)?,
+                (Some(start), None) => db.scan(start.clone()..)?,
+                (Some(start), Some(end)) => db.scan(start.clone()..end.clone())?,
+            };
+            write_entries(&entries, out)?;
+        }
+        InputCommand::Prefix { prefix } => write_entries(&db.scan_prefix(prefix)?, out)?,
+        InputCommand::Count { prefix } => {
+            let count = db.scan_prefix(prefix.as_deref().unwrap_or_default())?.len();
+            writeln!(out, "{}", count)?;
         }
-    };
-
-    let key = match iter.next() {
-        Some(v) => v.to_string(),
-        None => return Err(Error::new(ErrorKind::InvalidInput, "Could not parse input")),
-    };
-    let value = match (command, iter.next()) {
-        (InputCommandType::Set, Some(v)) => v.to_string(),
-        (InputCommandType::Get, other) => other.unwrap_or_default().to_string(),
-        (_, _) => return Err(Error::new(ErrorKind::InvalidInput, "Could not parse input")),
-    };
-
-    Ok(InputCommand {
-        command,
-        key,
-        value,
-    })
+        InputCommand::Flush => db.flush(&FlushOptions::default())?,
+        InputCommand::Compact { level } => db.compact(*level)?,
+        InputCommand::Stats => write_stats(db, out)?,
+        InputCommand::Verify { verify_checksums } => {
+            let verify_options = VerifyOptions::default().verify_checksums(*verify_checksums);
+            let report = db.verify(&verify_options)?;
+            writeln!(out, "{}", report)?;
+            // fail with the first problem, so scripts can tell
+            let problems = report.problems().collect::<Vec<_>>();
+            if let Some(first) = problems.first() {
+                let message = match problems.len() {
+                    1 => first.message.clone(),
+                    count => format!("{} (and {} more problems)", first.message, count - 1),
+                };
+                return Err(Error::corruption(&first.file, first.offset, message));
+            }
+        }
+        InputCommand::Help => writeln!(out, "{}", HELP)?,
+        InputCommand::Quit => {}
+    }
+    Ok(())
+}
+
+/// Writes one entry per line, quoted so the output can be pasted back into `set` commands
+pub fn write_entries(entries: &[(String, String)], out: &mut dyn Write) -> Result<()> {
+    for (key, value) in entries {
+        writeln!(out, "{} {}", quote(key), quote(value))?;
+    }
+    Ok(())
+}
+
+fn quote(word: &str) -> String {
+    match shlex::try_quote(word) {
+        Ok(quoted) => quoted.into_owned(),
+        // words with a nul byte can not be quoted for a shell, so show them escaped instead
+        Err(_) => format!("{:?}", word),
+    }
+}
+
+#[derive(Default)]
+struct LevelStats {
+    files: usize,
+    entries: u64,
+    tombstones: u64,
+    bytes: u64,
+}
+
+fn write_stats(db: &DB, out: &mut dyn Write) -> Result<()> {
+    let mut levels = BTreeMap::<u8, LevelStats>::new();
+    for (path, properties) in db.table_properties()? {
+        let level = levels.entry(SSTable::level_from_path(&path)?).or_default();
+        level.files += 1;
+        level.entries += properties.num_entries;
+        level.tombstones += properties.num_tombstones;
+        level.bytes += properties.raw_key_size + properties.raw_value_size;
+    }
+
+    if levels.is_empty() {
+        writeln!(out, "no SSTables")?;
+    }
+    for (level, stats) in levels {
+        writeln!(
+            out,
+            "level {}: {} files, {} entries, {} tombstones, {} bytes of keys and values",
+            level, stats.files, stats.entries, stats.tombstones, stats.bytes
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::db::options::Options;
+    use crate::env::MemEnv;
+
+    fn parse(line: &str) -> InputCommand {
+        InputCommand::parse(line).unwrap().unwrap()
+    }
+
+    #[test]
+    fn parse_quoted() {
+        assert_eq!(
+            parse(r#"SET "first name" 'Jane Doe'"#),
+            InputCommand::Set {
+                key: "first name".to_owned(),
+                value: "Jane Doe".to_owned()
+            }
+        );
+        assert_eq!(
+            parse(r"get a\ b"),
+            InputCommand::Get {
+                key: "a b".to_owned()
+            }
+        );
+        assert_eq!(
+            parse("scan a"),
+            InputCommand::Scan {
+                start: Some("a".to_owned()),
+                end: None
+            }
+        );
+        assert_eq!(parse("compact 1"), InputCommand::Compact { level: 1 });
+        assert!(InputCommand::parse("   ").unwrap().is_none());
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(InputCommand::parse("set a").is_err());
+        assert!(InputCommand::parse("scan a b c").is_err());
+        assert!(InputCommand::parse("compact x").is_err());
+        assert!(InputCommand::parse("get 'a").is_err());
+        assert!(InputCommand::parse("frobnicate").is_err());
+    }
+
+    #[test]
+    fn execute_commands() {
+        let env = Arc::new(MemEnv::new());
+        let db = DB::open(Path::new("cli"), Options::default().env(env)).unwrap();
+        let run = |line: &str| {
+            let mut out = vec![];
+            execute(&db, &parse(line), &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        run("set 'a b' 'hello world'");
+        run("set ab 1");
+        run("set c 2");
+        run("delete c");
+        assert_eq!(run("get 'a b'"), "hello world\n");
+        assert_eq!(run("get c"), "(not found)\n");
+        assert_eq!(run("scan"), "'a b' 'hello world'\nab 1\n");
+        assert_eq!(run("prefix ab"), "ab 1\n");
+        assert_eq!(run("count a"), "2\n");
+
+        run("flush");
+        assert_eq!(
+            run("stats"),
+            "level 0: 1 files, 3 entries, 1 tombstones, 18 bytes of keys and values\n"
+        );
+    }
 }
@@ -0,0 +1,487 @@
+use std::io::{BufRead, Write};
+use std::ops::Bound;
+use std::path::Path;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::{json, Value};
+
+use crate::db::db::DB;
+use crate::db::options::WriteOptions;
+use crate::db::write_batch::WriteBatch;
+use crate::error::{Error, Result};
+
+/// Number of records `import` writes at a time, unless `--batch-size` says otherwise
+pub const DEFAULT_BATCH_SIZE: usize = 1000;
+
+/// How keys and values are written by `export` and read by `import`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DataFormat {
+    /// One JSON object per line, with the fields `key` and `value`
+    #[default]
+    JsonLines,
+    /// A header line, then one key and value per line
+    Csv,
+}
+
+impl DataFormat {
+    pub fn from_name(name: &str) -> Result<DataFormat> {
+        match name {
+            "jsonl" => Ok(DataFormat::JsonLines),
+            "csv" => Ok(DataFormat::Csv),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown format '{}', expected jsonl or csv",
+                name
+            ))),
+        }
+    }
+
+    /// The format of a file named `file`, going by its extension
+    fn from_file(file: Option<&str>) -> DataFormat {
+        match file.map(Path::new).and_then(Path::extension) {
+            Some(extension) if extension == "csv" => DataFormat::Csv,
+            _ => DataFormat::JsonLines,
+        }
+    }
+}
+
+/// Names of the key and value fields, or CSV columns
+fn field_names(base64: bool) -> [&'static str; 2] {
+    if base64 {
+        ["key_base64", "value_base64"]
+    } else {
+        ["key", "value"]
+    }
+}
+
+/// Writes the live keys and values of a database, or of a range of its keys
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Export {
+    pub format: DataFormat,
+    /// Write keys and values in base64, in the fields `key_base64` and `value_base64`
+    pub base64: bool,
+    /// Only keys starting with this prefix
+    pub prefix: Option<String>,
+    /// Only keys from `start` (inclusive) to `end` (exclusive)
+    pub start: Option<String>,
+    pub end: Option<String>,
+    /// Where to write to, standard output if `None`
+    pub file: Option<String>,
+}
+
+impl Export {
+    pub fn from_args(args: &[String]) -> Result<Export> {
+        let mut export = Export::default();
+        let mut format = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--format" => format = Some(DataFormat::from_name(value(arg, args.next())?)?),
+                "--base64" => export.base64 = true,
+                "--prefix" => export.prefix = Some(value(arg, args.next())?.to_owned()),
+                "--start" => export.start = Some(value(arg, args.next())?.to_owned()),
+                "--end" => export.end = Some(value(arg, args.next())?.to_owned()),
+                _ => set_file(&mut export.file, arg)?,
+            }
+        }
+        if export.prefix.is_some() && (export.start.is_some() || export.end.is_some()) {
+            return Err(Error::InvalidArgument(
+                "--prefix can not be combined with --start or --end".to_owned(),
+            ));
+        }
+        export.format = format.unwrap_or_else(|| DataFormat::from_file(export.file.as_deref()));
+        Ok(export)
+    }
+
+    /// Writes the keys and values to `out`, sorted by key, as they are read. Returns how many
+    /// were written
+    pub fn run(&self, db: &DB, out: &mut dyn Write) -> Result<usize> {
+        let entries = match &self.prefix {
+            Some(prefix) => db.iter_prefix(prefix)?,
+            None => db.iter((
+                self.start.clone().map_or(Bound::Unbounded, Bound::Included),
+                self.end.clone().map_or(Bound::Unbounded, Bound::Excluded),
+            ))?,
+        };
+        let encode = |text: &str| match self.base64 {
+            true => STANDARD.encode(text),
+            false => text.to_owned(),
+        };
+        let [key_field, value_field] = field_names(self.base64);
+
+        let mut written = 0;
+        match self.format {
+            DataFormat::JsonLines => {
+                for entry in entries {
+                    let (key, value) = entry?;
+                    let json = json!({ key_field: encode(&key), value_field: encode(&value) });
+                    serde_json::to_writer(&mut *out, &json).map_err(std::io::Error::from)?;
+                    writeln!(out)?;
+                    written += 1;
+                }
+            }
+            DataFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(&mut *out);
+                writer
+                    .write_record([key_field, value_field])
+                    .map_err(std::io::Error::from)?;
+                for entry in entries {
+                    let (key, value) = entry?;
+                    writer
+                        .write_record([encode(&key), encode(&value)])
+                        .map_err(std::io::Error::from)?;
+                    written += 1;
+                }
+                writer.flush()?;
+            }
+        }
+        Ok(written)
+    }
+}
+
+/// Sets the keys and values written by `Export`, a batch at a time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    pub format: DataFormat,
+    /// Records per write
+    pub batch_size: usize,
+    /// Check every line, without writing anything
+    pub dry_run: bool,
+    /// Report the number of records imported after every batch
+    pub progress: bool,
+    /// Where to read from, standard input if `None`
+    pub file: Option<String>,
+}
+
+impl Default for Import {
+    fn default() -> Import {
+        Import {
+            format: DataFormat::default(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            dry_run: false,
+            progress: false,
+            file: None,
+        }
+    }
+}
+
+impl Import {
+    pub fn from_args(args: &[String]) -> Result<Import> {
+        let mut import = Import::default();
+        let mut format = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--format" => format = Some(DataFormat::from_name(value(arg, args.next())?)?),
+                "--batch-size" => {
+                    let batch_size = value(arg, args.next())?;
+                    import.batch_size = batch_size
+                        .parse()
+                        .ok()
+                        .filter(|batch_size| *batch_size > 0)
+                        .ok_or_else(|| {
+                            Error::InvalidArgument(format!(
+                                "'{}' is not a valid batch size",
+                                batch_size
+                            ))
+                        })?;
+                }
+                "--dry-run" => import.dry_run = true,
+                "--progress" => import.progress = true,
+                _ => set_file(&mut import.file, arg)?,
+            }
+        }
+        import.format = format.unwrap_or_else(|| DataFormat::from_file(import.file.as_deref()));
+        Ok(import)
+    }
+
+    /// Reads keys and values from `input` and sets them in batches of `batch_size`, calling
+    /// `progress` with the number of records so far after each batch. Nothing is written on a
+    /// dry run, but every record is checked all the same. Returns the number of records.
+    ///
+    /// Fails on the first line that can not be read, after the batches before it were written
+    pub fn run(
+        &self,
+        db: &DB,
+        input: &mut dyn BufRead,
+        progress: &mut dyn FnMut(usize),
+    ) -> Result<usize> {
+        let mut records = 0;
+        let mut batch = WriteBatch::new();
+        let mut write = |batch: WriteBatch, records: usize| -> Result<()> {
+            if !self.dry_run && !batch.is_empty() {
+                db.write(batch, &WriteOptions::default())?;
+            }
+            progress(records);
+            Ok(())
+        };
+
+        let mut add = |key: String, value: String| -> Result<()> {
+            batch.set(key, value);
+            records += 1;
+            if batch.len() >= self.batch_size {
+                write(std::mem::take(&mut batch), records)?;
+            }
+            Ok(())
+        };
+        match self.format {
+            DataFormat::JsonLines => read_json_lines(input, &mut add)?,
+            DataFormat::Csv => read_csv(input, &mut add)?,
+        }
+
+        if !batch.is_empty() {
+            write(batch, records)?;
+        }
+        Ok(records)
+    }
+}
+
+fn read_json_lines(
+    input: &mut dyn BufRead,
+    add: &mut dyn FnMut(String, String) -> Result<()>,
+) -> Result<()> {
+    for (number, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let invalid =
+            |message: String| Error::InvalidArgument(format!("line {}: {}", number + 1, message));
+
+        let json: Value = serde_json::from_str(&line).map_err(|err| invalid(err.to_string()))?;
+        let field = |name: &str| json.get(name).and_then(Value::as_str);
+        let (key, value) = match (field("key"), field("value")) {
+            (Some(key), Some(value)) => (key.to_owned(), value.to_owned()),
+            _ => match (field("key_base64"), field("value_base64")) {
+                (Some(key), Some(value)) => (
+                    decode(key).map_err(invalid)?,
+                    decode(value).map_err(invalid)?,
+                ),
+                _ => {
+                    return Err(invalid(
+                        "expected the string fields key and value, or key_base64 and \
+                         value_base64"
+                            .to_owned(),
+                    ))
+                }
+            },
+        };
+        add(key, value)?;
+    }
+    Ok(())
+}
+
+fn read_csv(
+    input: &mut dyn BufRead,
+    add: &mut dyn FnMut(String, String) -> Result<()>,
+) -> Result<()> {
+    let mut reader = csv::Reader::from_reader(input);
+    let invalid =
+        |line: u64, message: String| Error::InvalidArgument(format!("line {}: {}", line, message));
+    let csv_error = |err: csv::Error| {
+        let line = err.position().map_or(1, |position| position.line());
+        match err.kind() {
+            csv::ErrorKind::Io(_) => std::io::Error::from(err).into(),
+            csv::ErrorKind::UnequalLengths {
+                expected_len, len, ..
+            } => invalid(
+                line,
+                format!("expected {} fields, found {}", expected_len, len),
+            ),
+            csv::ErrorKind::Utf8 { .. } => invalid(line, "invalid UTF-8".to_owned()),
+            _ => invalid(line, err.to_string()),
+        }
+    };
+
+    let headers = reader.headers().map_err(csv_error)?.clone();
+    let base64 = if headers == field_names(false)[..] {
+        false
+    } else if headers == field_names(true)[..] {
+        true
+    } else {
+        return Err(invalid(
+            1,
+            "expected the header key,value or key_base64,value_base64".to_owned(),
+        ));
+    };
+
+    for record in reader.records() {
+        let record = record.map_err(csv_error)?;
+        let line = record.position().map_or(0, |position| position.line());
+        let (key, value) = (&record[0], &record[1]);
+        if base64 {
+            add(
+                decode(key).map_err(|message| invalid(line, message))?,
+                decode(value).map_err(|message| invalid(line, message))?,
+            )?;
+        } else {
+            add(key.to_owned(), value.to_owned())?;
+        }
+    }
+    Ok(())
+}
+
+/// Decodes a base64 key or value, which must be UTF-8 like every key and value
+fn decode(text: &str) -> std::result::Result<String, String> {
+    let bytes = STANDARD
+        .decode(text)
+        .map_err(|err| format!("invalid base64 '{}': {}", text, err))?;
+    String::from_utf8(bytes).map_err(|_| format!("base64 '{}' is not valid UTF-8", text))
+}
+
+/// The value of the command line option `option`
+fn value<'a>(option: &str, value: Option<&'a String>) -> Result<&'a str> {
+    value
+        .map(String::as_str)
+        .ok_or_else(|| Error::InvalidArgument(format!("{} needs a value", option)))
+}
+
+fn set_file(file: &mut Option<String>, arg: &str) -> Result<()> {
+    if arg.starts_with("--") {
+        return Err(Error::InvalidArgument(format!("unknown option {}", arg)));
+    }
+    if file.is_some() {
+        return Err(Error::InvalidArgument("expected a single file".to_owned()));
+    }
+    if arg != "-" {
+        *file = Some(arg.to_owned());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::db::options::Options;
+    use crate::env::MemEnv;
+
+    fn args(line: &str) -> Vec<String> {
+        shlex::split(line).unwrap()
+    }
+
+    fn export(db: &DB, line: &str) -> String {
+        let mut out = vec![];
+        Export::from_args(&args(line))
+            .unwrap()
+            .run(db, &mut out)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn import(db: &DB, line: &str, input: &str) -> Result<(usize, Vec<usize>)> {
+        let mut progress = vec![];
+        let records =
+            Import::from_args(&args(line))?.run(db, &mut input.as_bytes(), &mut |records| {
+                progress.push(records)
+            })?;
+        Ok((records, progress))
+    }
+
+    fn open(name: &str) -> DB {
+        let options = Options::default().env(Arc::new(MemEnv::new()));
+        DB::open(Path::new(name), options).unwrap()
+    }
+
+    #[test]
+    fn from_args() {
+        let export = Export::from_args(&args("--prefix a out.csv")).unwrap();
+        assert_eq!(export.format, DataFormat::Csv);
+        assert_eq!(export.file.as_deref(), Some("out.csv"));
+        let export = Export::from_args(&args("--format jsonl --base64 out.csv")).unwrap();
+        assert_eq!(export.format, DataFormat::JsonLines);
+        assert!(export.base64);
+        assert!(Export::from_args(&args("--prefix a --start b")).is_err());
+        assert!(Export::from_args(&args("--format xml")).is_err());
+
+        let import = Import::from_args(&args("--batch-size 10 --dry-run -")).unwrap();
+        assert_eq!(import.batch_size, 10);
+        assert!(import.dry_run);
+        assert_eq!(import.file, None);
+        assert!(Import::from_args(&args("--batch-size 0")).is_err());
+        assert!(Import::from_args(&args("a b")).is_err());
+    }
+
+    #[test]
+    fn export_and_import() {
+        let db = open("export");
+        db.set("a".to_owned(), "1".to_owned()).unwrap();
+        db.set("b,\"c\"".to_owned(), "line\nbreak".to_owned())
+            .unwrap();
+        db.set("c".to_owned(), "3".to_owned()).unwrap();
+        db.set("d".to_owned(), "gone".to_owned()).unwrap();
+        db.delete("d".to_owned()).unwrap();
+
+        let jsonl = export(&db, "");
+        assert_eq!(
+            jsonl,
+            "{\"key\":\"a\",\"value\":\"1\"}\n\
+             {\"key\":\"b,\\\"c\\\"\",\"value\":\"line\\nbreak\"}\n\
+             {\"key\":\"c\",\"value\":\"3\"}\n"
+        );
+        assert_eq!(
+            export(&db, "--format csv --start b --end c"),
+            "key,value\n\"b,\"\"c\"\"\",\"line\nbreak\"\n"
+        );
+        assert_eq!(
+            export(&db, "--base64 --prefix c"),
+            "{\"key_base64\":\"Yw==\",\"value_base64\":\"Mw==\"}\n"
+        );
+
+        for format in ["jsonl", "jsonl --base64", "csv --base64"] {
+            let copy = open("import");
+            let input = export(&db, &format!("--format {}", format));
+            let line = format!(
+                "--batch-size 2 --format {}",
+                &format[..format.find(' ').unwrap_or(format.len())]
+            );
+            let (records, progress) = import(&copy, &line, &input).unwrap();
+            assert_eq!(records, 3);
+            assert_eq!(progress, vec![2, 3]);
+            assert_eq!(copy.scan(..).unwrap(), db.scan(..).unwrap());
+        }
+        let csv = export(&db, "--format csv");
+        let copy = open("import");
+        assert_eq!(import(&copy, "--format csv", &csv).unwrap().0, 3);
+        assert_eq!(copy.scan(..).unwrap(), db.scan(..).unwrap());
+    }
+
+    #[test]
+    fn import_invalid() {
+        let db = open("import_invalid");
+        let input = "{\"key\":\"a\",\"value\":\"1\"}\n\n{\"key\":\"b\"}\n";
+        assert_eq!(
+            import(&db, "--dry-run", input).unwrap_err().to_string(),
+            "Invalid argument: line 3: expected the string fields key and value, or \
+             key_base64 and value_base64"
+        );
+        let err = import(&db, "", "{\"key_base64\":\"!\",\"value_base64\":\"\"}").unwrap_err();
+        assert!(
+            err.to_string().contains("line 1: invalid base64 '!'"),
+            "{}",
+            err
+        );
+        let err = import(&db, "--format csv", "k,v\na,1\n").unwrap_err();
+        assert!(
+            err.to_string().contains("line 1: expected the header"),
+            "{}",
+            err
+        );
+        let err = import(&db, "--format csv", "key,value\na,1\nb\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid argument: line 3: expected 2 fields, found 1"
+        );
+
+        // a dry run checks every line, but writes nothing
+        let (records, _) = import(&db, "--dry-run --format csv", "key,value\na,1\n").unwrap();
+        assert_eq!(records, 1);
+        assert!(db.scan(..).unwrap().is_empty());
+        // batches before the first invalid line are written
+        let input = "{\"key\":\"a\",\"value\":\"1\"}\n[]\n";
+        assert!(import(&db, "--batch-size 1", input).is_err());
+        assert_eq!(db.get(&"a".to_owned()).unwrap(), Some("1".to_owned()));
+    }
+}
@@ -1 +1,7 @@
+#![allow(clippy::module_inception)]
 pub mod cli;
+pub mod export;
+pub mod repl;
+pub mod script;
+pub mod sst_dump;
+pub mod wal_dump;
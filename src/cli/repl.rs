@@ -0,0 +1,62 @@
+use std::io::{stdout, Write};
+use std::path::Path;
+
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+use super::cli::{execute, InputCommand};
+use crate::db::db::DB;
+use crate::error::Result;
+
+const PROMPT: &str = "> ";
+
+/// Reads commands from the terminal and runs them against `db` until `quit` or end of input.
+/// Lines are kept in the file at `history`, if given, so they can be recalled next time
+pub fn run(db: &DB, history: Option<&Path>) -> Result<()> {
+    let mut editor = DefaultEditor::new().map_err(readline_error)?;
+    if let Some(history) = history {
+        // there is no history yet the first time
+        let _ = editor.load_history(history);
+    }
+
+    let result = read_eval_print(db, &mut editor);
+
+    if let Some(history) = history {
+        editor.save_history(history).map_err(readline_error)?;
+    }
+    result
+}
+
+fn read_eval_print(db: &DB, editor: &mut DefaultEditor) -> Result<()> {
+    let mut out = stdout();
+    loop {
+        let line = match editor.readline(PROMPT) {
+            Ok(line) => line,
+            // Ctrl-C drops the line being typed, Ctrl-D leaves
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => return Ok(()),
+            Err(err) => return Err(readline_error(err)),
+        };
+        if !line.trim().is_empty() {
+            editor.add_history_entry(&line).map_err(readline_error)?;
+        }
+
+        let result = match InputCommand::parse(&line) {
+            Ok(Some(InputCommand::Quit)) => return Ok(()),
+            Ok(Some(command)) => execute(db, &command, &mut out),
+            Ok(None) => Ok(()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            writeln!(out, "error: {}", err)?;
+        }
+        out.flush()?;
+    }
+}
+
+fn readline_error(err: ReadlineError) -> crate::error::Error {
+    match err {
+        ReadlineError::Io(err) => err.into(),
+        err => std::io::Error::other(err.to_string()).into(),
+    }
+}
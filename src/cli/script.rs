@@ -0,0 +1,309 @@
+use std::io::{BufRead, Write};
+
+use super::cli::{execute, write_entries, InputCommand};
+use super::export::{Export, Import};
+use crate::db::db::DB;
+use crate::db::options::WriteOptions;
+use crate::db::write_batch::WriteBatch;
+use crate::error::{Error, Result};
+
+/// Exit code of a `get` that finds nothing
+pub const EXIT_NOT_FOUND: u8 = 1;
+/// Exit code of a command line that can not be parsed
+pub const EXIT_USAGE: u8 = 2;
+/// Exit code of a command the database fails to run
+pub const EXIT_ERROR: u8 = 3;
+
+pub const USAGE: &str = "\
+usage: rust_kv_store <db>                          start an interactive shell
+       rust_kv_store <db> get <key>                print the value of a key
+       rust_kv_store <db> put <key> <value>        set a key
+       rust_kv_store <db> delete <key>             delete a key
+       rust_kv_store <db> scan [<start> [<end>]]   print the keys from start to end
+       rust_kv_store <db> dump                     print every key and value
+       rust_kv_store <db> load [<file>]            set the keys printed by dump
+       rust_kv_store <db> export [--format jsonl|csv] [--base64]
+                          [--prefix <prefix> | --start <key> --end <key>] [<file>]
+       rust_kv_store <db> import [--format jsonl|csv] [--batch-size <n>] [--dry-run]
+                          [--progress] [<file>]
+       rust_kv_store <db> ingest <file>...         add SSTables built outside the database
+       rust_kv_store <db> compact [<level>]        compact the SSTables of a level
+       rust_kv_store <db> verify [--no-checksums]  check the database for corruption
+       rust_kv_store <db> repair                   rebuild a database that no longer opens
+       rust_kv_store <db> --batch [<file>]         apply set and delete commands atomically
+       rust_kv_store sst_dump <file> [--records] [--format text|hex|json]
+       rust_kv_store wal_dump <file> [--format text|hex|json]
+
+load, import and --batch read from standard input if no file, or -, is given, and export
+writes to standard output. The format of export and import defaults to csv for files ending
+in .csv, and to JSON Lines otherwise";
+
+/// What the command line asks for, besides the path of the database
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Subcommand {
+    /// Start the interactive shell
+    Shell,
+    Command(InputCommand),
+    Dump,
+    /// Read lines of keys and values, as printed by `Dump`
+    Load {
+        file: Option<String>,
+    },
+    /// Read lines of `set` and `delete` commands
+    Batch {
+        file: Option<String>,
+    },
+    Export(Export),
+    Import(Import),
+    /// Add external SSTables
+    Ingest {
+        files: Vec<String>,
+    },
+    /// Salvage what can be read, without opening the database first
+    Repair,
+}
+
+impl Subcommand {
+    pub fn from_args(args: &[String]) -> Result<Subcommand> {
+        let file = |args: &[String]| match args {
+            [] => Ok(None),
+            [file] if file == "-" => Ok(None),
+            [file] => Ok(Some(file.clone())),
+            _ => Err(Error::InvalidArgument("expected a single file".to_owned())),
+        };
+
+        match args.split_first() {
+            None => Ok(Subcommand::Shell),
+            Some((name, [])) if name == "dump" => Ok(Subcommand::Dump),
+            Some((name, [])) if name == "repair" => Ok(Subcommand::Repair),
+            Some((name, rest)) if name == "export" => {
+                Ok(Subcommand::Export(Export::from_args(rest)?))
+            }
+            Some((name, rest)) if name == "import" => {
+                Ok(Subcommand::Import(Import::from_args(rest)?))
+            }
+            Some((name, [])) if name == "ingest" => Err(Error::InvalidArgument(
+                "expected the paths of the SSTables to ingest".to_owned(),
+            )),
+            Some((name, files)) if name == "ingest" => Ok(Subcommand::Ingest {
+                files: files.to_vec(),
+            }),
+            Some((name, rest)) if name == "load" => Ok(Subcommand::Load { file: file(rest)? }),
+            Some((name, rest)) if name == "--batch" => Ok(Subcommand::Batch { file: file(rest)? }),
+            Some(_) => match InputCommand::from_words(args)? {
+                InputCommand::Help | InputCommand::Quit => Err(Error::InvalidArgument(
+                    "help and quit only work in the shell".to_owned(),
+                )),
+                command => Ok(Subcommand::Command(command)),
+            },
+        }
+    }
+
+    /// Whether the subcommand only reads, so the database can be opened read-only next to a
+    /// process that has it open for writing
+    pub fn is_read_only(&self) -> bool {
+        if let Subcommand::Import(import) = self {
+            return import.dry_run;
+        }
+        matches!(
+            self,
+            Subcommand::Dump
+                | Subcommand::Export(_)
+                | Subcommand::Command(
+                    InputCommand::Get { .. }
+                        | InputCommand::Scan { .. }
+                        | InputCommand::Prefix { .. }
+                        | InputCommand::Count { .. }
+                        | InputCommand::Stats
+                        | InputCommand::Verify { .. }
+                )
+        )
+    }
+}
+
+/// Runs a single command. Returns `false` if it is a `get` of a key that does not exist, in
+/// which case nothing is printed
+pub fn run_command(db: &DB, command: &InputCommand, out: &mut dyn Write) -> Result<bool> {
+    match command {
+        InputCommand::Get { key } => match db.get(key)? {
+            Some(value) => {
+                writeln!(out, "{}", value)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        },
+        command => {
+            execute(db, command, out)?;
+            Ok(true)
+        }
+    }
+}
+
+pub fn dump(db: &DB, out: &mut dyn Write) -> Result<()> {
+    write_entries(&db.scan(..)?, out)
+}
+
+/// Sets the keys and values read from `input`, one quoted pair per line as written by `dump`,
+/// in a single write. Returns the number of keys set
+pub fn load(db: &DB, input: &mut dyn BufRead) -> Result<usize> {
+    let batch = read_batch(input, |words| match words {
+        [key, value] => Ok(InputCommand::Set {
+            key: key.clone(),
+            value: value.clone(),
+        }),
+        _ => Err(Error::InvalidArgument(
+            "expected a key and a value".to_owned(),
+        )),
+    })?;
+    apply(db, batch)
+}
+
+/// Applies the `set` and `delete` commands read from `input`, one per line, in a single write:
+/// either all of them take effect or none do. Blank lines and lines starting with `#` are
+/// skipped. Returns the number of updates
+pub fn batch(db: &DB, input: &mut dyn BufRead) -> Result<usize> {
+    let batch = read_batch(input, InputCommand::from_words)?;
+    apply(db, batch)
+}
+
+fn apply(db: &DB, batch: WriteBatch) -> Result<usize> {
+    let len = batch.len();
+    if !batch.is_empty() {
+        db.write(batch, &WriteOptions::default())?;
+    }
+    Ok(len)
+}
+
+/// Collects the updates of every line of `input` into a batch, failing on the first line that
+/// is not an update
+fn read_batch(
+    input: &mut dyn BufRead,
+    parse: impl Fn(&[String]) -> Result<InputCommand>,
+) -> Result<WriteBatch> {
+    let mut batch = WriteBatch::new();
+    for (number, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        let invalid =
+            |message: String| Error::InvalidArgument(format!("line {}: {}", number + 1, message));
+
+        let words = shlex::split(&line)
+            .ok_or_else(|| invalid("unterminated quote or escape".to_owned()))?;
+        match parse(&words) {
+            Ok(InputCommand::Set { key, value }) => batch.set(key, value),
+            Ok(InputCommand::Delete { key }) => batch.delete(key),
+            Ok(_) => return Err(invalid("only set and delete can be batched".to_owned())),
+            Err(Error::InvalidArgument(message)) => return Err(invalid(message)),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(batch)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::db::options::Options;
+    use crate::env::MemEnv;
+
+    fn args(line: &str) -> Vec<String> {
+        shlex::split(line).unwrap()
+    }
+
+    #[test]
+    fn from_args() {
+        assert_eq!(Subcommand::from_args(&[]).unwrap(), Subcommand::Shell);
+        assert_eq!(
+            Subcommand::from_args(&args("put a 'b c'")).unwrap(),
+            Subcommand::Command(InputCommand::Set {
+                key: "a".to_owned(),
+                value: "b c".to_owned()
+            })
+        );
+        assert_eq!(
+            Subcommand::from_args(&args("--batch -")).unwrap(),
+            Subcommand::Batch { file: None }
+        );
+        assert_eq!(
+            Subcommand::from_args(&args("load dump.txt")).unwrap(),
+            Subcommand::Load {
+                file: Some("dump.txt".to_owned())
+            }
+        );
+        assert!(Subcommand::from_args(&args("dump")).unwrap().is_read_only());
+        assert_eq!(
+            Subcommand::from_args(&args("repair")).unwrap(),
+            Subcommand::Repair
+        );
+        assert_eq!(
+            Subcommand::from_args(&args("ingest a.sst b.sst")).unwrap(),
+            Subcommand::Ingest {
+                files: vec!["a.sst".to_owned(), "b.sst".to_owned()]
+            }
+        );
+        assert!(Subcommand::from_args(&args("ingest")).is_err());
+        assert!(Subcommand::from_args(&args("export --prefix a"))
+            .unwrap()
+            .is_read_only());
+        assert!(Subcommand::from_args(&args("import --dry-run"))
+            .unwrap()
+            .is_read_only());
+        assert!(!Subcommand::from_args(&args("import"))
+            .unwrap()
+            .is_read_only());
+        assert!(Subcommand::from_args(&args("get")).is_err());
+        assert!(Subcommand::from_args(&args("quit")).is_err());
+    }
+
+    #[test]
+    fn batch_dump_load() {
+        let env = Arc::new(MemEnv::new());
+        let options = Options::default().env(env);
+        let db = DB::open(Path::new("batch"), options.clone()).unwrap();
+        db.set("gone".to_owned(), "1".to_owned()).unwrap();
+
+        let script = "# comment\nset a 1\n\nput 'b c' \"d e\"\ndelete gone\n";
+        assert_eq!(batch(&db, &mut script.as_bytes()).unwrap(), 3);
+        let mut out = vec![];
+        dump(&db, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out.clone()).unwrap(),
+            "a 1\n'b c' 'd e'\n"
+        );
+
+        let found = run_command(
+            &db,
+            &InputCommand::Get {
+                key: "x".to_owned(),
+            },
+            &mut vec![],
+        );
+        assert!(!found.unwrap());
+
+        let copy = DB::open(Path::new("copy"), options).unwrap();
+        assert_eq!(load(&copy, &mut out.as_slice()).unwrap(), 2);
+        assert_eq!(copy.scan(..).unwrap(), db.scan(..).unwrap());
+    }
+
+    #[test]
+    fn batch_is_atomic() {
+        let env = Arc::new(MemEnv::new());
+        let db = DB::open(Path::new("atomic"), Options::default().env(env)).unwrap();
+
+        let err = batch(&db, &mut "set a 1\nget a\n".as_bytes()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid argument: line 2: only set and delete can be batched"
+        );
+        let err = batch(&db, &mut "set a 1\nset b\n".as_bytes()).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("line 2: wrong number of arguments"));
+        assert!(db.scan(..).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,413 @@
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::PathBuf;
+
+use serde_json::{json, Value};
+
+use crate::env::Env;
+use crate::error::{Error, Result};
+use crate::storage::properties::TableProperties;
+use crate::storage::record::Record;
+use crate::storage::sstable::{BlockHandle, SSTable};
+
+pub const USAGE: &str = "\
+usage: rust_kv_store sst_dump <file> [--records] [--format text|hex|json]
+
+Prints the footer, properties and data blocks of an SSTable. --records also prints every
+record, with keys and values as text or hex. --format json prints a single JSON document";
+
+/// How `sst_dump` and `wal_dump` print what they find
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DumpFormat {
+    #[default]
+    Text,
+    /// Like `Text`, with keys and values in hex
+    Hex,
+    Json,
+}
+
+impl DumpFormat {
+    pub fn from_name(name: &str) -> Result<DumpFormat> {
+        match name {
+            "text" => Ok(DumpFormat::Text),
+            "hex" => Ok(DumpFormat::Hex),
+            "json" => Ok(DumpFormat::Json),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown format '{}', expected text, hex or json",
+                name
+            ))),
+        }
+    }
+
+    /// Shows a key or value so where it starts and ends is unambiguous
+    pub fn bytes(self, text: &str) -> String {
+        match self {
+            DumpFormat::Hex => text.bytes().fold(String::new(), |mut hex, byte| {
+                let _ = write!(hex, "{:02x}", byte);
+                hex
+            }),
+            DumpFormat::Text | DumpFormat::Json => format!("{:?}", text),
+        }
+    }
+
+    /// Parses `--format <name>` out of `args`, returning the arguments left
+    pub fn from_args(args: &[String]) -> Result<(DumpFormat, Vec<String>)> {
+        let mut format = DumpFormat::default();
+        let mut rest = vec![];
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--format" {
+                let name = args.next().ok_or_else(|| {
+                    Error::InvalidArgument("--format needs text, hex or json".to_owned())
+                })?;
+                format = DumpFormat::from_name(name)?;
+            } else {
+                rest.push(arg.clone());
+            }
+        }
+        Ok((format, rest))
+    }
+}
+
+/// Inspects an SSTable without opening the database it belongs to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SstDump {
+    pub file: PathBuf,
+    /// Print every record, not only the summary of each block
+    pub records: bool,
+    pub format: DumpFormat,
+}
+
+/// What was found in a data block
+struct BlockSummary {
+    first_key: String,
+    handle: BlockHandle,
+    /// The records of the block and its size before compression, unless it could not be read
+    contents: Result<(Vec<Record>, usize)>,
+}
+
+impl SstDump {
+    pub fn from_args(args: &[String]) -> Result<SstDump> {
+        let (format, args) = DumpFormat::from_args(args)?;
+        let mut records = false;
+        let mut files = vec![];
+        for arg in args {
+            match arg.as_str() {
+                "--records" => records = true,
+                _ if arg.starts_with("--") => {
+                    return Err(Error::InvalidArgument(format!("unknown option {}", arg)))
+                }
+                _ => files.push(PathBuf::from(arg)),
+            }
+        }
+        match files.as_slice() {
+            [file] => Ok(SstDump {
+                file: file.clone(),
+                records,
+                format,
+            }),
+            _ => Err(Error::InvalidArgument(
+                "expected the path of a single SSTable".to_owned(),
+            )),
+        }
+    }
+
+    /// Prints the table to `out`. A data block that can not be read is reported and skipped;
+    /// only a footer, index or properties block that can not be read fails the dump
+    pub fn run(&self, env: &dyn Env, out: &mut dyn Write) -> Result<()> {
+        // the table can have any name, as it need not be part of a database
+        let table = SSTable::from_external_file(env, &self.file)?;
+        let blocks = table
+            .index_entries()
+            .map(|(first_key, handle)| BlockSummary {
+                first_key: first_key.to_owned(),
+                handle,
+                contents: table.read_block(env, &self.file, handle).map(|block| {
+                    let size = block.to_bytes(table.encoding()).len();
+                    (block.records().to_vec(), size)
+                }),
+            })
+            .collect::<Vec<_>>();
+
+        match self.format {
+            DumpFormat::Json => {
+                serde_json::to_writer_pretty(&mut *out, &self.to_json(&table, &blocks))
+                    .map_err(std::io::Error::from)?;
+                writeln!(out)?;
+                Ok(())
+            }
+            DumpFormat::Text | DumpFormat::Hex => self.write_text(&table, &blocks, out),
+        }
+    }
+
+    fn write_text(
+        &self,
+        table: &SSTable,
+        blocks: &[BlockSummary],
+        out: &mut dyn Write,
+    ) -> Result<()> {
+        let footer = table.footer();
+        writeln!(out, "file: {}", self.file.display())?;
+        writeln!(out, "footer:")?;
+        writeln!(out, "  format version: {}", footer.format_version)?;
+        writeln!(
+            out,
+            "  metaindex block: {}",
+            handle_text(footer.metaindex_handle)
+        )?;
+        writeln!(out, "  index block: {}", handle_text(footer.index_handle))?;
+
+        let properties = table.properties();
+        writeln!(out, "properties:")?;
+        for (name, value) in properties_list(properties, self.format) {
+            writeln!(out, "  {}: {}", name, value)?;
+        }
+
+        writeln!(out, "data blocks: {}", blocks.len())?;
+        for (index, block) in blocks.iter().enumerate() {
+            write!(
+                out,
+                "  block {}: first key {}, {}",
+                index,
+                self.format.bytes(&block.first_key),
+                handle_text(block.handle)
+            )?;
+            match &block.contents {
+                Ok((records, size)) => {
+                    writeln!(
+                        out,
+                        ", {} bytes uncompressed, {} records",
+                        size,
+                        records.len()
+                    )?;
+                    if self.records {
+                        for record in records {
+                            writeln!(out, "    {}", self.record_text(record))?;
+                        }
+                    }
+                }
+                Err(err) => writeln!(out, ", unreadable: {}", err)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn record_text(&self, record: &Record) -> String {
+        match record {
+            Record::Value {
+                timestamp,
+                key,
+                value,
+            } => format!(
+                "{} @ {} => {}",
+                self.format.bytes(key),
+                timestamp,
+                self.format.bytes(value)
+            ),
+            Record::Tombstone { timestamp, key } => {
+                format!("{} @ {} => deleted", self.format.bytes(key), timestamp)
+            }
+        }
+    }
+
+    fn to_json(&self, table: &SSTable, blocks: &[BlockSummary]) -> Value {
+        let footer = table.footer();
+        let properties = table.properties();
+        let blocks_json = blocks
+            .iter()
+            .map(|block| {
+                let mut json = json!({
+                    "first_key": block.first_key,
+                    "offset": block.handle.offset,
+                    "size": block.handle.size,
+                });
+                match &block.contents {
+                    Ok((records, size)) => {
+                        json["uncompressed_size"] = json!(size);
+                        json["num_records"] = json!(records.len());
+                        if self.records {
+                            json["records"] = records.iter().map(record_json).collect();
+                        }
+                    }
+                    Err(err) => json["error"] = json!(err.to_string()),
+                }
+                json
+            })
+            .collect::<Vec<_>>();
+
+        json!({
+            "file": self.file.display().to_string(),
+            "footer": {
+                "format_version": footer.format_version,
+                "metaindex_block": handle_json(footer.metaindex_handle),
+                "index_block": handle_json(footer.index_handle),
+            },
+            // sequence numbers are 128 bit, which JSON numbers can not hold
+            "properties": {
+                "num_entries": properties.num_entries,
+                "num_tombstones": properties.num_tombstones,
+                "num_data_blocks": properties.num_data_blocks,
+                "raw_key_size": properties.raw_key_size,
+                "raw_value_size": properties.raw_value_size,
+                "smallest_key": properties.smallest_key,
+                "largest_key": properties.largest_key,
+                "min_sequence": properties.min_sequence.to_string(),
+                "max_sequence": properties.max_sequence.to_string(),
+                "creation_time": properties.creation_time,
+                "compression": properties.compression,
+                "format_version": properties.format_version,
+            },
+            "data_blocks": blocks_json,
+        })
+    }
+}
+
+fn handle_text(handle: BlockHandle) -> String {
+    format!("offset {}, size {}", handle.offset, handle.size)
+}
+
+fn handle_json(handle: BlockHandle) -> Value {
+    json!({ "offset": handle.offset, "size": handle.size })
+}
+
+pub fn record_json(record: &Record) -> Value {
+    match record {
+        Record::Value {
+            timestamp,
+            key,
+            value,
+        } => json!({
+            "key": key,
+            "timestamp": timestamp.to_string(),
+            "tombstone": false,
+            "value": value,
+        }),
+        Record::Tombstone { timestamp, key } => json!({
+            "key": key,
+            "timestamp": timestamp.to_string(),
+            "tombstone": true,
+        }),
+    }
+}
+
+/// Names and values of the properties, in the order they are printed
+fn properties_list(
+    properties: &TableProperties,
+    format: DumpFormat,
+) -> Vec<(&'static str, String)> {
+    vec![
+        ("entries", properties.num_entries.to_string()),
+        ("tombstones", properties.num_tombstones.to_string()),
+        ("data blocks", properties.num_data_blocks.to_string()),
+        ("raw key size", properties.raw_key_size.to_string()),
+        ("raw value size", properties.raw_value_size.to_string()),
+        ("smallest key", format.bytes(&properties.smallest_key)),
+        ("largest key", format.bytes(&properties.largest_key)),
+        ("min sequence", properties.min_sequence.to_string()),
+        ("max sequence", properties.max_sequence.to_string()),
+        ("creation time", properties.creation_time.to_string()),
+        ("compression", properties.compression.clone()),
+        ("format version", properties.format_version.to_string()),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+    use crate::env::{FaultInjectionEnv, MemEnv};
+    use crate::storage::sst_file_writer::SstFileWriter;
+    use crate::storage::sstable::TableOptions;
+
+    fn dump(env: &dyn Env, file: &Path, args: &str) -> String {
+        let mut args = shlex::split(args).unwrap();
+        args.push(file.display().to_string());
+        let mut out = vec![];
+        SstDump::from_args(&args)
+            .unwrap()
+            .run(env, &mut out)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn write_table(env: &dyn Env) -> PathBuf {
+        let mut records = (0..20)
+            .map(|i| Record::new(format!("key{:02}", i), format!("value {}", i)))
+            .collect::<Vec<_>>();
+        records.push(Record::new_tombstone("zz".to_owned()));
+        let options = TableOptions {
+            block_size: 100,
+            ..Default::default()
+        };
+        SSTable::from_records_with_options(records, &options)
+            .write(env, Path::new("sstable"))
+            .unwrap()
+    }
+
+    #[test]
+    fn text() {
+        let env = MemEnv::new();
+        let file = write_table(&env);
+
+        let out = dump(&env, &file, "");
+        assert!(out.contains("  entries: 21\n  tombstones: 1\n"));
+        assert!(out.contains("  smallest key: \"key00\"\n"));
+        assert!(out.contains("  block 0: first key \"key00\", offset 0, size "));
+        assert!(!out.contains(" => "));
+
+        let out = dump(&env, &file, "--records");
+        assert!(out.contains("\"key01\" @ "));
+        assert!(out.contains(" => \"value 1\"\n"));
+        assert!(out.contains("\"zz\" @ "));
+        assert!(out.ends_with(" => deleted\n"));
+
+        let out = dump(&env, &file, "--records --format hex");
+        assert!(out.contains(" => 76616c75652031\n"));
+    }
+
+    #[test]
+    fn json() {
+        let env = MemEnv::new();
+        let file = write_table(&env);
+
+        let out = dump(&env, &file, "--format json --records");
+        let json: Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(json["properties"]["num_entries"], 21);
+        let blocks = json["data_blocks"].as_array().unwrap();
+        assert!(blocks.len() > 1);
+        let records = blocks
+            .iter()
+            .flat_map(|block| block["records"].as_array().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), 21);
+        assert_eq!(records[0]["key"], "key00");
+        assert_eq!(records[0]["value"], "value 0");
+        assert_eq!(records[20]["tombstone"], true);
+    }
+
+    #[test]
+    fn external_file() {
+        let env = MemEnv::new();
+        let file = Path::new("ext.sst");
+        let mut writer = SstFileWriter::new(TableOptions::default());
+        writer.put("a".to_owned(), "1".to_owned()).unwrap();
+        writer.finish(&env, file).unwrap();
+
+        let out = dump(&env, file, "--records");
+        assert!(out.contains("  entries: 1\n"));
+        assert!(out.contains(" => \"1\"\n"));
+    }
+
+    #[test]
+    fn corrupt_block() {
+        let env = FaultInjectionEnv::new(std::sync::Arc::new(MemEnv::new()));
+        let file = write_table(&env);
+        env.corrupt(&file, 10, 1).unwrap();
+
+        let out = dump(&env, &file, "--records");
+        assert!(out.contains("  block 0: first key \"key00\", offset 0, size "));
+        assert!(out.contains(", unreadable: Corruption in "));
+        assert!(out.contains("\"key19\" @ "));
+    }
+}
@@ -0,0 +1,223 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use serde_json::{json, Value};
+
+use super::script::EXIT_ERROR;
+use super::sst_dump::{record_json, DumpFormat};
+use crate::env::Env;
+use crate::error::{Error, Result};
+use crate::storage::record::Record;
+use crate::storage::wal::{WalEnd, WriteAheadLogIter};
+
+pub const USAGE: &str = "\
+usage: rust_kv_store wal_dump <file> [--format text|hex|json]
+
+Prints every record of a WAL with its offset, and where and why reading stopped. Exits with 3
+unless every record could be read";
+
+/// Inspects a WAL without opening the database it belongs to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalDump {
+    pub file: PathBuf,
+    pub format: DumpFormat,
+}
+
+/// A record and where it was found
+struct Entry {
+    offset: u64,
+    size: u64,
+    record: Record,
+}
+
+impl WalDump {
+    pub fn from_args(args: &[String]) -> Result<WalDump> {
+        let (format, args) = DumpFormat::from_args(args)?;
+        match args.as_slice() {
+            [file] if !file.starts_with("--") => Ok(WalDump {
+                file: PathBuf::from(file),
+                format,
+            }),
+            _ => Err(Error::InvalidArgument(
+                "expected the path of a single WAL".to_owned(),
+            )),
+        }
+    }
+
+    /// Prints the records of the WAL to `out`. Returns why reading stopped, which is
+    /// `WalEnd::Eof` for a WAL that is intact
+    pub fn run(&self, env: &dyn Env, out: &mut dyn Write) -> Result<WalEnd> {
+        let mut wal_iter = WriteAheadLogIter::open(env, &self.file)?;
+        let mut entries = vec![];
+        loop {
+            let offset = wal_iter.offset();
+            match wal_iter.next() {
+                Some(record) => entries.push(Entry {
+                    offset,
+                    size: wal_iter.offset() - offset,
+                    record,
+                }),
+                None => break,
+            }
+        }
+        let end = wal_iter.end().cloned().unwrap_or(WalEnd::Eof);
+        let end_offset = wal_iter.offset();
+        let format = if wal_iter.is_legacy() {
+            "legacy"
+        } else {
+            "version 1"
+        };
+
+        match self.format {
+            DumpFormat::Json => {
+                let records = entries
+                    .iter()
+                    .map(|entry| {
+                        let mut json = record_json(&entry.record);
+                        json["offset"] = json!(entry.offset);
+                        json["size"] = json!(entry.size);
+                        json
+                    })
+                    .collect::<Vec<Value>>();
+                let json = json!({
+                    "file": self.file.display().to_string(),
+                    "format": format,
+                    "records": records,
+                    "end": {
+                        "offset": end_offset,
+                        "reason": end_name(&end),
+                        "message": end.to_string(),
+                    },
+                });
+                serde_json::to_writer_pretty(&mut *out, &json).map_err(std::io::Error::from)?;
+                writeln!(out)?;
+            }
+            DumpFormat::Text | DumpFormat::Hex => {
+                writeln!(out, "file: {}", self.file.display())?;
+                writeln!(out, "format: {}", format)?;
+                for entry in &entries {
+                    writeln!(out, "{}", self.entry_text(entry))?;
+                }
+                writeln!(
+                    out,
+                    "{} records, stopped at offset {}: {}",
+                    entries.len(),
+                    end_offset,
+                    end
+                )?;
+            }
+        }
+        Ok(end)
+    }
+
+    fn entry_text(&self, entry: &Entry) -> String {
+        let record = &entry.record;
+        let (kind, value) = match record {
+            Record::Value { value, .. } => ("value", self.format.bytes(value)),
+            Record::Tombstone { .. } => ("tombstone", "deleted".to_owned()),
+        };
+        format!(
+            "offset {}: {}, timestamp {}, {} bytes (key {}, value {}): {} => {}",
+            entry.offset,
+            kind,
+            record.get_timestamp(),
+            entry.size,
+            record.key_size(),
+            record.value_size(),
+            self.format.bytes(&record.get_key()),
+            value
+        )
+    }
+}
+
+/// Exit status of `wal_dump` for a WAL that stopped at `end`, which is an error unless the WAL
+/// was read to a clean end of file
+pub fn exit_code(end: &WalEnd) -> u8 {
+    match end {
+        WalEnd::Eof => 0,
+        WalEnd::Truncated | WalEnd::Corrupt(_) | WalEnd::Io(_) => EXIT_ERROR,
+    }
+}
+
+fn end_name(end: &WalEnd) -> &'static str {
+    match end {
+        WalEnd::Eof => "eof",
+        WalEnd::Truncated => "truncated",
+        WalEnd::Corrupt(_) => "corrupt",
+        WalEnd::Io(_) => "io_error",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::env::{FaultInjectionEnv, MemEnv};
+    use crate::storage::wal::WriteAheadLog;
+
+    fn dump(env: &dyn Env, file: &Path, format: &str) -> (WalEnd, String) {
+        let args = ["--format", format, &file.display().to_string()].map(str::to_owned);
+        let mut out = vec![];
+        let end = WalDump::from_args(&args)
+            .unwrap()
+            .run(env, &mut out)
+            .unwrap();
+        (end, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn text() {
+        let env = Arc::new(FaultInjectionEnv::new(Arc::new(MemEnv::new())));
+        let mut wal = WriteAheadLog::new(env.clone(), Path::new("wal")).unwrap();
+        wal.set("a".to_owned(), "hello".to_owned()).unwrap();
+        wal.delete("b".to_owned()).unwrap();
+        wal.set("c".to_owned(), "d".to_owned()).unwrap();
+        wal.sync().unwrap();
+
+        let (end, out) = dump(env.as_ref(), wal.path(), "text");
+        assert_eq!(end, WalEnd::Eof);
+        assert_eq!(exit_code(&end), 0);
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines[1], "format: version 1");
+        assert!(lines[2].starts_with("offset 12: value, timestamp "));
+        assert!(lines[2].ends_with(" (key 1, value 5): \"a\" => \"hello\""));
+        assert!(lines[3].ends_with(" (key 1, value 0): \"b\" => deleted"));
+        assert!(lines[5].starts_with("3 records, stopped at offset "));
+        assert!(lines[5].ends_with(": clean end of file"));
+
+        // corrupt the value of the second record
+        let second = lines[3]["offset ".len()..].split(':').next().unwrap();
+        env.corrupt(wal.path(), second.parse::<u64>().unwrap() + 9, 1)
+            .unwrap();
+        let (end, out) = dump(env.as_ref(), wal.path(), "hex");
+        assert_eq!(end, WalEnd::Corrupt("checksum mismatch".to_owned()));
+        assert_eq!(exit_code(&end), EXIT_ERROR);
+        assert!(out.contains(": 61 => 68656c6c6f\n"));
+        assert!(out.ends_with(&format!(
+            "1 records, stopped at offset {}: corrupt record: checksum mismatch\n",
+            second
+        )));
+    }
+
+    #[test]
+    fn json() {
+        let env = Arc::new(MemEnv::new());
+        let mut wal = WriteAheadLog::new(env.clone(), Path::new("wal")).unwrap();
+        wal.set("a".to_owned(), "b".to_owned()).unwrap();
+        let bytes = env.read(wal.path()).unwrap();
+        env.create(wal.path())
+            .unwrap()
+            .append(&bytes[..bytes.len() - 1])
+            .unwrap();
+
+        let (end, out) = dump(env.as_ref(), wal.path(), "json");
+        assert_eq!(end, WalEnd::Truncated);
+        assert_eq!(exit_code(&end), EXIT_ERROR);
+        let json: Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(json["records"].as_array().unwrap().len(), 0);
+        assert_eq!(json["end"]["offset"], 12);
+        assert_eq!(json["end"]["reason"], "truncated");
+    }
+}
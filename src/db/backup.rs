@@ -0,0 +1,582 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::db::{DB, LOCK_FILE};
+use super::manifest::Manifest;
+use super::options::{FlushOptions, OPTIONS_FILE};
+use crate::env::{Env, FileLock};
+use crate::error::{Error, Result};
+use crate::storage::fs::{list_files, remove_temp_files, write_atomic};
+
+pub type BackupId = u32;
+
+/// Summary of a backup, see `BackupEngine::backups`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupInfo {
+    pub id: BackupId,
+    /// When the backup was created, in microseconds since the unix epoch
+    pub timestamp: u128,
+    /// Bytes of all files in the backup, including those it shares with other backups
+    pub size: u64,
+    pub num_files: usize,
+}
+
+/// A file of a backup, with its path relative to the backup directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BackupFile {
+    path: PathBuf,
+    size: u64,
+    crc32: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Backup {
+    timestamp: u128,
+    /// SSTables in the order of the manifest, then the options file if the database had one
+    files: Vec<BackupFile>,
+    /// Timestamp the records of ingested SSTables are read with, by path in the backup
+    global_sequences: BTreeMap<PathBuf, u128>,
+}
+
+/// Keeps backups of a database in a directory of their own, which can be on another `Env`.
+///
+/// SSTables are copied into `shared/` under their name, size and checksum, so each is copied
+/// once and used by every backup that includes it, and a table rewritten under the same name,
+/// e.g. by `DB::repair`, or a table of another database is never mistaken for it. Everything else of a backup is in `private/<id>/`, and the list of
+/// its files with their sizes and checksums in `meta/<id>`. The metadata file is written last,
+/// so a backup that did not finish does not exist, and its files are removed the next time the
+/// engine is opened
+pub struct BackupEngine {
+    env: Arc<dyn Env>,
+    dir: PathBuf,
+    backups: BTreeMap<BackupId, Backup>,
+    /// Held for as long as the engine is open
+    lock_file: Box<dyn FileLock>,
+}
+
+impl BackupEngine {
+    /// Opens the backups in `dir`, creating it if needed
+    pub fn open(env: Arc<dyn Env>, dir: &Path) -> Result<BackupEngine> {
+        env.create_dir_all(dir)?;
+        let lock_file = env.lock(&dir.join(LOCK_FILE))?;
+        remove_temp_files(env.as_ref(), &dir.join("meta"))?;
+        remove_temp_files(env.as_ref(), &dir.join("shared"))?;
+
+        let mut backups = BTreeMap::new();
+        if env.exists(&dir.join("meta")) {
+            for meta_path in env.list(&dir.join("meta"))? {
+                let id = meta_path
+                    .file_name()
+                    .and_then(|name| name.to_str()?.parse::<BackupId>().ok())
+                    .ok_or_else(|| Error::corruption(&meta_path, None, "not a backup id"))?;
+                backups.insert(id, Backup::load(env.as_ref(), &meta_path)?);
+            }
+        }
+
+        let engine = BackupEngine {
+            env,
+            dir: dir.to_path_buf(),
+            backups,
+            lock_file,
+        };
+        engine.remove_unused_files()?;
+        Ok(engine)
+    }
+
+    /// Backs up the records of `db` that were written before this call, while it keeps
+    /// accepting writes. The memtable is flushed first, so only SSTables need to be copied,
+    /// and those already in an earlier backup are not copied again
+    pub fn create_new_backup(&mut self, db: &DB) -> Result<BackupId> {
+        db.flush(&FlushOptions::default())?;
+        let live_files = db.live_files();
+        let db_env = db.options().env.as_ref();
+        let id = self.backups.keys().next_back().map_or(1, |id| id + 1);
+
+        let mut files = vec![];
+        let mut global_sequences = BTreeMap::new();
+        for sstable_path in live_files.sstables.iter() {
+            let bytes = db_env.read(sstable_path)?;
+            let path = Path::new("shared").join(shared_file_name(sstable_path, &bytes));
+            if let Some(sequence) = live_files.global_sequences.get(sstable_path) {
+                global_sequences.insert(path.clone(), *sequence);
+            }
+            let file = match self.file(&path) {
+                Some(file) => file.clone(),
+                None => self.write_file(path, &bytes)?,
+            };
+            files.push(file);
+        }
+        let options_path = db.path().join(OPTIONS_FILE);
+        if db_env.exists(&options_path) {
+            let path = Path::new("private").join(id.to_string()).join(OPTIONS_FILE);
+            files.push(self.copy_in(db_env, &options_path, path)?);
+        }
+        drop(live_files);
+
+        let backup = Backup {
+            timestamp: self.env.now().as_micros(),
+            files,
+            global_sequences,
+        };
+        backup.write(self.env.as_ref(), &self.meta_path(id))?;
+        self.backups.insert(id, backup);
+        Ok(id)
+    }
+
+    /// Every backup, oldest first
+    pub fn backups(&self) -> Vec<BackupInfo> {
+        self.backups
+            .iter()
+            .map(|(id, backup)| BackupInfo {
+                id: *id,
+                timestamp: backup.timestamp,
+                size: backup.files.iter().map(|file| file.size).sum(),
+                num_files: backup.files.len(),
+            })
+            .collect()
+    }
+
+    /// Checks that every file of the backup is there, with the size and checksum it had when
+    /// it was backed up
+    pub fn verify_backup(&self, id: BackupId) -> Result<()> {
+        for file in self.backup(id)?.files.iter() {
+            self.read(file)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the backup and the files no other backup uses
+    pub fn delete_backup(&mut self, id: BackupId) -> Result<()> {
+        self.backup(id)?;
+        self.env.remove_file(&self.meta_path(id))?;
+        self.env.sync_dir(&self.dir.join("meta"))?;
+        self.backups.remove(&id);
+        self.remove_unused_files()
+    }
+
+    /// Deletes all but the newest `num_backups_to_keep` backups. Returns the ids deleted
+    pub fn purge_old_backups(&mut self, num_backups_to_keep: usize) -> Result<Vec<BackupId>> {
+        let num_to_delete = self.backups.len().saturating_sub(num_backups_to_keep);
+        let ids = self
+            .backups
+            .keys()
+            .take(num_to_delete)
+            .copied()
+            .collect::<Vec<_>>();
+        for id in ids.iter() {
+            self.delete_backup(*id)?;
+        }
+        Ok(ids)
+    }
+
+    /// Writes the database of the backup to `db_dir`, which must not exist or be empty. Every
+    /// file is checked against its checksum on the way
+    pub fn restore(&self, id: BackupId, db_dir: &Path) -> Result<()> {
+        let backup = self.backup(id)?;
+        let env = self.env.as_ref();
+        if env.exists(db_dir) && !env.list(db_dir)?.is_empty() {
+            return Err(Error::InvalidArgument(format!(
+                "can not restore into {}, which is not empty",
+                db_dir.display()
+            )));
+        }
+
+        let sstable_dir = db_dir.join("sstable");
+        env.create_dir_all(&sstable_dir)?;
+        let mut manifest = Manifest::default();
+        for file in backup.files.iter() {
+            let bytes = self.read(file)?;
+            if file.path.starts_with("shared") {
+                let sstable_path = sstable_dir.join(db_file_name(&file.path));
+                write_atomic(env, &sstable_path, &bytes)?;
+                if let Some(sequence) = backup.global_sequences.get(&file.path) {
+                    manifest
+                        .global_sequences
+                        .insert(sstable_path.clone(), *sequence);
+                }
+                manifest.sstables.push(sstable_path);
+            } else {
+                write_atomic(env, &db_dir.join(file.path.file_name().unwrap()), &bytes)?;
+            }
+        }
+        manifest.write(env, db_dir)
+    }
+
+    /// Releases the lock on the backup directory
+    pub fn close(self) -> Result<()> {
+        self.lock_file.unlock()
+    }
+
+    fn backup(&self, id: BackupId) -> Result<&Backup> {
+        self.backups
+            .get(&id)
+            .ok_or_else(|| Error::NotFound(format!("backup {} does not exist", id)))
+    }
+
+    fn meta_path(&self, id: BackupId) -> PathBuf {
+        self.dir.join("meta").join(id.to_string())
+    }
+
+    /// The file at `path` in any of the backups
+    fn file(&self, path: &Path) -> Option<&BackupFile> {
+        self.backups
+            .values()
+            .flat_map(|backup| backup.files.iter())
+            .find(|file| file.path == path)
+    }
+
+    /// Copies the file at `source` on `source_env` to `path` in the backup directory
+    fn copy_in(&self, source_env: &dyn Env, source: &Path, path: PathBuf) -> Result<BackupFile> {
+        self.write_file(path, &source_env.read(source)?)
+    }
+
+    /// Writes `bytes` to `path` in the backup directory
+    fn write_file(&self, path: PathBuf, bytes: &[u8]) -> Result<BackupFile> {
+        let backup_path = self.dir.join(&path);
+        self.env.create_dir_all(backup_path.parent().unwrap())?;
+        write_atomic(self.env.as_ref(), &backup_path, bytes)?;
+        Ok(BackupFile {
+            path,
+            size: bytes.len() as u64,
+            crc32: crc32fast::hash(bytes),
+        })
+    }
+
+    /// Reads a file of a backup, checking its size and checksum
+    fn read(&self, file: &BackupFile) -> Result<Vec<u8>> {
+        let path = self.dir.join(&file.path);
+        if !self.env.exists(&path) {
+            return Err(Error::corruption(path, None, "backup file is missing"));
+        }
+        let bytes = self.env.read(&path)?;
+        if bytes.len() as u64 != file.size {
+            return Err(Error::corruption(
+                path,
+                None,
+                format!(
+                    "backup file has {} bytes, expected {}",
+                    bytes.len(),
+                    file.size
+                ),
+            ));
+        }
+        if crc32fast::hash(&bytes) != file.crc32 {
+            return Err(Error::corruption(path, None, "checksum mismatch"));
+        }
+        Ok(bytes)
+    }
+
+    /// Removes the files of deleted backups, and of backups that did not finish
+    fn remove_unused_files(&self) -> Result<()> {
+        let env = self.env.as_ref();
+        let shared_dir = self.dir.join("shared");
+        for path in list_files(env, &shared_dir, "ss")? {
+            let relative_path = path.strip_prefix(&self.dir).unwrap();
+            if self.file(relative_path).is_none() {
+                env.remove_file(&path)?;
+            }
+        }
+        let private_dir = self.dir.join("private");
+        if env.exists(&private_dir) {
+            for path in env.list(&private_dir)? {
+                let id = path
+                    .file_name()
+                    .and_then(|name| name.to_str()?.parse::<BackupId>().ok());
+                if !id.is_some_and(|id| self.backups.contains_key(&id)) {
+                    env.remove_dir_all(&path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Name in `shared/` of the SSTable at `path` holding `bytes`: `<number>_<level>_<crc32>_<size>.ss`
+fn shared_file_name(path: &Path, bytes: &[u8]) -> String {
+    format!(
+        "{}_{:08x}_{}.ss",
+        path.file_stem().unwrap().to_string_lossy(),
+        crc32fast::hash(bytes),
+        bytes.len()
+    )
+}
+
+/// Name of a shared SSTable in the database, without the checksum and size. Backups made
+/// before those were part of the name have the name of the database already
+fn db_file_name(path: &Path) -> String {
+    let stem = path.file_stem().unwrap().to_string_lossy();
+    let parts = stem.split('_').collect::<Vec<_>>();
+    match parts.as_slice() {
+        [number, level, _, _] => format!("{}_{}.ss", number, level),
+        _ => format!("{}.ss", stem),
+    }
+}
+
+impl Backup {
+    fn write(&self, env: &dyn Env, path: &Path) -> Result<()> {
+        let mut contents = "# rust_kv_store backup\n".to_owned();
+        contents += &format!("timestamp={}\n", self.timestamp);
+        for file in self.files.iter() {
+            contents += &format!(
+                "file={} {} {:08x}\n",
+                file.path.display(),
+                file.size,
+                file.crc32
+            );
+        }
+        for (file_path, sequence) in self.global_sequences.iter() {
+            contents += &format!("global_sequence={} {}\n", file_path.display(), sequence);
+        }
+        env.create_dir_all(path.parent().unwrap())?;
+        write_atomic(env, path, contents.as_bytes())
+    }
+
+    fn load(env: &dyn Env, path: &Path) -> Result<Backup> {
+        let contents = String::from_utf8(env.read(path)?)
+            .map_err(|_| Error::corruption(path, None, "backup metadata is not valid UTF-8"))?;
+        let mut backup = Backup {
+            timestamp: 0,
+            files: vec![],
+            global_sequences: BTreeMap::new(),
+        };
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid =
+                || Error::corruption(path, None, format!("invalid backup entry '{}'", line));
+            match line.split_once('=').ok_or_else(invalid)? {
+                ("timestamp", timestamp) => {
+                    backup.timestamp = timestamp.parse().map_err(|_| invalid())?
+                }
+                ("file", file) => {
+                    let mut fields = file.rsplitn(3, ' ');
+                    let (Some(crc32), Some(size), Some(file_path)) =
+                        (fields.next(), fields.next(), fields.next())
+                    else {
+                        return Err(invalid());
+                    };
+                    backup.files.push(BackupFile {
+                        path: PathBuf::from(file_path),
+                        size: size.parse().map_err(|_| invalid())?,
+                        crc32: u32::from_str_radix(crc32, 16).map_err(|_| invalid())?,
+                    });
+                }
+                ("global_sequence", entry) => {
+                    let (file_path, sequence) = entry.rsplit_once(' ').ok_or_else(invalid)?;
+                    backup.global_sequences.insert(
+                        PathBuf::from(file_path),
+                        sequence.parse().map_err(|_| invalid())?,
+                    );
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(backup)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::options::Options;
+    use crate::env::{FaultInjectionEnv, MemEnv};
+    use crate::storage::record::Record;
+    use crate::storage::sst_file_writer::SstFileWriter;
+    use crate::storage::sstable::{SSTable, TableOptions};
+
+    fn keys(db: &DB) -> Vec<String> {
+        db.scan(..)
+            .unwrap()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    #[test]
+    fn backup_and_restore() {
+        let env = Arc::new(MemEnv::new());
+        let options = Options::default().env(env.clone());
+        let db = DB::open(Path::new("db"), options.clone()).unwrap();
+        let mut engine = BackupEngine::open(env.clone(), Path::new("backups")).unwrap();
+
+        db.set("a".to_owned(), "1".to_owned()).unwrap();
+        assert_eq!(engine.create_new_backup(&db).unwrap(), 1);
+        db.set("b".to_owned(), "2".to_owned()).unwrap();
+        db.delete("a".to_owned()).unwrap();
+        assert_eq!(engine.create_new_backup(&db).unwrap(), 2);
+        db.set("c".to_owned(), "3".to_owned()).unwrap();
+
+        // the SSTable of the first backup is shared with the second
+        let backups = engine.backups();
+        assert_eq!(backups.len(), 2);
+        assert_eq!(backups[0].num_files, 2);
+        assert_eq!(backups[1].num_files, 3);
+        let shared = list_files(env.as_ref(), Path::new("backups/shared"), "ss").unwrap();
+        assert_eq!(shared.len(), 2);
+
+        engine.restore(1, Path::new("restored1")).unwrap();
+        engine.restore(2, Path::new("restored2")).unwrap();
+        assert!(engine.restore(2, Path::new("db")).is_err());
+        assert!(matches!(
+            engine.restore(3, Path::new("restored3")),
+            Err(Error::NotFound(_))
+        ));
+        let restored = DB::open(Path::new("restored1"), options.clone()).unwrap();
+        assert_eq!(keys(&restored), vec!["a"]);
+        let restored = DB::open(Path::new("restored2"), options.clone()).unwrap();
+        assert_eq!(keys(&restored), vec!["b"]);
+
+        // backups survive reopening the engine, and deleting one keeps the files of the other
+        engine.close().unwrap();
+        let mut engine = BackupEngine::open(env.clone(), Path::new("backups")).unwrap();
+        assert_eq!(engine.backups(), backups);
+        assert_eq!(engine.purge_old_backups(1).unwrap(), vec![1]);
+        engine.verify_backup(2).unwrap();
+        assert_eq!(engine.backups()[0].id, 2);
+        engine.restore(2, Path::new("restored4")).unwrap();
+        assert_eq!(engine.create_new_backup(&db).unwrap(), 3);
+    }
+
+    #[test]
+    fn backup_ingested_file() {
+        let env = Arc::new(MemEnv::new());
+        let options = Options::default().env(env.clone());
+        let db = DB::open(Path::new("db"), options.clone()).unwrap();
+        let mut writer = SstFileWriter::new(TableOptions::default());
+        writer.put("a".to_owned(), "1".to_owned()).unwrap();
+        writer.finish(env.as_ref(), Path::new("a.sst")).unwrap();
+        db.ingest_external_files(&[PathBuf::from("a.sst")]).unwrap();
+
+        // the sequence of the ingested file is restored along with it
+        let mut engine = BackupEngine::open(env.clone(), Path::new("backups")).unwrap();
+        engine.create_new_backup(&db).unwrap();
+        engine.close().unwrap();
+        let engine = BackupEngine::open(env.clone(), Path::new("backups")).unwrap();
+        engine.restore(1, Path::new("restored")).unwrap();
+        let restored = DB::open(Path::new("restored"), options).unwrap();
+        assert_eq!(
+            restored.table_properties().unwrap()[0].1,
+            db.table_properties().unwrap()[0].1
+        );
+    }
+
+    #[test]
+    fn backup_tables_with_the_same_name() {
+        let env = Arc::new(MemEnv::new());
+        let options = Options::default().env(env.clone());
+        let mut engine = BackupEngine::open(env.clone(), Path::new("backups")).unwrap();
+        for (dir, value) in [("db1", "1"), ("db2", "2")] {
+            let db = DB::open(Path::new(dir), options.clone()).unwrap();
+            db.set("a".to_owned(), value.to_owned()).unwrap();
+            engine.create_new_backup(&db).unwrap();
+            db.close().unwrap();
+        }
+
+        // rewritten in place, like a repair does
+        let db = DB::open(Path::new("db1"), options.clone()).unwrap();
+        let sstables = db.live_files().sstables.clone();
+        let records = vec![Record::new("a".to_owned(), "3".to_owned())];
+        env.create(&sstables[0])
+            .unwrap()
+            .append(&SSTable::from_records(records).to_bytes())
+            .unwrap();
+        engine.create_new_backup(&db).unwrap();
+        drop(db);
+
+        let shared = list_files(env.as_ref(), Path::new("backups/shared"), "ss").unwrap();
+        assert_eq!(shared.len(), 3);
+        for (id, value) in [(1, "1"), (2, "2"), (3, "3")] {
+            let dir = PathBuf::from(format!("restored{}", id));
+            engine.restore(id, &dir).unwrap();
+            let restored = DB::open(&dir, options.clone()).unwrap();
+            assert_eq!(
+                restored.get(&"a".to_owned()).unwrap().as_deref(),
+                Some(value)
+            );
+            assert_eq!(
+                list_files(env.as_ref(), &dir.join("sstable"), "ss").unwrap(),
+                vec![dir.join("sstable").join(sstables[0].file_name().unwrap())]
+            );
+        }
+    }
+
+    #[test]
+    fn backup_during_compaction() {
+        let env = Arc::new(MemEnv::new());
+        let db = DB::open(Path::new("db"), Options::default().env(env.clone())).unwrap();
+        let mut engine = BackupEngine::open(env.clone(), Path::new("backups")).unwrap();
+        for i in 0..3 {
+            db.set(i.to_string(), i.to_string()).unwrap();
+            db.flush(&FlushOptions::default()).unwrap();
+        }
+
+        // a compaction while the files are being copied must not remove them
+        let live_files = db.live_files();
+        db.compact(0).unwrap();
+        assert!(live_files.sstables.iter().all(|path| env.exists(path)));
+        drop(live_files);
+        assert_eq!(
+            list_files(env.as_ref(), Path::new("db/sstable"), "ss")
+                .unwrap()
+                .len(),
+            1
+        );
+
+        engine.create_new_backup(&db).unwrap();
+        engine.restore(1, Path::new("restored")).unwrap();
+        let restored = DB::open(Path::new("restored"), Options::default().env(env)).unwrap();
+        assert_eq!(keys(&restored), vec!["0", "1", "2"]);
+    }
+
+    #[test]
+    fn verify_corrupt_backup() {
+        let env = Arc::new(FaultInjectionEnv::new(Arc::new(MemEnv::new())));
+        let db = DB::open(Path::new("db"), Options::default().env(env.clone())).unwrap();
+        let mut engine = BackupEngine::open(env.clone(), Path::new("backups")).unwrap();
+        db.set("a".to_owned(), "1".to_owned()).unwrap();
+        engine.create_new_backup(&db).unwrap();
+        engine.verify_backup(1).unwrap();
+
+        let shared = list_files(env.as_ref(), Path::new("backups/shared"), "ss").unwrap();
+        env.corrupt(&shared[0], 0, 1).unwrap();
+        let err = engine.verify_backup(1).unwrap_err();
+        assert!(err.to_string().contains("checksum mismatch"), "{}", err);
+        assert!(engine.restore(1, Path::new("restored")).is_err());
+
+        env.remove_file(&shared[0]).unwrap();
+        let err = engine.verify_backup(1).unwrap_err();
+        assert!(
+            err.to_string().contains("backup file is missing"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn unfinished_backup_is_removed() {
+        let env = Arc::new(MemEnv::new());
+        let db = DB::open(Path::new("db"), Options::default().env(env.clone())).unwrap();
+        let mut engine = BackupEngine::open(env.clone(), Path::new("backups")).unwrap();
+        db.set("a".to_owned(), "1".to_owned()).unwrap();
+        engine.create_new_backup(&db).unwrap();
+        assert!(matches!(
+            BackupEngine::open(env.clone(), Path::new("backups")),
+            Err(Error::Busy(_))
+        ));
+        engine.close().unwrap();
+
+        // as if the process stopped before writing the metadata
+        env.remove_file(Path::new("backups/meta/1")).unwrap();
+        let engine = BackupEngine::open(env.clone(), Path::new("backups")).unwrap();
+        assert!(engine.backups().is_empty());
+        assert!(list_files(env.as_ref(), Path::new("backups/shared"), "ss")
+            .unwrap()
+            .is_empty());
+        assert!(!env.exists(Path::new("backups/private/1")));
+    }
+}
@@ -0,0 +1,143 @@
+//! Crashes a database at random points of a randomized workload, then checks that reopening it
+//! recovers every acknowledged write, and nothing that was not written
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::db::DB;
+use super::options::{CompactionPolicy, Options};
+use crate::env::{FaultInjectionEnv, MemEnv};
+use crate::storage::wal::SyncMode;
+
+const SEEDS: u64 = 20;
+const CRASHES_PER_SEED: usize = 5;
+const KEYS: usize = 40;
+/// Writes that fail before the environment stops failing
+const FAILED_WRITES: usize = 3;
+/// Most writes issued once the environment stopped failing, before the crash
+const MAX_WRITES_AFTER_FAILURE: usize = 20;
+
+/// The value each key should have after a crash. A write that failed may or may not have made
+/// it to disk, so its key may have any of the values of the writes that failed since the last
+/// acknowledged one
+#[derive(Default)]
+struct Expected {
+    values: BTreeMap<String, String>,
+    in_doubt: BTreeMap<String, Vec<Option<String>>>,
+}
+
+impl Expected {
+    fn check(&self, db: &DB, seed: u64) {
+        for i in 0..KEYS {
+            let key = format!("key{}", i);
+            let value = db.get(&key).unwrap();
+            let acknowledged = self.values.get(&key).cloned();
+            let allowed = value == acknowledged
+                || self
+                    .in_doubt
+                    .get(&key)
+                    .is_some_and(|in_doubt| in_doubt.contains(&value));
+            assert!(
+                allowed,
+                "seed {}: {} is {:?}, expected {:?}",
+                seed, key, value, acknowledged
+            );
+        }
+    }
+
+    /// Takes the values found after a crash as the truth for the keys in doubt
+    fn resolve(&mut self, db: &DB) {
+        for key in std::mem::take(&mut self.in_doubt).into_keys() {
+            match db.get(&key).unwrap() {
+                Some(value) => self.values.insert(key, value),
+                None => self.values.remove(&key),
+            };
+        }
+    }
+}
+
+fn options(env: &FaultInjectionEnv) -> Options {
+    Options::default()
+        .env(Arc::new(env.clone()))
+        .memtable_bytes(2_000)
+        .write_buffer_bytes(8_000)
+        .compaction_policy(CompactionPolicy::Level0FileNum(3))
+        .sync_mode(SyncMode::Fsync)
+}
+
+/// Writes through a failure of the environment, which stops after a few failed writes, and then
+/// a few more before the crash. Writes acknowledged after the failure must survive as well,
+/// whatever state it left the database in
+fn run_workload(
+    db: &DB,
+    env: &FaultInjectionEnv,
+    rng: &mut StdRng,
+    expected: &mut Expected,
+    seed: u64,
+    round: usize,
+) {
+    let mut failures = 0;
+    let mut ops = rng.gen_range(0..300);
+    let mut op = 0;
+    while op < ops {
+        let key = format!("key{}", rng.gen_range(0..KEYS));
+        let value = match rng.gen_bool(0.8) {
+            true => Some(format!("{}-{}-{}", seed, round, op)),
+            false => None,
+        };
+        let result = match &value {
+            Some(value) => db.set(key.clone(), value.clone()),
+            None => db.delete(key.clone()),
+        };
+
+        match result {
+            Ok(()) => {
+                expected.in_doubt.remove(&key);
+                match value {
+                    Some(value) => expected.values.insert(key, value),
+                    None => expected.values.remove(&key),
+                };
+            }
+            Err(_) => {
+                expected.in_doubt.entry(key).or_default().push(value);
+                failures += 1;
+                if failures == FAILED_WRITES {
+                    env.reset();
+                    ops = ops.min(op + 1 + rng.gen_range(0..MAX_WRITES_AFTER_FAILURE));
+                }
+            }
+        }
+        op += 1;
+    }
+}
+
+#[test]
+fn acknowledged_writes_survive_crashes() {
+    let path = Path::new("crash_test");
+    for seed in 0..SEEDS {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let env = FaultInjectionEnv::new(Arc::new(MemEnv::new()));
+        let mut expected = Expected::default();
+
+        for round in 0..CRASHES_PER_SEED {
+            let db = DB::open(path, options(&env)).unwrap();
+            expected.check(&db, seed);
+            expected.resolve(&db);
+
+            // fail somewhere in the middle of the workload, or not at all
+            env.fail_after(rng.gen_range(0..1_000));
+            run_workload(&db, &env, &mut rng, &mut expected, seed, round);
+
+            env.crash();
+            drop(db);
+            env.drop_unsynced_data().unwrap();
+            env.reset();
+        }
+
+        let db = DB::open(path, options(&env)).unwrap();
+        expected.check(&db, seed);
+    }
+}
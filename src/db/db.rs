@@ -1,176 +1,1389 @@
 #![allow(dead_code)]
-use std::collections::HashMap;
-use std::fs::remove_file;
-use std::io;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::ErrorKind;
+use std::ops::{Bound, RangeBounds};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
 
+use super::manifest::{Manifest, MANIFEST_FILE};
+use super::options::{
+    CompactionPolicy, FlushOptions, Options, VerifyOptions, WriteOptions, OPTIONS_FILE,
+};
+use super::repair::{repair, RepairReport};
+use super::table_cache::TableCache;
+use super::verify::{verify_table, Problem, VerifyReport};
+use super::write_batch::WriteBatch;
+use crate::env::{Env, FileLock};
+use crate::error::{Error, Result};
+
+use crate::storage::fs::{file_number, link_or_copy, list_files, remove_temp_files, write_atomic};
 use crate::storage::memtable::MemTable;
+use crate::storage::properties::TableProperties;
 use crate::storage::record::Record;
-use crate::storage::sstable::SSTable;
-use crate::storage::wal::WriteAheadLog;
+use crate::storage::sstable::{BlockHandle, SSTable};
+use crate::storage::wal::{SyncMode, WriteAheadLog, WriteAheadLogIter};
 
-use glob::{glob, GlobResult};
 use itertools::Itertools;
 
-#[derive(Hash, PartialEq, Eq)]
-enum DBConfig {
-    MemtableSize,
+/// Locked by the process that has the database open
+pub const LOCK_FILE: &str = "LOCK";
+/// How often a secondary re-reads the manifest when the WALs it lists disappear while catching up
+const MAX_CATCH_UP_ATTEMPTS: usize = 10;
+
+/// The parts of the database that change as data is written, and readers need a consistent
+/// view of
+struct DBState {
+    mem_table: MemTable,
+    /// Memtables that are full and waiting to be flushed by the background thread, oldest first
+    immutables: VecDeque<Arc<ImmutableMemTable>>,
+    sstables: Vec<PathBuf>,
+    /// The WAL records are appended to, unless the database is not open for writing
+    wal_path: Option<PathBuf>,
+    /// Number of the next file the database creates, see `Manifest::next_file_number`
+    next_file_number: AtomicU64,
+    /// See `Manifest::global_sequences`
+    global_sequences: BTreeMap<PathBuf, u128>,
 }
-pub struct DB {
-    root_path: PathBuf,
-    wal: WriteAheadLog,
+
+impl DBState {
+    /// Memory used by all memtables, which is what `Options::write_buffer_bytes` limits
+    fn memory_usage(&self) -> usize {
+        self.mem_table.approximate_memory_usage()
+            + self
+                .immutables
+                .iter()
+                .map(|immutable| immutable.mem_table.approximate_memory_usage())
+                .sum::<usize>()
+    }
+
+    /// Takes the number for a new SSTable or WAL
+    fn new_file_number(&self) -> u64 {
+        self.next_file_number.fetch_add(1, Ordering::SeqCst)
+    }
+
+    fn manifest(&self) -> Manifest {
+        Manifest {
+            sstables: self.sstables.clone(),
+            wals: self
+                .immutables
+                .iter()
+                .map(|immutable| immutable.wal_path.clone())
+                .chain(self.wal_path.clone())
+                .collect(),
+            next_file_number: self.next_file_number.load(Ordering::SeqCst),
+            global_sequences: self.global_sequences.clone(),
+        }
+    }
+}
+
+struct ImmutableMemTable {
     mem_table: MemTable,
+    /// The WAL holding the records of the memtable, which can be removed once it is flushed
+    wal_path: PathBuf,
+}
+
+/// Memtables holding the records read from each WAL, and how far each WAL was read
+type TailedWals = (VecDeque<Arc<ImmutableMemTable>>, HashMap<PathBuf, u64>);
+
+/// Writes waiting to be committed. The first writer to find no commit in progress becomes the
+/// leader, and commits the writes of everyone queued at that point with a single WAL append and
+/// sync
+#[derive(Default)]
+struct WriteQueue {
+    next_id: u64,
+    pending: Vec<(u64, WriteBatch, SyncMode)>,
+    results: HashMap<u64, Result<()>>,
+    committing: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Primary,
+    ReadOnly,
+    /// Read-only, and follows the primary with `DB::try_catch_up_with_primary`
+    Secondary,
+}
+
+/// SSTables that compactions replaced while `LiveFiles` kept them from being removed
+#[derive(Default)]
+struct ObsoleteFiles {
+    /// Number of `LiveFiles` alive
+    pins: usize,
+    pending: Vec<PathBuf>,
+}
+
+#[derive(Default)]
+struct Background {
+    shutting_down: bool,
+    /// Set while a thread flushes on behalf of an `Env` without background threads
+    flushing: bool,
+    /// First error hit by a background flush. Writes fail with it from then on
+    error: Option<Error>,
+}
+
+/// Everything the background flush thread needs access to. Without background threads, see
+/// `Env::background_threads`, the threads calling into the database do its work instead.
+///
+/// Locks are taken in the order `wal`, `background`, `state`, and `background` is never locked
+/// while holding `state`
+struct Shared {
+    root_path: PathBuf,
+    mode: Mode,
     sstable_path: PathBuf,
-    sstables: Vec<PathBuf>,
     wal_path: PathBuf,
-    config: HashMap<DBConfig, usize>,
+    options: Options,
+    table_cache: TableCache,
+    // only the writer leading a commit touches the WAL. `None` if the database is read-only
+    wal: Mutex<Option<WriteAheadLog>>,
+    state: RwLock<DBState>,
+    write_queue: Mutex<WriteQueue>,
+    write_done: Condvar,
+    background: Mutex<Background>,
+    /// Signalled when a memtable is frozen, a flush finishes or the database shuts down
+    background_changed: Condvar,
+    /// How far the WALs of the primary have been read, for databases that are not the primary
+    wal_offsets: Mutex<HashMap<PathBuf, u64>>,
+    obsolete_files: Mutex<ObsoleteFiles>,
+}
+
+/// A key-value store that can be shared between threads. Writes from concurrent threads are
+/// committed together (group commit), and full memtables are flushed by a background thread
+pub struct DB {
+    root_path: PathBuf,
+    shared: Arc<Shared>,
+    flush_thread: Option<JoinHandle<()>>,
+    /// Whether the database still needs to be shut down, which read-only databases never do
+    open: bool,
+    /// Holds the exclusive lock on `LOCK_FILE` for as long as the database is open
+    lock_file: Option<Box<dyn FileLock>>,
 }
 
 impl DB {
-    pub fn new(path: &Path) -> DB {
-        let wal = WriteAheadLog::new(&path.join("wal")).unwrap();
-        let mem_table = MemTable::new();
+    /// Opens the database at `path` with the default options, creating it if it does not exist
+    pub fn new(path: &Path) -> Result<DB> {
+        DB::open(path, Options::default())
+    }
 
-        let sstables: Vec<PathBuf> = glob(path.join("sstable").join("*.ss").to_str().unwrap())
-            .unwrap()
-            .flat_map(|p| p.ok())
-            .sorted()
-            .collect();
+    pub fn open(path: &Path, options: Options) -> Result<DB> {
+        options.validate()?;
+        let env = options.env.clone();
+        let env = env.as_ref();
 
-        DB {
-            root_path: path.to_path_buf(),
-            wal,
-            mem_table,
-            sstable_path: path.join("sstable"),
+        let exists = env.exists(&path.join(OPTIONS_FILE))
+            || env.exists(&path.join("sstable"))
+            || env.exists(&path.join("wal"));
+        if exists && options.error_if_exists {
+            return Err(Error::InvalidArgument(format!(
+                "database {} already exists",
+                path.display()
+            )));
+        }
+        if !exists && !options.create_if_missing {
+            return Err(Error::NotFound(format!(
+                "database {} does not exist",
+                path.display()
+            )));
+        }
+
+        env.create_dir_all(path)?;
+        let lock_file = env.lock(&path.join(LOCK_FILE))?;
+        // files that were still being written when the process stopped
+        remove_temp_files(env, path)?;
+        remove_temp_files(env, &path.join("sstable"))?;
+        options.write(&path.join(OPTIONS_FILE))?;
+
+        let on_disk = list_files(env, &path.join("sstable"), "ss")?;
+        let on_disk_wals = list_files(env, &path.join("wal"), "wal")?;
+        let manifest = Manifest::load(env, path)?;
+        // past every file there is, even those the manifest does not know about
+        let mut next_file_number = on_disk
+            .iter()
+            .chain(on_disk_wals.iter())
+            .filter_map(|file_path| file_number(file_path))
+            .map(|number| number + 1)
+            .chain(manifest.as_ref().map(|manifest| manifest.next_file_number))
+            .max()
+            .unwrap_or(1);
+        let (mut sstables, wals, global_sequences) = match manifest {
+            Some(manifest) => {
+                // written by a flush or compaction that did not get to update the manifest, or
+                // replaced by a compaction that did not get to remove them
+                let live = manifest
+                    .sstables
+                    .iter()
+                    .map(|sstable_path| sstable_path.file_name())
+                    .collect_vec();
+                for sstable_path in on_disk.iter() {
+                    if !live.contains(&sstable_path.file_name()) {
+                        env.remove_file(sstable_path)?;
+                    }
+                }
+                (manifest.sstables, manifest.wals, manifest.global_sequences)
+            }
+            None => (on_disk, on_disk_wals.clone(), BTreeMap::new()),
+        };
+
+        // records that had not been flushed when the database was last closed, or crashed
+        let mut recovered = MemTable::new();
+        for wal_path in wals.iter() {
+            for record in WriteAheadLogIter::open(env, wal_path)? {
+                recovered.insert(record);
+            }
+        }
+        if !recovered.is_empty() {
+            let mut sstable = SSTable::from_records_with_options(
+                recovered.to_records(),
+                &options.table_options(),
+            );
+            sstables.push(sstable.write_numbered(env, &path.join("sstable"), next_file_number)?);
+            next_file_number += 1;
+        }
+
+        let mut wal =
+            WriteAheadLog::create(options.env.clone(), &path.join("wal"), next_file_number)?;
+        wal.set_sync_mode(options.sync_mode);
+
+        let state = DBState {
+            mem_table: MemTable::new(),
+            immutables: VecDeque::new(),
             sstables,
-            wal_path: path.join("wal"),
-            config: vec![(DBConfig::MemtableSize, 128_000)]
-                .into_iter()
-                .collect(),
+            wal_path: Some(wal.path().to_path_buf()),
+            next_file_number: AtomicU64::new(next_file_number + 1),
+            global_sequences,
+        };
+        state.manifest().write(env, path)?;
+        // their records are in an SSTable now, or they were left behind by a freeze that did
+        // not get to update the manifest and never had records
+        for wal_path in on_disk_wals.iter() {
+            env.remove_file(wal_path)?;
         }
+        env.sync_dir(&path.join("wal"))?;
+
+        let shared = Arc::new(Shared::new(path, Mode::Primary, options, Some(wal), state));
+
+        let flush_thread = if env.background_threads() {
+            let shared = shared.clone();
+            Some(
+                thread::Builder::new()
+                    .name("rust_kv_store-flush".to_owned())
+                    .spawn(move || shared.flush_loop())?,
+            )
+        } else {
+            None
+        };
+
+        Ok(DB {
+            root_path: path.to_path_buf(),
+            shared,
+            flush_thread,
+            open: true,
+            lock_file: Some(lock_file),
+        })
     }
 
-    fn set_config(&mut self, config: DBConfig, value: usize) {
-        self.config.insert(config, value);
+    /// Opens the database at `path` without changing anything in its directory, and without
+    /// taking its lock, so it can be read while another process has it open. Writes that are
+    /// only in the WAL are visible if `replay_wal` is set. All mutating calls fail with
+    /// `Error::NotSupported`.
+    ///
+    /// Files are read through the environment of `options`. The other options come from the
+    /// database itself, unless it was created before they were persisted
+    pub fn open_read_only(path: &Path, options: &Options, replay_wal: bool) -> Result<DB> {
+        let db = DB::open_without_lock(path, options, Mode::ReadOnly)?;
+        db.shared.catch_up(replay_wal)?;
+        Ok(db)
     }
 
-    pub fn restore_wal(&mut self) {
-        let s = self.wal_path.join("*.wal").to_str().unwrap().to_string();
-        let mem_table = match glob(&s).unwrap().next() {
-            Some(GlobResult::Ok(wal_path)) => {
-                let wal = WriteAheadLog::from_file(&wal_path).unwrap();
-                wal.into_memtable()
+    /// Opens the database at `path` read-only, like `open_read_only` with `replay_wal` set,
+    /// but keeps following the primary, which may be in another process, through
+    /// `try_catch_up_with_primary`
+    pub fn open_as_secondary(path: &Path, options: &Options) -> Result<DB> {
+        let db = DB::open_without_lock(path, options, Mode::Secondary)?;
+        db.shared.catch_up(true)?;
+        Ok(db)
+    }
+
+    /// Rebuilds the database at `path` from whatever can still be read, for when it no longer
+    /// opens. Every SSTable and WAL on disk is scanned: tables with unreadable blocks are
+    /// rewritten without them, WALs are turned into an SSTable, and files that can not be
+    /// read at all are moved to the `lost` directory. The manifest is then written anew.
+    ///
+    /// The database must not be open. Table options come from the database itself, unless it
+    /// was created before they were persisted
+    pub fn repair(path: &Path, options: &Options) -> Result<RepairReport> {
+        let options_path = path.join(OPTIONS_FILE);
+        // a damaged options file must not stand in the way of a repair
+        let options = if options.env.exists(&options_path) {
+            Options::load(options.env.clone(), &options_path).unwrap_or_else(|_| options.clone())
+        } else {
+            options.clone()
+        };
+        repair(path, &options)
+    }
+
+    fn open_without_lock(path: &Path, options: &Options, mode: Mode) -> Result<DB> {
+        let env = options.env.as_ref();
+        let options_path = path.join(OPTIONS_FILE);
+        let options = if env.exists(&options_path) {
+            Options::load(options.env.clone(), &options_path)?
+        } else if env.exists(&path.join("sstable")) || env.exists(&path.join("wal")) {
+            options.clone()
+        } else {
+            return Err(Error::NotFound(format!(
+                "database {} does not exist",
+                path.display()
+            )));
+        };
+
+        let state = DBState {
+            mem_table: MemTable::new(),
+            immutables: VecDeque::new(),
+            sstables: vec![],
+            wal_path: None,
+            next_file_number: AtomicU64::new(0),
+            global_sequences: BTreeMap::new(),
+        };
+
+        Ok(DB {
+            root_path: path.to_path_buf(),
+            shared: Arc::new(Shared::new(path, mode, options, None, state)),
+            flush_thread: None,
+            open: false,
+            lock_file: None,
+        })
+    }
+
+    /// Picks up the SSTables the primary has written and the records it has appended to its
+    /// WALs since this secondary was opened or last caught up
+    pub fn try_catch_up_with_primary(&self) -> Result<()> {
+        if self.shared.mode != Mode::Secondary {
+            return Err(Error::NotSupported(format!(
+                "{} is not opened as a secondary",
+                self.root_path.display()
+            )));
+        }
+        self.shared.catch_up(true)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.root_path
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.shared.options
+    }
+
+    pub fn get(&self, key: &String) -> Result<Option<String>> {
+        self.shared.offer_background_work();
+        let state = self.shared.state.read().unwrap();
+
+        // the newest record for the key decides, even if it is a tombstone
+        let memtables = std::iter::once(&state.mem_table).chain(
+            state
+                .immutables
+                .iter()
+                .rev()
+                .map(|immutable| &immutable.mem_table),
+        );
+        for mem_table in memtables {
+            if let Some(record) = mem_table.get_record(key) {
+                return Ok(value(record));
+            }
+        }
+
+        for ss_table_path in state.sstables.iter().rev() {
+            if let Some(record) = self.shared.table_cache.get(ss_table_path, key)? {
+                return Ok(value(&record));
             }
-            _ => unreachable!(),
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the live keys in `range` with their values, sorted by key
+    pub fn scan<R: RangeBounds<String>>(&self, range: R) -> Result<Vec<(String, String)>> {
+        self.iter(range)?.collect()
+    }
+
+    /// Returns the live keys starting with `prefix` with their values, sorted by key
+    pub fn scan_prefix(&self, prefix: &str) -> Result<Vec<(String, String)>> {
+        self.iter_prefix(prefix)?.collect()
+    }
+
+    /// Like `scan`, but reads the keys and values as the iterator is advanced, from the SSTables
+    /// and memtables live when it was created. Compactions leave those SSTables on disk until
+    /// it is dropped
+    pub fn iter<R: RangeBounds<String>>(&self, range: R) -> Result<DBIterator> {
+        self.shared.offer_background_work();
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+        let state = self.shared.state.read().unwrap();
+
+        // sources go from oldest to newest, and newer records replace older ones
+        let mut sources = vec![];
+        for sstable_path in state.sstables.iter() {
+            let table = self.shared.table_cache.table(sstable_path)?;
+            sources.push(Source {
+                records: VecDeque::new(),
+                blocks: table.block_handles(&range).into(),
+                table: Some((sstable_path.clone(), table)),
+            });
+        }
+        // memtables are copied, they are small next to the SSTables
+        let memtables = state
+            .immutables
+            .iter()
+            .map(|immutable| &immutable.mem_table)
+            .chain(std::iter::once(&state.mem_table));
+        for mem_table in memtables {
+            sources.push(Source {
+                records: mem_table.range(range.clone()).cloned().collect(),
+                blocks: VecDeque::new(),
+                table: None,
+            });
+        }
+
+        Ok(DBIterator {
+            live_files: self.pin_live_files(&state),
+            range,
+            sources,
+        })
+    }
+
+    /// Like `iter`, for the live keys starting with `prefix`
+    pub fn iter_prefix(&self, prefix: &str) -> Result<DBIterator> {
+        let start = Bound::Included(prefix.to_owned());
+        let end = match prefix_end(prefix) {
+            Some(end) => Bound::Excluded(end),
+            None => Bound::Unbounded,
         };
+        self.iter((start, end))
+    }
+
+    pub fn set(&self, key: String, value: String) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.set(key, value);
+        self.write(batch, &WriteOptions::default())
+    }
 
-        self.mem_table = mem_table;
+    pub fn delete(&self, key: String) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.delete(key);
+        self.write(batch, &WriteOptions::default())
     }
-    pub fn get(&self, key: &String) -> Option<String> {
-        if let Some(v) = self.mem_table.get(key) {
-            return Some(v);
+
+    /// Applies all updates in `batch`. Returns once they are as durable as the sync mode of
+    /// `write_options` asks for, falling back to `Options::sync_mode`
+    pub fn write(&self, batch: WriteBatch, write_options: &WriteOptions) -> Result<()> {
+        let shared = &self.shared;
+        shared.check_writable()?;
+        let sync_mode = write_options.sync_mode.unwrap_or(shared.options.sync_mode);
+
+        let mut queue = shared.write_queue.lock().unwrap();
+        let id = queue.next_id;
+        queue.next_id += 1;
+        queue.pending.push((id, batch, sync_mode));
+
+        // wait until a leader has committed our batch, or until we can lead a commit ourselves
+        loop {
+            if let Some(result) = queue.results.remove(&id) {
+                return result;
+            }
+            if !queue.committing {
+                break;
+            }
+            queue = shared.write_done.wait(queue).unwrap();
         }
 
-        for ss_table_path in self.sstables.iter().rev() {
-            if let Some(value) = SSTable::from_disk(ss_table_path)
-                .unwrap_or_default()
-                .get(ss_table_path, key)
-                .unwrap()
-            {
-                return Some(value);
+        queue.committing = true;
+        let group = std::mem::take(&mut queue.pending);
+        drop(queue);
+
+        let ids = group.iter().map(|(id, ..)| *id).collect_vec();
+        let result = shared.commit(group);
+
+        let mut queue = shared.write_queue.lock().unwrap();
+        for writer_id in ids.into_iter().filter(|writer_id| *writer_id != id) {
+            let writer_result = match &result {
+                Ok(()) => Ok(()),
+                Err(err) => Err(err.duplicate()),
+            };
+            queue.results.insert(writer_id, writer_result);
+        }
+        queue.committing = false;
+        drop(queue);
+        shared.write_done.notify_all();
+
+        shared.offer_background_work();
+        result
+    }
+
+    /// Writes the memtable to an SSTable, even if it is not full
+    pub fn flush(&self, flush_options: &FlushOptions) -> Result<()> {
+        self.shared.check_writable()?;
+        // holding the WAL keeps writers out while the memtable is swapped
+        let mut wal_guard = self.shared.wal.lock().unwrap();
+        let wal = active_wal(&mut wal_guard)?;
+        let mut state = self.shared.state.write().unwrap();
+        if !state.mem_table.is_empty() {
+            self.shared.freeze_memtable(wal, &mut state)?;
+        }
+        drop(state);
+        drop(wal_guard);
+        self.shared.notify_background();
+
+        if flush_options.wait {
+            self.wait_for_flushes()?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the memtable, waits for background work to finish and removes the WAL, so the
+    /// next open finds all data in SSTables
+    pub fn close(mut self) -> Result<()> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> Result<()> {
+        if !std::mem::replace(&mut self.open, false) {
+            return Ok(());
+        }
+        let shared = &self.shared;
+
+        // hand the memtable to the flush thread together with the active WAL, so that WAL is
+        // removed once the memtable is flushed
+        let mut wal_guard = shared.wal.lock().unwrap();
+        let wal = active_wal(&mut wal_guard)?;
+        // stop the flush thread even if this fails
+        let sync_result = wal.sync();
+        let mut state = shared.state.write().unwrap();
+        let mem_table = std::mem::take(&mut state.mem_table);
+        state.wal_path = None;
+        let remove_wal = mem_table.is_empty();
+        let manifest_result = if remove_wal {
+            shared.write_manifest(&state)
+        } else {
+            state.immutables.push_back(Arc::new(ImmutableMemTable {
+                mem_table,
+                wal_path: wal.path().to_path_buf(),
+            }));
+            Ok(())
+        };
+        drop(state);
+
+        shared.background.lock().unwrap().shutting_down = true;
+        shared.background_changed.notify_all();
+        match self.flush_thread.take() {
+            Some(flush_thread) => {
+                let _ = flush_thread.join();
+            }
+            None => shared.run_background_work(true),
+        }
+
+        sync_result?;
+        manifest_result?;
+        if let Some(err) = &shared.background.lock().unwrap().error {
+            return Err(err.duplicate());
+        }
+        if remove_wal {
+            shared.env().remove_file(wal.path())?;
+            shared.env().sync_dir(&shared.wal_path)?;
+        }
+
+        if let Some(lock_file) = self.lock_file.take() {
+            lock_file.unlock()?;
+        }
+        Ok(())
+    }
+
+    /// Blocks until every frozen memtable has been flushed to an SSTable
+    pub(crate) fn wait_for_flushes(&self) -> Result<()> {
+        let shared = &self.shared;
+        if !shared.env().background_threads() {
+            shared.run_background_work(true);
+        }
+        let mut background = shared.background.lock().unwrap();
+        loop {
+            if let Some(err) = &background.error {
+                return Err(err.duplicate());
+            }
+            if shared.state.read().unwrap().immutables.is_empty() {
+                return Ok(());
+            }
+            background = shared.background_changed.wait(background).unwrap();
+        }
+    }
+
+    /// Returns the properties of every live SSTable, oldest first
+    pub fn table_properties(&self) -> Result<Vec<(PathBuf, TableProperties)>> {
+        self.shared
+            .state
+            .read()
+            .unwrap()
+            .sstables
+            .iter()
+            .map(|path| {
+                Ok((
+                    path.clone(),
+                    self.shared.table_cache.table(path)?.properties().clone(),
+                ))
+            })
+            .collect()
+    }
+
+    /// Checks every live SSTable and that the manifest matches the files on disk. The SSTables
+    /// are checked while writes, flushes and compactions go on, and compactions leave them on
+    /// disk until they are. Returns an error only if the files can not be listed; whatever is
+    /// wrong with them is in the report
+    pub fn verify(&self, verify_options: &VerifyOptions) -> Result<VerifyReport> {
+        let shared = &self.shared;
+        let env = shared.env();
+        let live_files = self.live_files();
+        let mut report = VerifyReport {
+            tables: live_files
+                .sstables
+                .iter()
+                .map(|path| verify_table(env, path, verify_options))
+                .collect(),
+            ..Default::default()
+        };
+        drop(live_files);
+
+        // the manifest changes together with the state
+        let state = shared.state.read().unwrap();
+        let manifest_path = self.root_path.join(MANIFEST_FILE);
+        let manifest = match Manifest::load(env, &self.root_path) {
+            Ok(Some(manifest)) => manifest,
+            // databases created before the manifest was introduced only have their files
+            Ok(None) => return Ok(report),
+            Err(err) => {
+                report.problems.push(Problem {
+                    file: manifest_path,
+                    offset: None,
+                    message: err.to_string(),
+                });
+                return Ok(report);
+            }
+        };
+        // read-only databases may be behind the manifest of the primary
+        if shared.mode == Mode::Primary && manifest.sstables != state.sstables {
+            report.problems.push(Problem {
+                file: manifest_path.clone(),
+                offset: None,
+                message: "lists other SSTables than the open database uses".to_owned(),
+            });
+        }
+        for path in manifest.sstables.iter().chain(manifest.wals.iter()) {
+            if !env.exists(path) {
+                report.problems.push(Problem {
+                    file: path.clone(),
+                    offset: None,
+                    message: "listed in the manifest, but missing".to_owned(),
+                });
+            }
+        }
+
+        let on_disk = list_files(env, &shared.sstable_path, "ss")?
+            .into_iter()
+            .chain(list_files(env, &shared.wal_path, "wal")?);
+        report.untracked_files = on_disk
+            .filter(|path| !manifest.sstables.contains(path) && !manifest.wals.contains(path))
+            .collect();
+        Ok(report)
+    }
+
+    pub fn compact(&self, level: u8) -> Result<()> {
+        self.shared.check_writable()?;
+        let mut state = self.shared.state.write().unwrap();
+        self.shared.compact_level(&mut state, level)
+    }
+
+    /// Adds the SSTables at `paths`, e.g. built with `SstFileWriter`, to the database in a
+    /// single step, without going through the WAL and memtable. Each file is hard-linked into
+    /// the database, or copied if it cannot be, as it is, so it must not be changed in place
+    /// afterwards. Its records are read with one timestamp, newer than every write before and
+    /// kept in the manifest, so they replace older values of their keys. A file goes to level 1
+    /// if no live SSTable overlaps it, and to level 0 otherwise.
+    ///
+    /// The keys of each file must be strictly increasing, and the files must not overlap each
+    /// other. Writes wait while the files are added, and memtables holding keys in their range
+    /// are flushed first
+    pub fn ingest_external_files(&self, paths: &[PathBuf]) -> Result<()> {
+        let shared = &self.shared;
+        shared.check_writable()?;
+        let env = shared.env();
+        let mut files = paths
+            .iter()
+            .map(|path| check_external_file(env, path))
+            .collect::<Result<Vec<_>>>()?;
+        files.sort_by(|file, other| file.smallest_key.cmp(&other.smallest_key));
+        for pair in files.windows(2) {
+            if pair[0].largest_key >= pair[1].smallest_key {
+                return Err(Error::InvalidArgument(format!(
+                    "{} and {} overlap",
+                    pair[0].path.display(),
+                    pair[1].path.display()
+                )));
+            }
+        }
+
+        // holding the WAL keeps writers out until the files are live
+        let mut wal_guard = shared.wal.lock().unwrap();
+        let wal = active_wal(&mut wal_guard)?;
+        let mut state = shared.state.write().unwrap();
+        let in_memtables = files.iter().any(|file| {
+            let range = file.smallest_key.clone()..=file.largest_key.clone();
+            std::iter::once(&state.mem_table)
+                .chain(
+                    state
+                        .immutables
+                        .iter()
+                        .map(|immutable| &immutable.mem_table),
+                )
+                .any(|mem_table| mem_table.range(range.clone()).next().is_some())
+        });
+        if in_memtables {
+            // memtables are searched before SSTables, so their older values would win
+            if !state.mem_table.is_empty() {
+                shared.freeze_memtable(wal, &mut state)?;
             }
+            drop(state);
+            shared.notify_background();
+            self.wait_for_flushes()?;
+        } else {
+            drop(state);
+        }
+
+        let sequence = env.now().as_nanos();
+        env.create_dir_all(&shared.sstable_path)?;
+        let state = shared.state.read().unwrap();
+        let mut new_sstables = vec![];
+        for file in files.iter() {
+            let level =
+                match shared.overlaps_sstables(&state, &file.smallest_key, &file.largest_key)? {
+                    true => 0,
+                    false => 1,
+                };
+            let target = shared
+                .sstable_path
+                .join(SSTable::file_name(state.new_file_number(), level));
+            link_or_copy(env, &file.path, &target)?;
+            new_sstables.push(target);
         }
+        drop(state);
 
-        None
+        let mut state = shared.state.write().unwrap();
+        for sstable_path in new_sstables {
+            shared
+                .table_cache
+                .set_global_sequence(&sstable_path, sequence);
+            state
+                .global_sequences
+                .insert(sstable_path.clone(), sequence);
+            state.sstables.push(sstable_path);
+        }
+        shared.write_manifest(&state)?;
+        shared.maybe_compact_level0(&mut state)
     }
 
-    pub fn set(&mut self, key: String, value: String) -> Result<(), io::Error> {
-        let res = self.wal.set(key.clone(), value.clone());
-        match res {
-            Ok(_) => {
-                self.mem_table.set(key, value);
+    /// Creates a copy of the database in `dir`, which must not exist, that can be opened on its
+    /// own. SSTables are hard-linked, so on the same filesystem this takes little time or
+    /// space, and the WALs holding records that are not flushed yet are copied. Writes wait
+    /// until it is done
+    pub fn checkpoint(&self, dir: &Path) -> Result<()> {
+        let shared = &self.shared;
+        shared.check_writable()?;
+        let env = shared.env();
+        if env.exists(dir) {
+            return Err(Error::InvalidArgument(format!(
+                "{} already exists",
+                dir.display()
+            )));
+        }
+
+        let result = self.write_checkpoint(dir);
+        if result.is_err() && env.exists(dir) {
+            let _ = env.remove_dir_all(dir);
+        }
+        result
+    }
+
+    fn write_checkpoint(&self, dir: &Path) -> Result<()> {
+        let shared = &self.shared;
+        let env = shared.env();
+        // holding the WAL keeps writers out, so the files hold every write committed so far
+        let mut wal_guard = shared.wal.lock().unwrap();
+        active_wal(&mut wal_guard)?.flush()?;
+        let state = shared.state.read().unwrap();
+
+        let live = state.manifest();
+        let sstable_dir = dir.join("sstable");
+        let wal_dir = dir.join("wal");
+        env.create_dir_all(&sstable_dir)?;
+        env.create_dir_all(&wal_dir)?;
+        let mut manifest = Manifest {
+            next_file_number: live.next_file_number,
+            ..Default::default()
+        };
+        for sstable_path in live.sstables.iter() {
+            let target = sstable_dir.join(sstable_path.file_name().unwrap());
+            env.link(sstable_path, &target)?;
+            if let Some(sequence) = live.global_sequences.get(sstable_path) {
+                manifest.global_sequences.insert(target.clone(), *sequence);
             }
-            Err(e) => return Err(e),
+            manifest.sstables.push(target);
         }
+        // WALs are still appended to, so they can not be shared
+        for wal_path in live.wals.iter() {
+            let target = wal_dir.join(wal_path.file_name().unwrap());
+            write_atomic(env, &target, &env.read(wal_path)?)?;
+            manifest.wals.push(target);
+        }
+        env.sync_dir(&sstable_dir)?;
+        drop(state);
+        drop(wal_guard);
 
-        if self.mem_table.len() == *self.config.get(&DBConfig::MemtableSize).unwrap() {
-            let new_sstable = SSTable::from_records(self.mem_table.to_records());
+        shared.options.write(&dir.join(OPTIONS_FILE))?;
+        manifest.write(env, dir)
+    }
 
-            let new_sstable_path = new_sstable.write(&self.sstable_path).unwrap();
+    /// The live SSTables, which compactions leave on disk until the returned `LiveFiles` is
+    /// dropped. Records still in memtables are not included, flush first to have them
+    pub(crate) fn live_files(&self) -> LiveFiles {
+        let state = self.shared.state.read().unwrap();
+        self.pin_live_files(&state)
+    }
 
-            self.sstables.push(new_sstable_path);
+    fn pin_live_files(&self, state: &DBState) -> LiveFiles {
+        self.shared.obsolete_files.lock().unwrap().pins += 1;
+        LiveFiles {
+            shared: self.shared.clone(),
+            sstables: state.sstables.clone(),
+            global_sequences: state.global_sequences.clone(),
+        }
+    }
+}
+
+/// SSTables of a database that can be copied while it keeps running, see `DB::live_files`
+pub(crate) struct LiveFiles {
+    shared: Arc<Shared>,
+    /// Oldest first, like in the manifest
+    pub(crate) sstables: Vec<PathBuf>,
+    /// See `Manifest::global_sequences`
+    pub(crate) global_sequences: BTreeMap<PathBuf, u128>,
+}
 
-            self.mem_table = MemTable::new();
-            self.wal = WriteAheadLog::new(&self.root_path.join("wal")).unwrap();
+impl Drop for LiveFiles {
+    /// Removes the SSTables compactions replaced in the meantime. Failing to is harmless: the
+    /// next open removes SSTables that are not in the manifest
+    fn drop(&mut self) {
+        let mut obsolete_files = self.shared.obsolete_files.lock().unwrap();
+        obsolete_files.pins -= 1;
+        if obsolete_files.pins == 0 {
+            for sstable_path in std::mem::take(&mut obsolete_files.pending) {
+                let _ = self.shared.env().remove_file(&sstable_path);
+            }
         }
+    }
+}
 
+/// The live keys of a range and their values, sorted by key, see `DB::iter`
+pub struct DBIterator {
+    live_files: LiveFiles,
+    range: (Bound<String>, Bound<String>),
+    /// Oldest first. Emptied after an error, which ends the iteration
+    sources: Vec<Source>,
+}
+
+/// The records of an SSTable or memtable that the iterator has not reached yet. An SSTable is
+/// read a block at a time
+struct Source {
+    records: VecDeque<Record>,
+    blocks: VecDeque<BlockHandle>,
+    table: Option<(PathBuf, Arc<SSTable>)>,
+}
+
+impl DBIterator {
+    /// Reads blocks until the source at `index` has a record in range, or no blocks are left
+    fn fill(&mut self, index: usize) -> Result<()> {
+        let source = &mut self.sources[index];
+        while source.records.is_empty() {
+            let (Some((path, table)), Some(handle)) = (&source.table, source.blocks.pop_front())
+            else {
+                break;
+            };
+            let block = self
+                .live_files
+                .shared
+                .table_cache
+                .block(path, table, handle)?;
+            source.records.extend(
+                block
+                    .records()
+                    .iter()
+                    .filter(|record| self.range.contains(&record.get_key()))
+                    .cloned(),
+            );
+        }
         Ok(())
     }
 
-    pub fn delete(&mut self, key: String) -> Result<(), io::Error> {
-        let res = self.wal.delete(key.clone());
-        match res {
-            Ok(_) => {
-                self.mem_table.delete(key);
+    fn next_entry(&mut self) -> Result<Option<(String, String)>> {
+        loop {
+            // the smallest key decides, and the newest source holding it has its record
+            let mut newest: Option<(usize, String)> = None;
+            for index in 0..self.sources.len() {
+                self.fill(index)?;
+                if let Some(record) = self.sources[index].records.front() {
+                    let key = record.get_key();
+                    if newest
+                        .as_ref()
+                        .is_none_or(|(_, newest_key)| key <= *newest_key)
+                    {
+                        newest = Some((index, key));
+                    }
+                }
+            }
+            let Some((newest, key)) = newest else {
+                return Ok(None);
+            };
+
+            let record = self.sources[newest].records.pop_front().unwrap();
+            for source in self.sources.iter_mut() {
+                if source
+                    .records
+                    .front()
+                    .is_some_and(|record| record.get_key() == key)
+                {
+                    source.records.pop_front();
+                }
+            }
+            if let Some(value) = value(&record) {
+                return Ok(Some((key, value)));
             }
-            Err(e) => return Err(e),
         }
+    }
+}
 
-        if self.mem_table.len() == *self.config.get(&DBConfig::MemtableSize).unwrap() {
-            let new_sstable = SSTable::from_records(self.mem_table.to_records());
+impl Iterator for DBIterator {
+    type Item = Result<(String, String)>;
 
-            new_sstable.write(&self.sstable_path).unwrap();
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_entry() {
+            Ok(entry) => entry.map(Ok),
+            Err(err) => {
+                self.sources.clear();
+                Some(Err(err))
+            }
+        }
+    }
+}
 
-            self.mem_table = MemTable::new();
-            self.wal = WriteAheadLog::new(&self.root_path.join("wal")).unwrap();
+impl Drop for DB {
+    /// Shuts down like `close`, ignoring errors. Whatever could not be flushed is still in
+    /// the WAL
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
+impl Shared {
+    fn new(
+        path: &Path,
+        mode: Mode,
+        options: Options,
+        wal: Option<WriteAheadLog>,
+        state: DBState,
+    ) -> Shared {
+        let table_cache = TableCache::new(
+            options.env.clone(),
+            options.table_cache_size,
+            options.block_cache_bytes,
+        );
+        for (path, sequence) in state.global_sequences.iter() {
+            table_cache.set_global_sequence(path, *sequence);
+        }
+        Shared {
+            root_path: path.to_path_buf(),
+            mode,
+            sstable_path: path.join("sstable"),
+            wal_path: path.join("wal"),
+            table_cache,
+            options,
+            wal: Mutex::new(wal),
+            state: RwLock::new(state),
+            write_queue: Mutex::new(WriteQueue::default()),
+            write_done: Condvar::new(),
+            background: Mutex::new(Background::default()),
+            background_changed: Condvar::new(),
+            wal_offsets: Mutex::new(HashMap::new()),
+            obsolete_files: Mutex::new(ObsoleteFiles::default()),
         }
+    }
 
+    fn env(&self) -> &dyn Env {
+        self.options.env.as_ref()
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.mode != Mode::Primary {
+            return Err(Error::NotSupported(format!(
+                "{} is opened read-only",
+                self.root_path.display()
+            )));
+        }
         Ok(())
     }
 
-    pub fn compact(&mut self, level: u8) -> Result<(), io::Error> {
-        let sstables_to_compact = self
-            .sstables
-            .clone()
+    /// Appends the batches of a group to the WAL with the strongest sync mode any of them asked
+    /// for, then applies them to the memtable in the order they were queued
+    fn commit(&self, group: Vec<(u64, WriteBatch, SyncMode)>) -> Result<()> {
+        let sync_mode = group
+            .iter()
+            .fold(SyncMode::None, |mode, (_, _, sync_mode)| {
+                mode.max(*sync_mode)
+            });
+        // records are stamped with the time of the commit, on the clock of the `Env`
+        let timestamp = self.env().now().as_nanos();
+        let records = group
             .into_iter()
-            .filter(|s| {
-                s.file_stem()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .split_once('_')
-                    .unwrap()
-                    .1
-                    .parse::<u8>()
-                    .unwrap()
-                    == level
+            .flat_map(|(_, batch, _)| batch.records)
+            .map(|mut record| {
+                record.set_timestamp(timestamp);
+                record
             })
             .collect_vec();
 
+        let mut wal_guard = self.wal.lock().unwrap();
+        let wal = active_wal(&mut wal_guard)?;
+        self.make_room_for_write()?;
+        wal.append_batch(&records, sync_mode)?;
+
+        let mut state = self.state.write().unwrap();
+        for record in records {
+            state.mem_table.insert(record);
+        }
+
+        if state.mem_table.approximate_memory_usage() >= self.options.memtable_bytes {
+            self.freeze_memtable(wal, &mut state)?;
+            drop(state);
+            self.notify_background();
+        }
+
+        Ok(())
+    }
+
+    /// Waits while the memtables use up the write buffer and the flush thread has work that
+    /// will free some of it. Without background threads, does that work instead
+    fn make_room_for_write(&self) -> Result<()> {
+        let mut background = self.background.lock().unwrap();
+        loop {
+            if let Some(err) = &background.error {
+                return Err(err.duplicate());
+            }
+            let state = self.state.read().unwrap();
+            if state.immutables.is_empty() || state.memory_usage() < self.options.write_buffer_bytes
+            {
+                return Ok(());
+            }
+            drop(state);
+            if self.env().background_threads() {
+                background = self.background_changed.wait(background).unwrap();
+            } else {
+                drop(background);
+                self.run_background_work(true);
+                background = self.background.lock().unwrap();
+            }
+        }
+    }
+
+    /// Hands the memtable to the flush thread and starts a new memtable and WAL.
+    ///
+    /// The manifest naming the new WAL is written before any record goes into it, as opening
+    /// the database removes the WALs the manifest does not name. If that fails, writes keep
+    /// going to the old WAL, which both the old and the new manifest name
+    fn freeze_memtable(&self, wal: &mut WriteAheadLog, state: &mut DBState) -> Result<()> {
+        let mut new_wal = WriteAheadLog::create(
+            self.options.env.clone(),
+            &self.wal_path,
+            state.new_file_number(),
+        )?;
+        new_wal.set_sync_mode(self.options.sync_mode);
+        let mut manifest = state.manifest();
+        manifest.wals.push(new_wal.path().to_path_buf());
+        manifest.write(self.env(), &self.root_path)?;
+
+        let old_wal = std::mem::replace(wal, new_wal);
+        let mem_table = std::mem::take(&mut state.mem_table);
+        state.immutables.push_back(Arc::new(ImmutableMemTable {
+            mem_table,
+            wal_path: old_wal.path().to_path_buf(),
+        }));
+        state.wal_path = Some(wal.path().to_path_buf());
+        Ok(())
+    }
+
+    fn write_manifest(&self, state: &DBState) -> Result<()> {
+        state.manifest().write(self.env(), &self.root_path)
+    }
+
+    /// Replaces the view of a read-only or secondary database with the files in the manifest,
+    /// reading the records appended to the live WALs since the last call if `tail_wals` is set
+    fn catch_up(&self, tail_wals: bool) -> Result<()> {
+        let mut wal_offsets = self.wal_offsets.lock().unwrap();
+
+        // the primary writes a new manifest before it removes a WAL, so a WAL that is gone has
+        // been flushed to an SSTable listed by a newer manifest
+        let mut attempts = 0;
+        let (manifest, immutables, new_offsets) = loop {
+            let manifest = match Manifest::load(self.env(), &self.root_path)? {
+                Some(manifest) => manifest,
+                // written before the manifest was introduced
+                None => Manifest {
+                    sstables: list_files(self.env(), &self.sstable_path, "ss")?,
+                    wals: list_files(self.env(), &self.wal_path, "wal")?,
+                    ..Default::default()
+                },
+            };
+            if !tail_wals {
+                break (manifest, VecDeque::new(), HashMap::new());
+            }
+
+            match self.tail_wals(&manifest.wals, &wal_offsets)? {
+                Some((immutables, new_offsets)) => break (manifest, immutables, new_offsets),
+                None if attempts < MAX_CATCH_UP_ATTEMPTS => attempts += 1,
+                None => {
+                    return Err(Error::Busy(format!(
+                        "the WALs of {} changed during every attempt to catch up",
+                        self.root_path.display()
+                    )))
+                }
+            }
+        };
+
+        *wal_offsets = new_offsets;
+        for (path, sequence) in manifest.global_sequences.iter() {
+            self.table_cache.set_global_sequence(path, *sequence);
+        }
+        let mut state = self.state.write().unwrap();
+        state.sstables = manifest.sstables;
+        state.global_sequences = manifest.global_sequences;
+        state.immutables = immutables;
+        Ok(())
+    }
+
+    /// Reads the records appended to `wals` since `wal_offsets`, keeping one memtable per WAL.
+    /// Returns `None` if one of the WALs has been removed
+    fn tail_wals(
+        &self,
+        wals: &[PathBuf],
+        wal_offsets: &HashMap<PathBuf, u64>,
+    ) -> Result<Option<TailedWals>> {
+        let state = self.state.read().unwrap();
+        let mut immutables = VecDeque::new();
+        let mut new_offsets = HashMap::new();
+
+        for wal_path in wals {
+            let existing = state
+                .immutables
+                .iter()
+                .find(|immutable| &immutable.wal_path == wal_path);
+            let offset = match existing {
+                Some(_) => wal_offsets.get(wal_path).copied().unwrap_or(0),
+                None => 0,
+            };
+
+            let mut wal_iter = match WriteAheadLogIter::open_at(self.env(), wal_path, offset) {
+                Ok(wal_iter) => wal_iter,
+                Err(Error::Io(err)) if err.kind() == ErrorKind::NotFound => return Ok(None),
+                Err(err) => return Err(err),
+            };
+            let records = wal_iter.by_ref().collect_vec();
+            new_offsets.insert(wal_path.clone(), wal_iter.offset());
+
+            let immutable = match existing {
+                Some(existing) if records.is_empty() => existing.clone(),
+                _ => {
+                    let mut immutable = ImmutableMemTable {
+                        mem_table: existing
+                            .map(|existing| existing.mem_table.clone())
+                            .unwrap_or_default(),
+                        wal_path: wal_path.clone(),
+                    };
+                    for record in records {
+                        immutable.mem_table.insert(record);
+                    }
+                    Arc::new(immutable)
+                }
+            };
+            immutables.push_back(immutable);
+        }
+
+        Ok(Some((immutables, new_offsets)))
+    }
+
+    fn notify_background(&self) {
+        self.offer_background_work();
+        // taking the lock makes sure nobody is between checking their condition and waiting
+        drop(self.background.lock().unwrap());
+        self.background_changed.notify_all();
+    }
+
+    /// Lets the `Env` decide whether pending background work runs now, if there are no
+    /// background threads to run it
+    fn offer_background_work(&self) {
+        if self.mode == Mode::Primary && !self.env().background_threads() {
+            self.run_background_work(false);
+        }
+    }
+
+    /// Flushes frozen memtables on the calling thread, oldest first. Unless `all` is set, stops
+    /// as soon as `Env::run_background_work` says so
+    fn run_background_work(&self, all: bool) {
+        let mut background = self.background.lock().unwrap();
+        loop {
+            let next = self.state.read().unwrap().immutables.front().cloned();
+            let immutable = match next {
+                Some(_) if background.flushing && all => {
+                    background = self.background_changed.wait(background).unwrap();
+                    continue;
+                }
+                Some(immutable) if background.error.is_none() && !background.flushing => immutable,
+                _ => return,
+            };
+            if !all && !self.env().run_background_work() {
+                return;
+            }
+
+            background.flushing = true;
+            drop(background);
+            let result = self.flush_immutable(&immutable);
+            background = self.background.lock().unwrap();
+            background.flushing = false;
+            if let Err(err) = result {
+                background.error = Some(err);
+            }
+            self.background_changed.notify_all();
+        }
+    }
+
+    /// Body of the flush thread. Flushes frozen memtables oldest first, and only stops once
+    /// they are all flushed, or a flush failed
+    fn flush_loop(&self) {
+        loop {
+            let immutable = {
+                let mut background = self.background.lock().unwrap();
+                loop {
+                    let next = self.state.read().unwrap().immutables.front().cloned();
+                    match next {
+                        Some(immutable) if background.error.is_none() => break immutable,
+                        _ if background.shutting_down => return,
+                        _ => background = self.background_changed.wait(background).unwrap(),
+                    }
+                }
+            };
+
+            if let Err(err) = self.flush_immutable(&immutable) {
+                self.background.lock().unwrap().error = Some(err);
+            }
+            self.notify_background();
+        }
+    }
+
+    /// Writes a frozen memtable to a new SSTable. Its WAL is only removed once the SSTable is
+    /// safely on disk
+    fn flush_immutable(&self, immutable: &ImmutableMemTable) -> Result<()> {
+        let mut new_sstable = SSTable::from_records_with_options(
+            immutable.mem_table.to_records(),
+            &self.options.table_options(),
+        );
+
+        let number = self.state.read().unwrap().new_file_number();
+        let new_sstable_path =
+            new_sstable.write_numbered(self.env(), &self.sstable_path, number)?;
+
+        let mut state = self.state.write().unwrap();
+        state.sstables.push(new_sstable_path);
+        state.immutables.pop_front();
+        self.write_manifest(&state)?;
+        self.maybe_compact_level0(&mut state)?;
+        drop(state);
+
+        self.env().remove_file(&immutable.wal_path)?;
+        self.env().sync_dir(&self.wal_path)?;
+
+        Ok(())
+    }
+
+    /// Compacts level 0 once it has as many SSTables as `CompactionPolicy::Level0FileNum` asks
+    fn maybe_compact_level0(&self, state: &mut DBState) -> Result<()> {
+        if let CompactionPolicy::Level0FileNum(file_num) = self.options.compaction_policy {
+            let mut level0_files = 0;
+            for sstable_path in state.sstables.iter() {
+                if SSTable::level_from_path(sstable_path)? == 0 {
+                    level0_files += 1;
+                }
+            }
+            if level0_files >= file_num {
+                self.compact_level(state, 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a live SSTable may hold keys from `smallest` to `largest`
+    fn overlaps_sstables(&self, state: &DBState, smallest: &str, largest: &str) -> Result<bool> {
+        for sstable_path in state.sstables.iter() {
+            let table = self.table_cache.table(sstable_path)?;
+            let properties = table.properties();
+            // tables written before properties were introduced do not know their key range
+            if properties.format_version == 0
+                || (*properties.smallest_key <= *largest && smallest <= &*properties.largest_key)
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn compact_level(&self, state: &mut DBState, level: u8) -> Result<()> {
+        let mut sstables_to_compact = vec![];
+        for sstable_path in state.sstables.iter() {
+            if SSTable::level_from_path(sstable_path)? == level {
+                sstables_to_compact.push(sstable_path.clone());
+            }
+        }
+
         // collect all sstable entries in memtable to remove duplicates and tombstones
         let mut memtable = MemTable::new();
         for sstable_path in sstables_to_compact.iter() {
-            let mut sstable = SSTable::from_disk(sstable_path)?;
-            sstable.load_from_disk(sstable_path)?;
+            let mut sstable = SSTable::from_disk(self.env(), sstable_path)?;
+            if let Some(sequence) = state.global_sequences.get(sstable_path) {
+                sstable.set_global_sequence(*sequence);
+            }
+            sstable.load_from_disk(self.env(), sstable_path)?;
+            // newer tables come later and replace the records of older ones, timestamps and all
             for record in sstable.into_iter() {
-                match record {
-                    Record::Value { key, value, .. } => memtable.set(key, value),
-                    // remove tombstones from our set of records
-                    Record::Tombstone { key, .. } => memtable.delete(key),
-                }
+                memtable.insert(record);
             }
         }
 
-        let mut new_sstable = SSTable::from_records(memtable.to_records());
+        let mut new_sstable = SSTable::from_records_with_options(
+            memtable.to_records(),
+            &self.options.table_options(),
+        );
         new_sstable.level = 1;
 
-        let new_sstable_path = new_sstable.write(&self.sstable_path)?;
+        let number = state.new_file_number();
+        let new_sstable_path =
+            new_sstable.write_numbered(self.env(), &self.sstable_path, number)?;
 
         // after writing new SSTable, we need to clean the old data by
         // 1. update our list of active sstables
         // 2. deleting old SStables no longer need
 
-        let mut new_sstables = self
+        let mut new_sstables = state
             .sstables
             .clone()
             .into_iter()
@@ -178,110 +1391,310 @@ impl DB {
             .collect_vec();
 
         new_sstables.push(new_sstable_path);
-        self.sstables = new_sstables;
+        state.sstables = new_sstables;
+        for sstable_path in sstables_to_compact.iter() {
+            state.global_sequences.remove(sstable_path);
+        }
+        self.write_manifest(state)?;
 
+        // the new SSTable has been synced, so the inputs are no longer needed
         for sstable_path in sstables_to_compact.iter() {
-            remove_file(sstable_path)?;
+            self.table_cache.evict(sstable_path);
+            self.remove_sstable(sstable_path)?;
         }
+        self.env().sync_dir(&self.sstable_path)?;
 
         Ok(())
     }
+
+    /// Removes an SSTable that is no longer live, unless `LiveFiles` still need it
+    fn remove_sstable(&self, sstable_path: &Path) -> Result<()> {
+        let mut obsolete_files = self.obsolete_files.lock().unwrap();
+        if obsolete_files.pins > 0 {
+            obsolete_files.pending.push(sstable_path.to_path_buf());
+            return Ok(());
+        }
+        self.env().remove_file(sstable_path)
+    }
+}
+
+/// Returns the WAL of a database that is not read-only
+fn active_wal(wal: &mut Option<WriteAheadLog>) -> Result<&mut WriteAheadLog> {
+    wal.as_mut()
+        .ok_or_else(|| Error::NotSupported("the database is opened read-only".to_owned()))
+}
+
+/// An SSTable that is not part of the database, checked by `check_external_file`
+struct ExternalFile {
+    path: PathBuf,
+    smallest_key: String,
+    largest_key: String,
+}
+
+/// Reads every block of an SSTable that is not part of the database, checking that it has at
+/// least one key and that its keys are strictly increasing
+fn check_external_file(env: &dyn Env, path: &Path) -> Result<ExternalFile> {
+    let sstable = SSTable::from_external_file(env, path)?;
+    let mut keys: Option<(String, String)> = None;
+    for (_, handle) in sstable.index_entries() {
+        for record in sstable.read_block(env, path, handle)?.records() {
+            let key = record.get_key();
+            keys = match keys {
+                None => Some((key.clone(), key)),
+                Some((_, ref largest)) if *largest >= key => {
+                    return Err(Error::InvalidArgument(format!(
+                        "{}: key {:?} does not sort after {:?}",
+                        path.display(),
+                        key,
+                        largest
+                    )));
+                }
+                Some((smallest, _)) => Some((smallest, key)),
+            };
+        }
+    }
+    let (smallest_key, largest_key) =
+        keys.ok_or_else(|| Error::InvalidArgument(format!("{} has no keys", path.display())))?;
+    Ok(ExternalFile {
+        path: path.to_owned(),
+        smallest_key,
+        largest_key,
+    })
+}
+
+/// Returns the smallest string greater than every string starting with `prefix`, if there is one
+fn prefix_end(prefix: &str) -> Option<String> {
+    let mut chars = prefix.chars().collect_vec();
+    while let Some(last) = chars.pop() {
+        // the next code point, skipping the surrogates that are not valid chars
+        let next = match last as u32 + 1 {
+            0xd800 => Some('\u{e000}'),
+            code => char::from_u32(code),
+        };
+        if let Some(next) = next {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
+}
+
+/// Returns the value of a record found for a key, which is `None` if the key was deleted
+fn value(record: &Record) -> Option<String> {
+    match record {
+        Record::Value { value, .. } => Some(value.clone()),
+        Record::Tombstone { .. } => None,
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use std::fs::remove_dir_all;
     use std::path::PathBuf;
 
     use super::*;
+    use crate::env::MemEnv;
+    use crate::storage::compression::Compression;
+    use crate::storage::sst_file_writer::SstFileWriter;
+    use crate::storage::sstable::TableOptions;
+
+    /// Options keeping the database in `env`
+    fn options_in(env: &Arc<MemEnv>) -> Options {
+        Options::default().env(env.clone())
+    }
+
+    /// Stops `db` without flushing the memtable, leaving its WAL behind like a crashed
+    /// process would
+    fn crash(mut db: DB) {
+        db.open = false;
+        db.shared.background.lock().unwrap().shutting_down = true;
+        db.shared.background_changed.notify_all();
+        db.flush_thread.take().unwrap().join().unwrap();
+    }
 
     #[test]
     fn set() {
-        let path = PathBuf::from("./tests/db/output/set");
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("set");
 
-        let mut db = DB::new(&path);
+        let db = DB::open(&path, options_in(&env)).unwrap();
         db.set("a".to_owned(), "b".to_owned()).unwrap();
 
-        assert_eq!(db.get(&"a".to_owned()), Some("b".to_owned()));
-        remove_dir_all(path).unwrap();
+        assert_eq!(db.get(&"a".to_owned()).unwrap(), Some("b".to_owned()));
     }
 
     #[test]
     fn delete() {
-        let path = PathBuf::from("./tests/db/output/delete");
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("delete");
 
-        let mut db = DB::new(&path);
+        let db = DB::open(&path, options_in(&env)).unwrap();
         db.set("a".to_owned(), "b".to_owned()).unwrap();
 
-        assert_eq!(db.get(&"a".to_owned()), Some("b".to_owned()));
+        assert_eq!(db.get(&"a".to_owned()).unwrap(), Some("b".to_owned()));
 
         db.delete("a".to_owned()).unwrap();
 
-        assert_eq!(db.get(&"a".to_owned()), None);
+        assert_eq!(db.get(&"a".to_owned()).unwrap(), None);
+    }
 
-        remove_dir_all(path).unwrap();
+    #[test]
+    fn scan() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("scan");
+        let db = DB::open(&path, options_in(&env).block_size(64)).unwrap();
+
+        for i in 0..100 {
+            db.set(format!("key{:03}", i), format!("old{}", i)).unwrap();
+        }
+        db.flush(&FlushOptions::default()).unwrap();
+        for i in (0..100).step_by(10) {
+            db.set(format!("key{:03}", i), format!("new{}", i)).unwrap();
+        }
+        db.delete("key011".to_owned()).unwrap();
+
+        let entries = db.scan("key009".to_owned().."key013".to_owned()).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                ("key009".to_owned(), "old9".to_owned()),
+                ("key010".to_owned(), "new10".to_owned()),
+                ("key012".to_owned(), "old12".to_owned()),
+            ]
+        );
+        assert_eq!(db.scan(..).unwrap().len(), 99);
+
+        db.set("kez".to_owned(), "z".to_owned()).unwrap();
+        let entries = db.scan_prefix("key05").unwrap();
+        assert_eq!(entries.len(), 10);
+        assert_eq!(entries[0], ("key050".to_owned(), "new50".to_owned()));
+        assert_eq!(db.scan_prefix("ke").unwrap().len(), 100);
+        assert!(db.scan_prefix("x").unwrap().is_empty());
+    }
+
+    #[test]
+    fn iter_reads_a_snapshot() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("iter_reads_a_snapshot");
+        let db = DB::open(&path, options_in(&env).block_size(64)).unwrap();
+        for i in 0..50 {
+            db.set(format!("key{:02}", i), "old".to_owned()).unwrap();
+        }
+        db.flush(&FlushOptions::default()).unwrap();
+        db.delete("key01".to_owned()).unwrap();
+
+        let mut iter = db.iter(..).unwrap();
+        assert_eq!(
+            iter.next().unwrap().unwrap(),
+            ("key00".to_owned(), "old".to_owned())
+        );
+
+        // neither writes nor a compaction that removes the SSTable change what it reads
+        let sstables = db.shared.state.read().unwrap().sstables.clone();
+        db.set("key02".to_owned(), "new".to_owned()).unwrap();
+        db.flush(&FlushOptions::default()).unwrap();
+        db.compact(0).unwrap();
+        let entries = iter.collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(entries.len(), 48);
+        assert_eq!(entries[0], ("key02".to_owned(), "old".to_owned()));
+        assert!(!env.exists(&sstables[0]));
+        assert_eq!(
+            db.iter_prefix("key02").unwrap().next().unwrap().unwrap(),
+            ("key02".to_owned(), "new".to_owned())
+        );
+    }
+
+    #[test]
+    fn prefix_end() {
+        assert_eq!(super::prefix_end("ab"), Some("ac".to_owned()));
+        assert_eq!(super::prefix_end("a\u{10ffff}"), Some("b".to_owned()));
+        assert_eq!(super::prefix_end("\u{d7ff}"), Some("\u{e000}".to_owned()));
+        assert_eq!(super::prefix_end(""), None);
     }
 
     #[test]
     fn set_wal_fail() {
-        let path = PathBuf::from("./tests/db/output/set_wal_fail");
-        let mut db = DB::new(&path);
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("set_wal_fail");
+        let db = DB::open(&path, options_in(&env)).unwrap();
 
         db.set("a".to_owned(), "b".to_owned()).unwrap();
-        remove_dir_all(path).unwrap();
     }
 
     #[test]
-    fn restore_wal() {
-        let path = PathBuf::from("./tests/db/output/restore_wal");
-        let mut db = DB::new(&path);
+    fn open_replays_wal() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("open_replays_wal");
+        let db = DB::open(&path, options_in(&env)).unwrap();
 
         db.set("a".to_owned(), "b".to_owned()).unwrap();
 
-        let mut db2 = DB::new(&path);
-        db2.restore_wal();
-        assert_eq!(db2.get(&"a".to_owned()), Some("b".to_owned()));
-        remove_dir_all(path).unwrap();
+        crash(db);
+
+        let db2 = DB::open(&path, options_in(&env)).unwrap();
+        assert_eq!(db2.get(&"a".to_owned()).unwrap(), Some("b".to_owned()));
+        db2.set("a".to_owned(), "c".to_owned()).unwrap();
+        crash(db2);
+
+        let db3 = DB::open(&path, options_in(&env)).unwrap();
+        assert_eq!(db3.get(&"a".to_owned()).unwrap(), Some("c".to_owned()));
     }
 
     #[test]
     fn multiple_ss_tables() {
-        let path = PathBuf::from("./tests/db/output/multiple_ss_tables");
-        let mut db = DB::new(&path);
-        let mem_table_size = 10_000;
-        db.set_config(DBConfig::MemtableSize, mem_table_size);
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("multiple_ss_tables");
+        let memtable_bytes = 100_000;
+        let db = DB::open(&path, options_in(&env).memtable_bytes(memtable_bytes)).unwrap();
 
-        for i in 0..mem_table_size - 1 {
+        let mut i = 0;
+        while db
+            .shared
+            .state
+            .read()
+            .unwrap()
+            .mem_table
+            .approximate_memory_usage()
+            + 1_000
+            < memtable_bytes
+        {
             db.set(format!("{}{}", "a".to_owned(), i), i.to_string())
                 .unwrap();
+            i += 1;
         }
+        assert!(db.shared.state.read().unwrap().immutables.is_empty());
+        assert!(db.shared.state.read().unwrap().sstables.is_empty());
 
-        assert_eq!(db.mem_table.len(), mem_table_size - 1);
-        db.set(
-            format!("{}{}", "a".to_owned(), mem_table_size),
-            mem_table_size.to_string(),
-        )
-        .unwrap();
-        assert_eq!(db.mem_table.len(), 0);
-        for i in mem_table_size + 1..2 * mem_table_size + 11 {
+        // until two memtables have been frozen
+        while db.shared.state.read().unwrap().immutables.len()
+            + db.shared.state.read().unwrap().sstables.len()
+            < 2
+        {
             db.set(format!("{}{}", "a".to_owned(), i), i.to_string())
                 .unwrap();
+            i += 1;
         }
+        assert_eq!(db.shared.state.read().unwrap().mem_table.len(), 0);
 
-        assert_eq!(db.mem_table.len(), 10);
+        for _ in 0..10 {
+            db.set(format!("{}{}", "a".to_owned(), i), i.to_string())
+                .unwrap();
+            i += 1;
+        }
+        assert_eq!(db.shared.state.read().unwrap().mem_table.len(), 10);
+        db.wait_for_flushes().unwrap();
+        assert_eq!(db.shared.state.read().unwrap().sstables.len(), 2);
 
-        assert_eq!(db.get(&"a1".to_owned()), Some("1".to_owned()));
-        assert_eq!(db.get(&"a2".to_owned()), Some("2".to_owned()));
-        assert_eq!(db.get(&"a3".to_owned()), Some("3".to_owned()));
-        remove_dir_all(path).unwrap();
+        assert_eq!(db.get(&"a1".to_owned()).unwrap(), Some("1".to_owned()));
+        assert_eq!(db.get(&"a2".to_owned()).unwrap(), Some("2".to_owned()));
+        assert_eq!(db.get(&"a3".to_owned()).unwrap(), Some("3".to_owned()));
     }
 
     #[test]
     fn compact() {
-        let path = PathBuf::from("./tests/db/output/compact");
-        let mut db = DB::new(&path);
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("compact");
+        let db = DB::open(&path, options_in(&env).memtable_bytes(150_000)).unwrap();
         let mem_table_size = 10_000;
-        db.set_config(DBConfig::MemtableSize, mem_table_size);
 
         for _ in 0..=mem_table_size {
             db.set("a".to_owned(), "0".to_string()).unwrap();
@@ -298,11 +1711,12 @@ mod test {
                 .unwrap();
         }
 
-        assert_eq!(db.get(&"a".to_string()), Some("0".to_string()));
-        assert_eq!(db.get(&"b1".to_string()), Some("1".to_string()));
-        assert_eq!(db.get(&"c".to_string()), Some("0".to_string()));
-        assert_eq!(db.get(&"d1".to_string()), Some("1".to_string()));
-        assert_eq!(db.sstables.len(), 2);
+        assert_eq!(db.get(&"a".to_string()).unwrap(), Some("0".to_string()));
+        assert_eq!(db.get(&"b1".to_string()).unwrap(), Some("1".to_string()));
+        assert_eq!(db.get(&"c".to_string()).unwrap(), Some("0".to_string()));
+        assert_eq!(db.get(&"d1".to_string()).unwrap(), Some("1".to_string()));
+        db.wait_for_flushes().unwrap();
+        assert!(db.shared.state.read().unwrap().sstables.len() >= 2);
 
         db.delete("c".to_owned()).unwrap();
 
@@ -311,15 +1725,599 @@ mod test {
                 .unwrap();
         }
 
+        db.wait_for_flushes().unwrap();
+        db.compact(0).unwrap();
+
+        assert_eq!(db.get(&"a".to_string()).unwrap(), Some("0".to_string()));
+        assert_eq!(db.get(&"b1".to_string()).unwrap(), Some("1".to_string()));
+        assert_eq!(db.get(&"c".to_string()).unwrap(), None);
+        assert_eq!(db.get(&"d1".to_string()).unwrap(), Some("1".to_string()));
+
+        assert_eq!(db.shared.state.read().unwrap().sstables.len(), 1);
+    }
+
+    #[test]
+    fn table_properties() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("table_properties");
+        let db = DB::open(&path, options_in(&env)).unwrap();
+        let mem_table_size = 100;
+
+        for i in 0..mem_table_size - 1 {
+            db.set(format!("{}{}", "a".to_owned(), i), i.to_string())
+                .unwrap();
+        }
+        db.delete("b".to_owned()).unwrap();
+        db.flush(&FlushOptions::default()).unwrap();
+
+        let properties = db.table_properties().unwrap();
+        assert_eq!(properties.len(), 1);
+        assert_eq!(properties[0].1.num_entries, mem_table_size as u64);
+        assert_eq!(properties[0].1.num_tombstones, 1);
+        assert_eq!(properties[0].1.smallest_key, "a0");
+        assert_eq!(properties[0].1.largest_key, "b");
+    }
+
+    #[test]
+    fn verify() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("verify");
+        let db = DB::open(&path, options_in(&env)).unwrap();
+        for i in 0..10 {
+            db.set(format!("a{}", i), i.to_string()).unwrap();
+            db.flush(&FlushOptions::default()).unwrap();
+        }
+
+        let report = db.verify(&VerifyOptions::default()).unwrap();
+        assert!(report.is_ok(), "{}", report);
+        assert_eq!(report.tables.len(), 10);
+        assert!(report.tables.iter().all(|table| table.records == 1));
+        assert!(report.untracked_files.is_empty());
+
+        let sstables = db.shared.state.read().unwrap().sstables.clone();
+        env.remove_file(&sstables[0]).unwrap();
+        env.create(&path.join("sstable").join("1_0.ss")).unwrap();
+
+        let report = db.verify(&VerifyOptions::default()).unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(report.tables[0].problems.len(), 1);
+        let problems = report.problems().collect_vec();
+        assert_eq!(problems.len(), 2);
+        assert_eq!(problems[1].file, sstables[0]);
+        assert_eq!(problems[1].message, "listed in the manifest, but missing");
+        assert_eq!(
+            report.untracked_files,
+            vec![path.join("sstable").join("1_0.ss")]
+        );
+    }
+
+    #[test]
+    fn checkpoint() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("checkpoint");
+        let db = DB::open(&path, options_in(&env)).unwrap();
+        db.set("a".to_owned(), "1".to_owned()).unwrap();
+        db.flush(&FlushOptions::default()).unwrap();
+        db.set("b".to_owned(), "2".to_owned()).unwrap();
+
+        let checkpoint_path = PathBuf::from("checkpoint_copy");
+        db.checkpoint(&checkpoint_path).unwrap();
+        assert!(matches!(
+            db.checkpoint(&checkpoint_path),
+            Err(Error::InvalidArgument(_))
+        ));
+        db.set("c".to_owned(), "3".to_owned()).unwrap();
+        db.flush(&FlushOptions::default()).unwrap();
         db.compact(0).unwrap();
+        db.close().unwrap();
+
+        let copy = DB::open(&checkpoint_path, options_in(&env)).unwrap();
+        assert_eq!(
+            copy.scan(..).unwrap(),
+            vec![
+                ("a".to_owned(), "1".to_owned()),
+                ("b".to_owned(), "2".to_owned())
+            ]
+        );
+        assert!(copy.verify(&VerifyOptions::default()).unwrap().is_ok());
+    }
+
+    fn external_file(env: &MemEnv, name: &str, entries: &[(&str, Option<&str>)]) -> PathBuf {
+        let mut writer = SstFileWriter::new(TableOptions::default());
+        for (key, value) in entries {
+            match value {
+                Some(value) => writer.put(key.to_string(), value.to_string()).unwrap(),
+                None => writer.delete(key.to_string()).unwrap(),
+            }
+        }
+        let path = PathBuf::from("external").join(name);
+        writer.finish(env, &path).unwrap();
+        path
+    }
+
+    #[test]
+    fn ingest_external_files() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("ingest_external_files");
+        let db = DB::open(&path, options_in(&env)).unwrap();
+        db.set("b".to_owned(), "old".to_owned()).unwrap();
+        db.set("c".to_owned(), "old".to_owned()).unwrap();
+        db.flush(&FlushOptions::default()).unwrap();
+        db.set("x".to_owned(), "old".to_owned()).unwrap();
+
+        let files = [
+            external_file(&env, "1.sst", &[("a", Some("1")), ("b", Some("1"))]),
+            external_file(&env, "2.sst", &[("c", None), ("d", Some("2"))]),
+            external_file(&env, "3.sst", &[("e", Some("3"))]),
+            external_file(&env, "4.sst", &[("x", Some("4"))]),
+        ];
+        db.ingest_external_files(&files).unwrap();
+        for (key, value) in [
+            ("a", Some("1")),
+            ("b", Some("1")),
+            ("c", None),
+            ("x", Some("4")),
+        ] {
+            assert_eq!(db.get(&key.to_owned()).unwrap().as_deref(), value);
+        }
+
+        // the files that overlap the old SSTable go to level 0, the others to level 1. The
+        // memtable holding x was flushed first
+        let sstables = db.shared.state.read().unwrap().sstables.clone();
+        let levels = sstables
+            .iter()
+            .map(|path| SSTable::level_from_path(path).unwrap())
+            .collect_vec();
+        assert_eq!(levels, vec![0, 0, 0, 0, 1, 0]);
+        let properties = db.table_properties().unwrap();
+        assert_eq!(properties[2].1.min_sequence, properties[5].1.max_sequence);
+        assert!(properties[2].1.min_sequence > properties[1].1.max_sequence);
+
+        // the files are linked as they are, and read with the sequence kept in the manifest
+        assert_eq!(
+            env.read(&sstables[2]).unwrap(),
+            env.read(&files[0]).unwrap()
+        );
+        db.close().unwrap();
+        let db = DB::open(&path, options_in(&env)).unwrap();
+        assert_eq!(db.get(&"e".to_owned()).unwrap(), Some("3".to_owned()));
+        assert_eq!(db.table_properties().unwrap(), properties);
+        db.checkpoint(Path::new("ingest_checkpoint")).unwrap();
+        let checkpoint = DB::open(Path::new("ingest_checkpoint"), options_in(&env)).unwrap();
+        let sequences = |properties: Vec<(PathBuf, TableProperties)>| {
+            properties
+                .into_iter()
+                .map(|(_, properties)| properties.min_sequence)
+                .collect_vec()
+        };
+        assert_eq!(
+            sequences(checkpoint.table_properties().unwrap()),
+            sequences(properties)
+        );
+        assert!(db.verify(&VerifyOptions::default()).unwrap().is_ok());
+    }
+
+    #[test]
+    fn ingest_invalid_files() {
+        let env = Arc::new(MemEnv::new());
+        let db = DB::open(Path::new("ingest_invalid_files"), options_in(&env)).unwrap();
+        let overlapping = [
+            external_file(&env, "1.sst", &[("a", Some("1")), ("c", Some("1"))]),
+            external_file(&env, "2.sst", &[("b", Some("2"))]),
+        ];
+        let err = db.ingest_external_files(&overlapping).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid argument: external/1.sst and external/2.sst overlap"
+        );
+
+        let unsorted = PathBuf::from("external/unsorted.sst");
+        let records = vec![
+            Record::new("b".to_owned(), "1".to_owned()),
+            Record::new("a".to_owned(), "1".to_owned()),
+        ];
+        env.create(&unsorted)
+            .unwrap()
+            .append(&SSTable::from_records(records).to_bytes())
+            .unwrap();
+        let err = db.ingest_external_files(&[unsorted]).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("key \"a\" does not sort after \"b\""));
+
+        assert!(db
+            .ingest_external_files(&[PathBuf::from("missing.sst")])
+            .is_err());
+        assert!(db.scan(..).unwrap().is_empty());
+    }
+
+    #[test]
+    fn get_corrupt_sstable() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("get_corrupt_sstable");
+        let db = DB::open(&path, options_in(&env).memtable_bytes(1)).unwrap();
+        db.set("a".to_owned(), "b".to_owned()).unwrap();
+
+        db.wait_for_flushes().unwrap();
+        let sstable_path = db.shared.state.read().unwrap().sstables[0].clone();
+        env.create(&sstable_path)
+            .unwrap()
+            .append(b"not an sstable")
+            .unwrap();
+
+        assert!(matches!(
+            db.get(&"a".to_owned()),
+            Err(Error::Corruption { .. })
+        ));
+    }
+
+    #[test]
+    fn open_options() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("open_options");
+
+        assert!(matches!(
+            DB::open(&path, options_in(&env).create_if_missing(false)),
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            DB::open(&path, options_in(&env).memtable_bytes(0)),
+            Err(Error::InvalidArgument(_))
+        ));
+
+        let options = options_in(&env)
+            .memtable_bytes(1)
+            .compression(Compression::Snappy);
+        let db = DB::open(&path, options.clone()).unwrap();
+        db.set("a".to_owned(), "b".to_owned()).unwrap();
+        assert_eq!(db.get(&"a".to_owned()).unwrap(), Some("b".to_owned()));
+        db.wait_for_flushes().unwrap();
+        assert_eq!(db.table_properties().unwrap()[0].1.compression, "snappy");
+        assert_eq!(
+            Options::load(env.clone(), &path.join(OPTIONS_FILE)).unwrap(),
+            options
+        );
+
+        drop(db);
+        assert!(matches!(
+            DB::open(&path, options_in(&env).error_if_exists(true)),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn level0_compaction_trigger() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("level0_compaction_trigger");
+        let options = options_in(&env)
+            .memtable_bytes(1)
+            .compaction_policy(CompactionPolicy::Level0FileNum(3));
+        let db = DB::open(&path, options).unwrap();
+
+        db.set("a".to_owned(), "1".to_owned()).unwrap();
+        db.set("b".to_owned(), "2".to_owned()).unwrap();
+        db.wait_for_flushes().unwrap();
+        assert_eq!(db.shared.state.read().unwrap().sstables.len(), 2);
+        db.set("c".to_owned(), "3".to_owned()).unwrap();
+        db.wait_for_flushes().unwrap();
+        assert_eq!(db.shared.state.read().unwrap().sstables.len(), 1);
+        assert_eq!(db.get(&"a".to_owned()).unwrap(), Some("1".to_owned()));
+    }
+
+    #[test]
+    fn write_batch() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("write_batch");
+        let db = DB::open(&path, options_in(&env)).unwrap();
+        db.set("a".to_owned(), "0".to_owned()).unwrap();
+
+        let mut batch = WriteBatch::new();
+        batch.set("b".to_owned(), "1".to_owned());
+        batch.delete("a".to_owned());
+        db.write(batch, &WriteOptions::default().sync_mode(SyncMode::Fsync))
+            .unwrap();
+
+        assert_eq!(db.get(&"a".to_owned()).unwrap(), None);
+        assert_eq!(db.get(&"b".to_owned()).unwrap(), Some("1".to_owned()));
+    }
+
+    #[test]
+    fn concurrent_writers() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("concurrent_writers");
+        let db = DB::open(
+            &path,
+            options_in(&env)
+                .memtable_bytes(10_000)
+                .sync_mode(SyncMode::DataSync),
+        )
+        .unwrap();
+
+        std::thread::scope(|scope| {
+            for thread in 0..8 {
+                let db = &db;
+                scope.spawn(move || {
+                    for i in 0..100 {
+                        db.set(format!("{}-{}", thread, i), i.to_string()).unwrap();
+                    }
+                });
+            }
+        });
+
+        for thread in 0..8 {
+            for i in 0..100 {
+                assert_eq!(
+                    db.get(&format!("{}-{}", thread, i)).unwrap(),
+                    Some(i.to_string())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn flush_removes_wal() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("flush_removes_wal");
+        let db = DB::open(&path, options_in(&env).memtable_bytes(1)).unwrap();
+        db.set("a".to_owned(), "b".to_owned()).unwrap();
+        db.set("c".to_owned(), "d".to_owned()).unwrap();
+
+        db.wait_for_flushes().unwrap();
+        assert_eq!(
+            list_files(env.as_ref(), &path.join("wal"), "wal")
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(db.shared.state.read().unwrap().sstables.len(), 2);
+    }
+
+    #[test]
+    fn file_numbers() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("file_numbers");
+        let db = DB::open(&path, options_in(&env).memtable_bytes(1)).unwrap();
+        for key in ["a", "b", "c"] {
+            db.set(key.to_owned(), key.to_owned()).unwrap();
+        }
+        db.wait_for_flushes().unwrap();
+        let manifest = db.shared.state.read().unwrap().manifest();
+        let numbers = manifest
+            .sstables
+            .iter()
+            .map(|file_path| file_number(file_path).unwrap())
+            .collect_vec();
+        assert_eq!(numbers.len(), 3);
+        assert!(numbers.windows(2).all(|pair| pair[0] < pair[1]));
+        let wal_number = file_number(&manifest.wals[0]).unwrap();
+        assert!(!numbers.contains(&wal_number));
+        assert!(manifest.next_file_number > numbers.into_iter().max().unwrap().max(wal_number));
+        db.close().unwrap();
+
+        // numbers already on disk are not handed out again, even by a file the manifest does
+        // not know about
+        let leftover = path.join("sstable").join("1000_0.ss");
+        env.create(&leftover).unwrap();
+        let db = DB::open(&path, options_in(&env)).unwrap();
+        let wal_path = db.shared.state.read().unwrap().wal_path.clone().unwrap();
+        assert!(file_number(&wal_path).unwrap() > 1000);
+        assert!(!env.exists(&leftover));
+    }
+
+    #[test]
+    fn open_removes_temp_files() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("open_removes_temp_files");
+        env.create_dir_all(&path.join("sstable")).unwrap();
+        let temp_sstable = path.join("sstable").join("1_0.ss.tmp");
+        env.create(&temp_sstable)
+            .unwrap()
+            .append(b"partially written")
+            .unwrap();
+
+        let db = DB::open(&path, options_in(&env)).unwrap();
+        assert!(!env.exists(&temp_sstable));
+        assert!(db.shared.state.read().unwrap().sstables.is_empty());
+    }
+
+    #[test]
+    fn get_deleted_from_sstable() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("get_deleted_from_sstable");
+        let db = DB::open(&path, options_in(&env).memtable_bytes(1)).unwrap();
+        db.set("a".to_owned(), "b".to_owned()).unwrap();
+        db.wait_for_flushes().unwrap();
+
+        // the tombstone in the memtable hides the value in the SSTable
+        db.delete("a".to_owned()).unwrap();
+        assert_eq!(db.get(&"a".to_owned()).unwrap(), None);
+        db.wait_for_flushes().unwrap();
+        assert_eq!(db.get(&"a".to_owned()).unwrap(), None);
+    }
+
+    #[test]
+    fn write_buffer_stalls_writes() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("write_buffer_stalls_writes");
+        let options = options_in(&env)
+            .memtable_bytes(2_000)
+            .write_buffer_bytes(4_000);
+        let db = DB::open(&path, options).unwrap();
+
+        for i in 0..1000 {
+            db.set(format!("a{}", i), i.to_string()).unwrap();
+            assert!(db.shared.state.read().unwrap().memory_usage() < 4_000 + 2_000);
+        }
+        db.wait_for_flushes().unwrap();
+        for i in 0..1000 {
+            assert_eq!(db.get(&format!("a{}", i)).unwrap(), Some(i.to_string()));
+        }
+    }
+
+    #[test]
+    fn flush() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("flush");
+        let db = DB::open(&path, options_in(&env)).unwrap();
+        db.set("a".to_owned(), "b".to_owned()).unwrap();
+
+        db.flush(&FlushOptions::default()).unwrap();
+        assert!(db.shared.state.read().unwrap().mem_table.is_empty());
+        assert_eq!(db.shared.state.read().unwrap().sstables.len(), 1);
+        assert_eq!(db.get(&"a".to_owned()).unwrap(), Some("b".to_owned()));
+
+        // nothing to flush
+        db.flush(&FlushOptions::default().wait(false)).unwrap();
+        db.wait_for_flushes().unwrap();
+        assert_eq!(db.shared.state.read().unwrap().sstables.len(), 1);
+    }
 
-        assert_eq!(db.get(&"a".to_string()), Some("0".to_string()));
-        assert_eq!(db.get(&"b1".to_string()), Some("1".to_string()));
-        assert_eq!(db.get(&"c".to_string()), None);
-        assert_eq!(db.get(&"d1".to_string()), Some("1".to_string()));
+    #[test]
+    fn close() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("close");
+        let db = DB::open(&path, options_in(&env)).unwrap();
+        db.set("a".to_owned(), "b".to_owned()).unwrap();
+        db.close().unwrap();
+
+        // the data is in an SSTable and no WAL needs to be replayed
+        assert!(list_files(env.as_ref(), &path.join("wal"), "wal")
+            .unwrap()
+            .is_empty());
+        let db = DB::open(&path, options_in(&env)).unwrap();
+        assert_eq!(db.get(&"a".to_owned()).unwrap(), Some("b".to_owned()));
+
+        // dropping shuts down the same way
+        db.set("c".to_owned(), "d".to_owned()).unwrap();
+        drop(db);
+        assert!(list_files(env.as_ref(), &path.join("wal"), "wal")
+            .unwrap()
+            .is_empty());
+        let db = DB::open(&path, options_in(&env)).unwrap();
+        assert_eq!(db.get(&"c".to_owned()).unwrap(), Some("d".to_owned()));
+    }
+
+    #[test]
+    fn lock() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("lock");
+        let db = DB::open(&path, options_in(&env)).unwrap();
+        assert!(matches!(
+            DB::open(&path, options_in(&env)),
+            Err(Error::Busy(_))
+        ));
+
+        db.close().unwrap();
+        assert!(DB::open(&path, options_in(&env)).is_ok());
+    }
+
+    #[test]
+    fn open_read_only() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("open_read_only");
+        let db = DB::open(&path, options_in(&env)).unwrap();
+        db.set("a".to_owned(), "1".to_owned()).unwrap();
+        db.flush(&FlushOptions::default()).unwrap();
+        db.set("b".to_owned(), "2".to_owned()).unwrap();
+        db.delete("a".to_owned()).unwrap();
+
+        let files = |path: &Path| {
+            [path.to_path_buf(), path.join("sstable"), path.join("wal")]
+                .iter()
+                .flat_map(|dir| env.list(dir).unwrap())
+                .collect_vec()
+        };
+        let files_before = files(&path);
+
+        // does not need the lock held by `db`
+        let snapshot = DB::open_read_only(&path, &options_in(&env), false).unwrap();
+        assert_eq!(snapshot.get(&"a".to_owned()).unwrap(), Some("1".to_owned()));
+        assert_eq!(snapshot.get(&"b".to_owned()).unwrap(), None);
+
+        let replayed = DB::open_read_only(&path, &options_in(&env), true).unwrap();
+        assert_eq!(replayed.get(&"a".to_owned()).unwrap(), None);
+        assert_eq!(replayed.get(&"b".to_owned()).unwrap(), Some("2".to_owned()));
+
+        assert!(matches!(
+            replayed.set("c".to_owned(), "3".to_owned()),
+            Err(Error::NotSupported(_))
+        ));
+        assert!(matches!(
+            replayed.flush(&FlushOptions::default()),
+            Err(Error::NotSupported(_))
+        ));
+        assert!(matches!(replayed.compact(0), Err(Error::NotSupported(_))));
+        drop(snapshot);
+        replayed.close().unwrap();
+        assert_eq!(files(&path), files_before);
+    }
+
+    #[test]
+    fn secondary() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("secondary");
+        let db = DB::open(&path, options_in(&env)).unwrap();
+        db.set("a".to_owned(), "1".to_owned()).unwrap();
+        db.flush(&FlushOptions::default()).unwrap();
+
+        let secondary = DB::open_as_secondary(&path, &options_in(&env)).unwrap();
+        assert_eq!(
+            secondary.get(&"a".to_owned()).unwrap(),
+            Some("1".to_owned())
+        );
+
+        db.set("b".to_owned(), "2".to_owned()).unwrap();
+        assert_eq!(secondary.get(&"b".to_owned()).unwrap(), None);
+        secondary.try_catch_up_with_primary().unwrap();
+        assert_eq!(
+            secondary.get(&"b".to_owned()).unwrap(),
+            Some("2".to_owned())
+        );
+
+        // the WAL holding b is flushed and removed
+        db.flush(&FlushOptions::default()).unwrap();
+        db.delete("a".to_owned()).unwrap();
+        db.set("c".to_owned(), "3".to_owned()).unwrap();
+        secondary.try_catch_up_with_primary().unwrap();
+        assert_eq!(secondary.get(&"a".to_owned()).unwrap(), None);
+        assert_eq!(
+            secondary.get(&"b".to_owned()).unwrap(),
+            Some("2".to_owned())
+        );
+        assert_eq!(
+            secondary.get(&"c".to_owned()).unwrap(),
+            Some("3".to_owned())
+        );
+
+        assert!(matches!(
+            secondary.set("d".to_owned(), "4".to_owned()),
+            Err(Error::NotSupported(_))
+        ));
+        let read_only = DB::open_read_only(&path, &options_in(&env), true).unwrap();
+        assert!(matches!(
+            read_only.try_catch_up_with_primary(),
+            Err(Error::NotSupported(_))
+        ));
+
+        drop(read_only);
+        drop(secondary);
+    }
+
+    #[test]
+    fn open_removes_sstables_missing_from_manifest() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("open_removes_sstables_missing_from_manifest");
+        let db = DB::open(&path, options_in(&env)).unwrap();
+        db.set("a".to_owned(), "1".to_owned()).unwrap();
+        db.close().unwrap();
 
-        assert_eq!(db.sstables.len(), 1);
+        // as if a flush crashed before it got to update the manifest
+        let orphan = SSTable::from_records(vec![Record::new("a".to_owned(), "2".to_owned())])
+            .write(env.as_ref(), &path.join("sstable"))
+            .unwrap();
 
-        remove_dir_all(path).unwrap();
+        let db = DB::open(&path, options_in(&env)).unwrap();
+        assert!(!env.exists(&orphan));
+        assert_eq!(db.get(&"a".to_owned()).unwrap(), Some("1".to_owned()));
     }
 }
@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::env::Env;
+use crate::error::{Error, Result};
+use crate::storage::fs::write_atomic;
+
+/// Name of the manifest, in the root of the database directory
+pub const MANIFEST_FILE: &str = "MANIFEST";
+
+/// The files that make up the current state of the database. SSTables that are on disk but not
+/// in the manifest are leftovers of a flush or compaction that did not finish.
+///
+/// The manifest is rewritten as a whole every time the set of files changes, with paths stored
+/// relative to the database directory, one `kind=path` per line. Ingested SSTables are followed
+/// by `global_sequence=path <sequence>`, and the manifest ends with `next_file=<number>`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Manifest {
+    /// Live SSTables, in the order they are searched last to first
+    pub sstables: Vec<PathBuf>,
+    /// WALs holding records that are not in an SSTable yet, oldest first
+    pub wals: Vec<PathBuf>,
+    /// Greater than the number of every file the database has created, so names are never
+    /// reused. 0 in manifests written before files were numbered
+    pub next_file_number: u64,
+    /// Timestamp the records of each ingested SSTable are read with, see
+    /// `SSTable::set_global_sequence`
+    pub global_sequences: BTreeMap<PathBuf, u128>,
+}
+
+impl Manifest {
+    pub fn write(&self, env: &dyn Env, root_path: &Path) -> Result<()> {
+        let mut contents = "# rust_kv_store manifest\n".to_owned();
+        for (kind, paths) in [("sstable", &self.sstables), ("wal", &self.wals)] {
+            for path in paths {
+                let relative_path = path.strip_prefix(root_path).unwrap_or(path);
+                contents += &format!("{}={}\n", kind, relative_path.display());
+            }
+        }
+        for (path, sequence) in self.global_sequences.iter() {
+            let relative_path = path.strip_prefix(root_path).unwrap_or(path);
+            contents += &format!("global_sequence={} {}\n", relative_path.display(), sequence);
+        }
+        contents += &format!("next_file={}\n", self.next_file_number);
+        write_atomic(env, &root_path.join(MANIFEST_FILE), contents.as_bytes())
+    }
+
+    /// Reads the manifest of the database at `root_path`, or returns `None` for databases
+    /// created before the manifest was introduced
+    pub fn load(env: &dyn Env, root_path: &Path) -> Result<Option<Manifest>> {
+        let path = root_path.join(MANIFEST_FILE);
+        if !env.exists(&path) {
+            return Ok(None);
+        }
+
+        let mut manifest = Manifest::default();
+        let contents = String::from_utf8(env.read(&path)?)
+            .map_err(|_| Error::corruption(&path, None, "manifest is not valid UTF-8"))?;
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid =
+                || Error::corruption(&path, None, format!("invalid manifest entry '{}'", line));
+            match line.split_once('=').ok_or_else(invalid)? {
+                ("sstable", file) => manifest.sstables.push(root_path.join(file)),
+                ("wal", file) => manifest.wals.push(root_path.join(file)),
+                ("global_sequence", entry) => {
+                    let (file, sequence) = entry.rsplit_once(' ').ok_or_else(invalid)?;
+                    let sequence = sequence.parse().map_err(|_| invalid())?;
+                    manifest
+                        .global_sequences
+                        .insert(root_path.join(file), sequence);
+                }
+                ("next_file", number) => {
+                    manifest.next_file_number = number.parse().map_err(|_| invalid())?
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(Some(manifest))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::env::MemEnv;
+
+    #[test]
+    fn write_and_load() {
+        let env = MemEnv::new();
+        let path = PathBuf::from("write_and_load");
+        env.create_dir_all(&path).unwrap();
+        assert_eq!(Manifest::load(&env, &path).unwrap(), None);
+
+        let manifest = Manifest {
+            sstables: vec![path.join("sstable").join("1_0.ss")],
+            wals: vec![path.join("wal").join("2.wal")],
+            next_file_number: 3,
+            global_sequences: BTreeMap::from([(path.join("sstable").join("1_0.ss"), 5)]),
+        };
+        manifest.write(&env, &path).unwrap();
+        assert_eq!(Manifest::load(&env, &path).unwrap(), Some(manifest));
+
+        env.create(&path.join(MANIFEST_FILE))
+            .unwrap()
+            .append(b"table=1_0.ss")
+            .unwrap();
+        assert!(matches!(
+            Manifest::load(&env, &path),
+            Err(Error::Corruption { .. })
+        ));
+    }
+}
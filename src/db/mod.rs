@@ -1 +1,13 @@
+#![allow(clippy::module_inception)]
+pub mod backup;
+#[cfg(test)]
+mod crash_test;
 pub mod db;
+pub mod manifest;
+pub mod options;
+pub mod repair;
+#[cfg(test)]
+mod sim_test;
+mod table_cache;
+pub mod verify;
+pub mod write_batch;
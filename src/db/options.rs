@@ -0,0 +1,380 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::env::{default_env, Env};
+use crate::error::{Error, Result};
+use crate::storage::compression::Compression;
+use crate::storage::fs::write_atomic;
+use crate::storage::sstable::TableOptions;
+use crate::storage::wal::SyncMode;
+
+/// Name of the file the options are persisted to, in the root of the database directory
+pub const OPTIONS_FILE: &str = "OPTIONS";
+const MAX_BLOCK_SIZE: usize = 64 << 20;
+
+/// When SSTables are compacted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompactionPolicy {
+    /// Only when `DB::compact` is called
+    #[default]
+    Manual,
+    /// Compact level 0 into level 1 once a flush leaves this many tables at level 0
+    Level0FileNum(usize),
+}
+
+/// Settings for opening a `DB`, built by chaining setters onto `Options::default()`
+///
+/// ```
+/// use rust_kv_store::db::options::Options;
+/// use rust_kv_store::storage::compression::Compression;
+///
+/// let options = Options::default()
+///     .memtable_bytes(8 << 20)
+///     .compression(Compression::Snappy)
+///     .create_if_missing(true);
+/// assert!(options.validate().is_ok());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub(crate) memtable_bytes: usize,
+    pub(crate) write_buffer_bytes: usize,
+    pub(crate) block_size: usize,
+    pub(crate) compression: Compression,
+    pub(crate) sync_mode: SyncMode,
+    pub(crate) table_cache_size: usize,
+    pub(crate) block_cache_bytes: usize,
+    pub(crate) compaction_policy: CompactionPolicy,
+    pub(crate) create_if_missing: bool,
+    pub(crate) error_if_exists: bool,
+    pub(crate) env: Arc<dyn Env>,
+}
+
+// the environment is not compared, it is where the options apply rather than what they are
+impl PartialEq for Options {
+    fn eq(&self, other: &Self) -> bool {
+        self.memtable_bytes == other.memtable_bytes
+            && self.write_buffer_bytes == other.write_buffer_bytes
+            && self.block_size == other.block_size
+            && self.compression == other.compression
+            && self.sync_mode == other.sync_mode
+            && self.table_cache_size == other.table_cache_size
+            && self.block_cache_bytes == other.block_cache_bytes
+            && self.compaction_policy == other.compaction_policy
+            && self.create_if_missing == other.create_if_missing
+            && self.error_if_exists == other.error_if_exists
+    }
+}
+
+impl Eq for Options {}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            memtable_bytes: 4 << 20,
+            write_buffer_bytes: 16 << 20,
+            block_size: TableOptions::default().block_size,
+            compression: Compression::None,
+            sync_mode: SyncMode::Flush,
+            table_cache_size: 1000,
+            block_cache_bytes: 8 << 20,
+            compaction_policy: CompactionPolicy::Manual,
+            create_if_missing: true,
+            error_if_exists: false,
+            env: default_env(),
+        }
+    }
+}
+
+impl Options {
+    /// Approximate memory the memtable may use before it is frozen and flushed to an SSTable
+    /// in the background
+    pub fn memtable_bytes(mut self, memtable_bytes: usize) -> Self {
+        self.memtable_bytes = memtable_bytes;
+        self
+    }
+
+    /// Memory shared by the active memtable and the frozen memtables waiting to be flushed.
+    /// Writes wait for flushes to finish once it is used up
+    pub fn write_buffer_bytes(mut self, write_buffer_bytes: usize) -> Self {
+        self.write_buffer_bytes = write_buffer_bytes;
+        self
+    }
+
+    /// Target size of SSTable data blocks before compression
+    pub fn block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    /// Codec used for the data blocks of new SSTables
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// How far writes are pushed towards the disk before they are acknowledged, unless the
+    /// write asks for something else through `WriteOptions`
+    pub fn sync_mode(mut self, sync_mode: SyncMode) -> Self {
+        self.sync_mode = sync_mode;
+        self
+    }
+
+    /// Number of SSTables whose index is kept in memory. 0 disables the cache
+    pub fn table_cache_size(mut self, table_cache_size: usize) -> Self {
+        self.table_cache_size = table_cache_size;
+        self
+    }
+
+    /// Bytes of decoded data blocks kept in memory. 0 disables the cache
+    pub fn block_cache_bytes(mut self, block_cache_bytes: usize) -> Self {
+        self.block_cache_bytes = block_cache_bytes;
+        self
+    }
+
+    pub fn compaction_policy(mut self, compaction_policy: CompactionPolicy) -> Self {
+        self.compaction_policy = compaction_policy;
+        self
+    }
+
+    /// Create the database if the directory does not contain one
+    pub fn create_if_missing(mut self, create_if_missing: bool) -> Self {
+        self.create_if_missing = create_if_missing;
+        self
+    }
+
+    /// Fail to open if the directory already contains a database
+    pub fn error_if_exists(mut self, error_if_exists: bool) -> Self {
+        self.error_if_exists = error_if_exists;
+        self
+    }
+
+    /// Where the database files are stored, the local filesystem unless set
+    pub fn env(mut self, env: Arc<dyn Env>) -> Self {
+        self.env = env;
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.memtable_bytes == 0 {
+            return Err(Error::InvalidArgument(
+                "memtable_bytes must be greater than 0".to_owned(),
+            ));
+        }
+        if self.write_buffer_bytes < self.memtable_bytes {
+            return Err(Error::InvalidArgument(format!(
+                "write_buffer_bytes ({}) must be at least memtable_bytes ({})",
+                self.write_buffer_bytes, self.memtable_bytes
+            )));
+        }
+        if self.block_size == 0 || self.block_size > MAX_BLOCK_SIZE {
+            return Err(Error::InvalidArgument(format!(
+                "block_size must be between 1 and {} bytes, got {}",
+                MAX_BLOCK_SIZE, self.block_size
+            )));
+        }
+        if let CompactionPolicy::Level0FileNum(file_num) = self.compaction_policy {
+            if file_num < 2 {
+                return Err(Error::InvalidArgument(format!(
+                    "level 0 compaction trigger must be at least 2 files, got {}",
+                    file_num
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// How the database builds SSTables, e.g. for an `SstFileWriter`
+    pub fn table_options(&self) -> TableOptions {
+        TableOptions {
+            block_size: self.block_size,
+            compression: self.compression,
+        }
+    }
+
+    /// Writes the options that affect how data is stored to `path`, one `name=value` per line
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let compaction_policy = match self.compaction_policy {
+            CompactionPolicy::Manual => "manual".to_owned(),
+            CompactionPolicy::Level0FileNum(file_num) => format!("level0_file_num:{}", file_num),
+        };
+
+        let contents = format!(
+            "# rust_kv_store options\n\
+             memtable_bytes={}\n\
+             write_buffer_bytes={}\n\
+             block_size={}\n\
+             compression={}\n\
+             sync_mode={}\n\
+             table_cache_size={}\n\
+             block_cache_bytes={}\n\
+             compaction_policy={}\n",
+            self.memtable_bytes,
+            self.write_buffer_bytes,
+            self.block_size,
+            self.compression,
+            self.sync_mode,
+            self.table_cache_size,
+            self.block_cache_bytes,
+            compaction_policy
+        );
+        write_atomic(self.env.as_ref(), path, contents.as_bytes())
+    }
+
+    /// Reads options persisted with `write` from `env`. Options missing from the file keep
+    /// their defaults
+    pub fn load(env: Arc<dyn Env>, path: &Path) -> Result<Options> {
+        let contents = String::from_utf8(env.read(path)?)
+            .map_err(|_| Error::corruption(path, None, "options file is not valid UTF-8"))?;
+        let mut options = Options::default().env(env);
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || Error::corruption(path, None, format!("invalid option '{}'", line));
+            let (name, value) = line.split_once('=').ok_or_else(invalid)?;
+            let parse_usize = |value: &str| value.parse::<usize>().map_err(|_| invalid());
+
+            match name {
+                "memtable_bytes" => options.memtable_bytes = parse_usize(value)?,
+                "write_buffer_bytes" => options.write_buffer_bytes = parse_usize(value)?,
+                "block_size" => options.block_size = parse_usize(value)?,
+                "compression" => options.compression = Compression::from_name(value)?,
+                "sync_mode" => options.sync_mode = SyncMode::from_name(value)?,
+                "table_cache_size" => options.table_cache_size = parse_usize(value)?,
+                "block_cache_bytes" => options.block_cache_bytes = parse_usize(value)?,
+                "compaction_policy" => {
+                    options.compaction_policy = match value.split_once(':') {
+                        None if value == "manual" => CompactionPolicy::Manual,
+                        Some(("level0_file_num", file_num)) => {
+                            CompactionPolicy::Level0FileNum(parse_usize(file_num)?)
+                        }
+                        _ => return Err(invalid()),
+                    }
+                }
+                // ignore options written by newer versions
+                _ => {}
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Settings for a single call to `DB::write`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WriteOptions {
+    pub(crate) sync_mode: Option<SyncMode>,
+}
+
+impl WriteOptions {
+    /// Overrides `Options::sync_mode` for this write
+    pub fn sync_mode(mut self, sync_mode: SyncMode) -> Self {
+        self.sync_mode = Some(sync_mode);
+        self
+    }
+}
+
+/// Settings for `DB::flush`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlushOptions {
+    pub(crate) wait: bool,
+}
+
+impl Default for FlushOptions {
+    fn default() -> Self {
+        FlushOptions { wait: true }
+    }
+}
+
+impl FlushOptions {
+    /// Return only once the memtable has been written to an SSTable
+    pub fn wait(mut self, wait: bool) -> Self {
+        self.wait = wait;
+        self
+    }
+}
+
+/// Settings for `DB::verify`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyOptions {
+    pub(crate) verify_checksums: bool,
+}
+
+impl Default for VerifyOptions {
+    fn default() -> Self {
+        VerifyOptions {
+            verify_checksums: true,
+        }
+    }
+}
+
+impl VerifyOptions {
+    /// Read every data block, checking its checksum and the order of its keys. Without it only
+    /// the footer, index and properties of each SSTable are read
+    pub fn verify_checksums(mut self, verify_checksums: bool) -> Self {
+        self.verify_checksums = verify_checksums;
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::env::MemEnv;
+
+    #[test]
+    fn validate() {
+        assert!(Options::default().validate().is_ok());
+        assert!(matches!(
+            Options::default().memtable_bytes(0).validate(),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            Options::default()
+                .memtable_bytes(2000)
+                .write_buffer_bytes(1000)
+                .validate(),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            Options::default().block_size(0).validate(),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            Options::default()
+                .compaction_policy(CompactionPolicy::Level0FileNum(1))
+                .validate(),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn write_and_load() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("write_and_load");
+        env.create_dir_all(&path).unwrap();
+
+        let options = Options::default()
+            .env(env.clone())
+            .memtable_bytes(1000)
+            .write_buffer_bytes(3000)
+            .block_size(100)
+            .compression(Compression::Snappy)
+            .sync_mode(SyncMode::Periodic(Duration::from_millis(250)))
+            .table_cache_size(10)
+            .block_cache_bytes(0)
+            .compaction_policy(CompactionPolicy::Level0FileNum(4));
+        options.write(&path.join(OPTIONS_FILE)).unwrap();
+
+        assert_eq!(
+            Options::load(env, &path.join(OPTIONS_FILE)).unwrap(),
+            options
+        );
+    }
+}
@@ -0,0 +1,394 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::db::LOCK_FILE;
+use super::manifest::Manifest;
+use super::options::Options;
+use crate::env::Env;
+use crate::error::{Error, Result};
+use crate::storage::fs::{file_number, list_files, remove_temp_files, write_atomic};
+use crate::storage::memtable::MemTable;
+use crate::storage::record::Record;
+use crate::storage::sstable::SSTable;
+use crate::storage::wal::{WalEnd, WriteAheadLogIter};
+
+/// Directory in the database that `DB::repair` moves files it could not fully read to
+pub const LOST_DIR: &str = "lost";
+
+/// What `DB::repair` did
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RepairReport {
+    /// SSTables in the rebuilt manifest, oldest first
+    pub sstables: Vec<PathBuf>,
+    /// SSTables that had unreadable blocks, and were rewritten with the records of the others
+    pub rewritten: Vec<PathBuf>,
+    /// Records recovered from WALs, which are now in an SSTable
+    pub wal_records: usize,
+    /// Files moved to the lost directory, and why
+    pub lost_files: Vec<(PathBuf, String)>,
+    /// Data blocks that could not be read from SSTables
+    pub lost_blocks: usize,
+    /// Records those blocks held, as far as the properties of their tables tell
+    pub lost_records: u64,
+}
+
+impl fmt::Display for RepairReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for path in &self.rewritten {
+            writeln!(
+                f,
+                "rewrote {} without its unreadable blocks",
+                path.display()
+            )?;
+        }
+        for (path, reason) in &self.lost_files {
+            writeln!(f, "moved {} to {}: {}", path.display(), LOST_DIR, reason)?;
+        }
+        write!(
+            f,
+            "{} SSTables kept, {} records recovered from WALs, {} blocks and about {} records lost",
+            self.sstables.len(),
+            self.wal_records,
+            self.lost_blocks,
+            self.lost_records
+        )
+    }
+}
+
+/// See `DB::repair`
+pub(crate) fn repair(path: &Path, options: &Options) -> Result<RepairReport> {
+    let env = options.env.as_ref();
+    if !env.exists(path) {
+        return Err(Error::NotFound(format!(
+            "database {} does not exist",
+            path.display()
+        )));
+    }
+    let lock_file = env.lock(&path.join(LOCK_FILE))?;
+    let sstable_dir = path.join("sstable");
+    let wal_dir = path.join("wal");
+    remove_temp_files(env, path)?;
+    remove_temp_files(env, &sstable_dir)?;
+
+    let mut report = RepairReport::default();
+    let mut sstables = list_files(env, &sstable_dir, "ss")?;
+    let mut wals = list_files(env, &wal_dir, "wal")?;
+    // past the numbers of every file there is or was, as far as the old manifest tells
+    let old_manifest = Manifest::load(env, path).ok().flatten();
+    let next_file_number = sstables
+        .iter()
+        .chain(wals.iter())
+        .filter_map(|file_path| file_number(file_path))
+        .map(|number| number + 1)
+        .chain(
+            old_manifest
+                .as_ref()
+                .map(|manifest| manifest.next_file_number),
+        )
+        .max()
+        .unwrap_or(1);
+    // without a manifest to go by, the numbers in their names order the tables and WALs
+    sstables.sort_by_key(|sstable_path| (file_number(sstable_path), sstable_path.clone()));
+    wals.sort_by_key(|wal_path| (file_number(wal_path), wal_path.clone()));
+    for sstable_path in sstables {
+        if salvage_table(env, path, &sstable_path, options, &mut report)? {
+            report.sstables.push(sstable_path);
+        }
+    }
+
+    // WALs hold the newest records, so they go into a table that is searched first
+    let mut mem_table = MemTable::new();
+    for wal_path in wals.iter() {
+        let mut wal_iter = match WriteAheadLogIter::open(env, wal_path) {
+            Ok(wal_iter) => wal_iter,
+            Err(err @ Error::Io(_)) => return Err(err),
+            Err(err) => {
+                move_to_lost(env, path, wal_path, err.to_string(), &mut report)?;
+                continue;
+            }
+        };
+        for record in wal_iter.by_ref() {
+            mem_table.insert(record);
+            report.wal_records += 1;
+        }
+        match wal_iter.end() {
+            // a crash in the middle of an append leaves a truncated record behind, which
+            // was never acknowledged
+            Some(WalEnd::Eof | WalEnd::Truncated) | None => {}
+            Some(end) => {
+                let reason = format!("{} at offset {}", end, wal_iter.offset());
+                move_to_lost(env, path, wal_path, reason, &mut report)?;
+            }
+        }
+    }
+    if !mem_table.is_empty() {
+        let mut sstable =
+            SSTable::from_records_with_options(mem_table.to_records(), &options.table_options());
+        report
+            .sstables
+            .push(sstable.write_numbered(env, &sstable_dir, next_file_number)?);
+    }
+
+    // ingested tables that were kept are still read with the sequence they were given
+    let mut global_sequences = old_manifest
+        .map(|manifest| manifest.global_sequences)
+        .unwrap_or_default();
+    global_sequences.retain(|sstable_path, _| report.sstables.contains(sstable_path));
+    Manifest {
+        sstables: report.sstables.clone(),
+        wals: vec![],
+        next_file_number: next_file_number + 1,
+        global_sequences,
+    }
+    .write(env, path)?;
+    for wal_path in wals.iter().filter(|wal_path| env.exists(wal_path)) {
+        env.remove_file(wal_path)?;
+    }
+    if env.exists(&wal_dir) {
+        env.sync_dir(&wal_dir)?;
+    }
+
+    lock_file.unlock()?;
+    Ok(report)
+}
+
+/// Reads what it can of the SSTable at `sstable_path`. A table with unreadable data blocks is
+/// rewritten from the records of the other blocks, and a table whose footer, index or
+/// properties can not be read is moved to the lost directory. Returns whether the table is kept
+fn salvage_table(
+    env: &dyn Env,
+    root_path: &Path,
+    sstable_path: &Path,
+    options: &Options,
+    report: &mut RepairReport,
+) -> Result<bool> {
+    let table = match SSTable::from_disk(env, sstable_path) {
+        Ok(table) => table,
+        Err(err @ Error::Io(_)) => return Err(err),
+        Err(err) => {
+            move_to_lost(env, root_path, sstable_path, err.to_string(), report)?;
+            return Ok(false);
+        }
+    };
+
+    let mut records: Vec<Record> = vec![];
+    let mut lost_blocks = 0;
+    for (_, handle) in table.index_entries() {
+        match table.read_block(env, sstable_path, handle) {
+            Ok(block) => records.extend(block.records().iter().cloned()),
+            Err(err @ Error::Io(_)) => return Err(err),
+            Err(_) => lost_blocks += 1,
+        }
+    }
+    if lost_blocks == 0 {
+        return Ok(true);
+    }
+
+    report.lost_blocks += lost_blocks;
+    report.lost_records += table
+        .properties()
+        .num_entries
+        .saturating_sub(records.len() as u64);
+    let reason = format!("{} unreadable data blocks", lost_blocks);
+    move_to_lost(env, root_path, sstable_path, reason, report)?;
+    if records.is_empty() {
+        return Ok(false);
+    }
+
+    // keep the name, which orders the table among the others
+    let mut salvaged = SSTable::from_records_with_options(records, &options.table_options());
+    salvaged.level = table.level;
+    write_atomic(env, sstable_path, &salvaged.to_bytes())?;
+    report.rewritten.push(sstable_path.to_path_buf());
+    Ok(true)
+}
+
+fn move_to_lost(
+    env: &dyn Env,
+    root_path: &Path,
+    file_path: &Path,
+    reason: String,
+    report: &mut RepairReport,
+) -> Result<()> {
+    let lost_dir = root_path.join(LOST_DIR);
+    env.create_dir_all(&lost_dir)?;
+    let lost_path = lost_dir.join(file_path.file_name().unwrap());
+    env.rename(file_path, &lost_path)?;
+    env.sync_dir(&lost_dir)?;
+    if let Some(dir) = file_path.parent() {
+        env.sync_dir(dir)?;
+    }
+    report.lost_files.push((lost_path, reason));
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::db::db::DB;
+    use crate::db::options::FlushOptions;
+    use crate::env::{FaultInjectionEnv, MemEnv};
+    use crate::storage::wal::WriteAheadLog;
+
+    #[test]
+    fn repair_sstables() {
+        let env = Arc::new(FaultInjectionEnv::new(Arc::new(MemEnv::new())));
+        let path = PathBuf::from("repair_sstables");
+        let options = Options::default().env(env.clone()).block_size(40);
+        let db = DB::open(&path, options.clone()).unwrap();
+        for i in 0..10 {
+            db.set(format!("a{}", i), i.to_string()).unwrap();
+        }
+        db.flush(&FlushOptions::default()).unwrap();
+        db.set("b".to_owned(), "b".to_owned()).unwrap();
+        db.flush(&FlushOptions::default()).unwrap();
+        db.set("c".to_owned(), "c".to_owned()).unwrap();
+        db.close().unwrap();
+
+        let sstables = Manifest::load(env.as_ref(), &path)
+            .unwrap()
+            .unwrap()
+            .sstables;
+        assert_eq!(sstables.len(), 3);
+        env.corrupt(&sstables[0], 3, 1).unwrap();
+        env.create(&sstables[1])
+            .unwrap()
+            .append(b"not an sstable")
+            .unwrap();
+        env.remove_file(&path.join("MANIFEST")).unwrap();
+        let mut wal = WriteAheadLog::new(env.clone(), &path.join("wal")).unwrap();
+        wal.set("d".to_owned(), "d".to_owned()).unwrap();
+        wal.sync().unwrap();
+
+        let report = DB::repair(&path, &options).unwrap();
+        assert_eq!(report.rewritten, vec![sstables[0].clone()]);
+        assert_eq!(report.lost_blocks, 1);
+        assert!(report.lost_records > 0);
+        assert_eq!(report.wal_records, 1);
+        let lost = path.join(LOST_DIR);
+        let lost_files = report
+            .lost_files
+            .iter()
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lost_files,
+            vec![
+                lost.join(sstables[0].file_name().unwrap()),
+                lost.join(sstables[1].file_name().unwrap())
+            ]
+        );
+        assert_eq!(report.sstables.len(), 3);
+        assert_eq!(
+            report.sstables[..2],
+            [sstables[0].clone(), sstables[2].clone()]
+        );
+        assert!(report.to_string().ends_with(&format!(
+            "3 SSTables kept, 1 records recovered from WALs, 1 blocks and about {} records lost",
+            report.lost_records
+        )));
+
+        let db = DB::open(&path, options).unwrap();
+        assert_eq!(db.get(&"a0".to_owned()).unwrap(), None);
+        assert_eq!(db.get(&"a9".to_owned()).unwrap(), Some("9".to_owned()));
+        assert_eq!(db.get(&"b".to_owned()).unwrap(), None);
+        assert_eq!(db.get(&"c".to_owned()).unwrap(), Some("c".to_owned()));
+        assert_eq!(db.get(&"d".to_owned()).unwrap(), Some("d".to_owned()));
+        assert!(db.verify(&Default::default()).unwrap().is_ok());
+    }
+
+    #[test]
+    fn repair_wal() {
+        let env = Arc::new(FaultInjectionEnv::new(Arc::new(MemEnv::new())));
+        let path = PathBuf::from("repair_wal");
+        let options = Options::default().env(env.clone());
+        DB::open(&path, options.clone()).unwrap().close().unwrap();
+
+        let mut wal = WriteAheadLog::new(env.clone(), &path.join("wal")).unwrap();
+        wal.set("a".to_owned(), "a".to_owned()).unwrap();
+        wal.set("a".to_owned(), "b".to_owned()).unwrap();
+        wal.set("c".to_owned(), "c".to_owned()).unwrap();
+        wal.sync().unwrap();
+        // the value of the last record
+        let size = env.read(wal.path()).unwrap().len() as u64;
+        env.corrupt(wal.path(), size - 1, 1).unwrap();
+
+        let report = DB::repair(&path, &options).unwrap();
+        assert_eq!(report.wal_records, 2);
+        assert_eq!(report.lost_files.len(), 1);
+        assert_eq!(
+            report.lost_files[0].0,
+            path.join(LOST_DIR).join(wal.path().file_name().unwrap())
+        );
+        assert!(report.lost_files[0].1.contains("checksum mismatch"));
+        assert!(!env.exists(wal.path()));
+
+        let db = DB::open(&path, options).unwrap();
+        assert_eq!(db.get(&"a".to_owned()).unwrap(), Some("b".to_owned()));
+        assert_eq!(db.get(&"c".to_owned()).unwrap(), None);
+    }
+
+    #[test]
+    fn repair_wals_in_number_order() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("repair_wals_in_number_order");
+        let options = Options::default().env(env.clone());
+        DB::open(&path, options.clone()).unwrap().close().unwrap();
+
+        // 10.wal sorts before 9.wal by name, but holds the newer records
+        for (number, value) in [(9, "old"), (10, "new")] {
+            let mut wal = WriteAheadLog::create(env.clone(), &path.join("wal"), number).unwrap();
+            wal.set("a".to_owned(), value.to_owned()).unwrap();
+            wal.set(value.to_owned(), value.to_owned()).unwrap();
+            wal.sync().unwrap();
+        }
+
+        let report = DB::repair(&path, &options).unwrap();
+        assert_eq!(report.wal_records, 4);
+
+        let db = DB::open(&path, options).unwrap();
+        assert_eq!(db.get(&"a".to_owned()).unwrap(), Some("new".to_owned()));
+        assert_eq!(db.get(&"old".to_owned()).unwrap(), Some("old".to_owned()));
+    }
+
+    #[test]
+    fn repair_garbage_wal() {
+        let env = Arc::new(MemEnv::new());
+        let path = PathBuf::from("repair_garbage_wal");
+        let options = Options::default().env(env.clone());
+        let db = DB::open(&path, options.clone()).unwrap();
+        db.set("a".to_owned(), "a".to_owned()).unwrap();
+        db.close().unwrap();
+
+        // random bytes, without the header of a WAL
+        let mut bytes = vec![0; 5000];
+        StdRng::seed_from_u64(46).fill(&mut bytes[..]);
+        let garbage_path = path.join("wal").join("1.wal");
+        env.create(&garbage_path).unwrap().append(&bytes).unwrap();
+
+        let report = DB::repair(&path, &options).unwrap();
+        assert_eq!(report.wal_records, 0);
+        assert_eq!(report.lost_files.len(), 1);
+        assert_eq!(report.lost_files[0].0, path.join(LOST_DIR).join("1.wal"));
+        assert!(report.lost_files[0].1.contains("corrupt record"));
+        assert_eq!(env.read(&report.lost_files[0].0).unwrap(), bytes);
+        assert!(!env.exists(&garbage_path));
+
+        let db = DB::open(&path, options).unwrap();
+        assert_eq!(db.get(&"a".to_owned()).unwrap(), Some("a".to_owned()));
+    }
+
+    #[test]
+    fn repair_missing() {
+        let options = Options::default().env(Arc::new(MemEnv::new()));
+        assert!(matches!(
+            DB::repair(Path::new("repair_missing"), &options),
+            Err(Error::NotFound(_))
+        ));
+    }
+}
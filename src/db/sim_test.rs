@@ -0,0 +1,109 @@
+//! Runs randomized workloads against a database in a `SimEnv`, checking reads against a model,
+//! and checks that a run replays exactly from its seed
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::db::DB;
+use super::options::{CompactionPolicy, FlushOptions, Options};
+use crate::env::{Env, SimEnv};
+use crate::error::Result;
+
+const OPS: usize = 1_000;
+const KEYS: usize = 100;
+
+fn open(env: &SimEnv, path: &Path) -> Result<DB> {
+    let options = Options::default()
+        .env(Arc::new(env.clone()))
+        .memtable_bytes(2_000)
+        .write_buffer_bytes(6_000)
+        .compaction_policy(CompactionPolicy::Level0FileNum(3));
+    DB::open(path, options)
+}
+
+/// Runs a workload, and returns a trace of the results of every operation and the files left
+/// behind. Stops at the first error, which only happens if `io_error_probability` is set
+fn run(seed: u64, io_error_probability: f64) -> Vec<String> {
+    let env = SimEnv::new(seed).io_error_probability(io_error_probability);
+    let path = Path::new("sim");
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut model = BTreeMap::new();
+    let mut trace = vec![];
+
+    let mut db = open(&env, path);
+    for op in 0..OPS {
+        let current = match &db {
+            Ok(current) => current,
+            Err(err) => {
+                trace.push(format!("open: {}", err));
+                break;
+            }
+        };
+        let key = format!("key{}", rng.gen_range(0..KEYS));
+        let result = match rng.gen_range(0..100) {
+            0..=49 => {
+                let value = format!("{}-{}", seed, op);
+                model.insert(key.clone(), value.clone());
+                current.set(key, value)
+            }
+            50..=64 => {
+                model.remove(&key);
+                current.delete(key)
+            }
+            65..=89 => current.get(&key).map(|value| {
+                assert_eq!(value.as_ref(), model.get(&key), "seed {}: {}", seed, key);
+                trace.push(format!("get {} = {:?}", key, value));
+            }),
+            90..=93 => current.flush(&FlushOptions::default().wait(rng.gen_bool(0.5))),
+            94..=96 => current.compact(0),
+            _ => {
+                let result = db.unwrap().close();
+                db = open(&env, path);
+                result
+            }
+        };
+        if let Err(err) = result {
+            trace.push(format!("op {}: {}", op, err));
+            break;
+        }
+    }
+    drop(db);
+
+    for dir in [path.to_path_buf(), path.join("sstable"), path.join("wal")] {
+        for file in env.list(&dir).unwrap_or_default() {
+            // the contents, timestamps included, must replay as well as the names
+            let checksum = env.read(&file).map(|bytes| crc32fast::hash(&bytes));
+            trace.push(format!("{} {:?}", file.display(), checksum.ok()));
+        }
+    }
+    trace
+}
+
+#[test]
+fn replays_from_seed() {
+    for seed in 0..10 {
+        let trace = run(seed, 0.0);
+        assert_eq!(trace, run(seed, 0.0), "seed {} did not replay", seed);
+        // background work did run
+        assert!(trace.iter().any(|line| line.contains(".ss ")));
+    }
+}
+
+#[test]
+fn replays_from_seed_with_io_errors() {
+    let mut failed = 0;
+    for seed in 0..10 {
+        let trace = run(seed, 0.002);
+        assert_eq!(trace, run(seed, 0.002), "seed {} did not replay", seed);
+        if trace
+            .iter()
+            .any(|line| line.contains("simulated I/O error"))
+        {
+            failed += 1;
+        }
+    }
+    assert!(failed > 0);
+}
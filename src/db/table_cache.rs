@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::env::Env;
+use crate::error::Result;
+use crate::storage::cache::LruCache;
+use crate::storage::record::Record;
+use crate::storage::sstable::{Block, BlockHandle, SSTable};
+
+/// Keeps the footer, index and properties of recently used SSTables in memory, together with
+/// their recently read data blocks, so lookups do not have to go to disk for them
+pub(crate) struct TableCache {
+    env: Arc<dyn Env>,
+    tables: Mutex<LruCache<PathBuf, Arc<SSTable>>>,
+    blocks: Mutex<LruCache<(PathBuf, usize), Arc<Block>>>,
+    /// Timestamps the records of ingested tables are read with, by path
+    global_sequences: Mutex<HashMap<PathBuf, u128>>,
+}
+
+impl TableCache {
+    pub fn new(env: Arc<dyn Env>, table_cache_size: usize, block_cache_bytes: usize) -> TableCache {
+        TableCache {
+            env,
+            tables: Mutex::new(LruCache::new(table_cache_size)),
+            blocks: Mutex::new(LruCache::new(block_cache_bytes)),
+            global_sequences: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Has the records of the table at `path` read with the timestamp `sequence`. Must be called
+    /// before the table is first read
+    pub fn set_global_sequence(&self, path: &Path, sequence: u128) {
+        self.global_sequences
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), sequence);
+    }
+
+    pub fn table(&self, path: &Path) -> Result<Arc<SSTable>> {
+        if let Some(table) = self.tables.lock().unwrap().get(&path.to_path_buf()) {
+            return Ok(table);
+        }
+
+        let mut table = SSTable::from_disk(self.env.as_ref(), path)?;
+        if let Some(sequence) = self.global_sequences.lock().unwrap().get(path) {
+            table.set_global_sequence(*sequence);
+        }
+        let table = Arc::new(table);
+        // every table counts as one entry
+        self.tables
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), table.clone(), 1);
+        Ok(table)
+    }
+
+    /// Returns the record for `key` in the table at `path`, including tombstones
+    pub fn get(&self, path: &Path, key: &String) -> Result<Option<Record>> {
+        let table = self.table(path)?;
+        let handle = match table.block_handle(key) {
+            Some(handle) => handle,
+            None => return Ok(None),
+        };
+
+        let block = self.block(path, &table, handle)?;
+        Ok(block.get_record(key).cloned())
+    }
+
+    /// Returns the data block at `handle` of `table`, which was read from `path`
+    pub fn block(&self, path: &Path, table: &SSTable, handle: BlockHandle) -> Result<Arc<Block>> {
+        let cache_key = (path.to_path_buf(), handle.offset);
+        if let Some(block) = self.blocks.lock().unwrap().get(&cache_key) {
+            return Ok(block);
+        }
+
+        let block = Arc::new(table.read_block(self.env.as_ref(), path, handle)?);
+        self.blocks
+            .lock()
+            .unwrap()
+            .insert(cache_key, block.clone(), handle.size);
+        Ok(block)
+    }
+
+    /// Drops a table that has been deleted from disk. Its blocks are left to age out
+    pub fn evict(&self, path: &Path) {
+        self.tables.lock().unwrap().remove(&path.to_path_buf());
+        self.global_sequences.lock().unwrap().remove(path);
+    }
+}
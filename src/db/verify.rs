@@ -0,0 +1,280 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::options::VerifyOptions;
+use crate::env::Env;
+use crate::error::Error;
+use crate::storage::sstable::SSTable;
+
+/// Something `DB::verify` found wrong with a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub file: PathBuf,
+    /// Position in `file` where the problem was found, if known
+    pub offset: Option<u64>,
+    pub message: String,
+}
+
+impl Problem {
+    fn new(file: &Path, offset: Option<u64>, message: impl Into<String>) -> Problem {
+        Problem {
+            file: file.to_path_buf(),
+            offset,
+            message: message.into(),
+        }
+    }
+
+    /// The problem behind an error hit while reading `file`
+    fn from_error(file: &Path, err: Error) -> Problem {
+        match err {
+            Error::Corruption {
+                file,
+                offset,
+                message,
+            } => Problem {
+                file,
+                offset,
+                message,
+            },
+            err => Problem::new(file, None, err.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.offset {
+            Some(offset) => write!(
+                f,
+                "{} at offset {}: {}",
+                self.file.display(),
+                offset,
+                self.message
+            ),
+            None => write!(f, "{}: {}", self.file.display(), self.message),
+        }
+    }
+}
+
+/// What `DB::verify` found in a live SSTable
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableReport {
+    pub path: PathBuf,
+    pub data_blocks: usize,
+    /// Records in the data blocks that could be read
+    pub records: u64,
+    pub problems: Vec<Problem>,
+}
+
+/// Result of `DB::verify`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct VerifyReport {
+    /// Every live SSTable, oldest first
+    pub tables: Vec<TableReport>,
+    /// Problems with the manifest, or with files it lists that are not SSTables
+    pub problems: Vec<Problem>,
+    /// SSTables and WALs on disk that the manifest does not list. These are not problems: a
+    /// flush or compaction that is in progress, or did not finish, leaves them behind
+    pub untracked_files: Vec<PathBuf>,
+}
+
+impl VerifyReport {
+    /// Whether no problems were found
+    pub fn is_ok(&self) -> bool {
+        self.problems().next().is_none()
+    }
+
+    pub fn problems(&self) -> impl Iterator<Item = &Problem> {
+        self.tables
+            .iter()
+            .flat_map(|table| table.problems.iter())
+            .chain(self.problems.iter())
+    }
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for table in &self.tables {
+            writeln!(
+                f,
+                "{}: {} data blocks, {} records",
+                table.path.display(),
+                table.data_blocks,
+                table.records
+            )?;
+            for problem in &table.problems {
+                writeln!(f, "  {}", problem)?;
+            }
+        }
+        for problem in &self.problems {
+            writeln!(f, "{}", problem)?;
+        }
+        for path in &self.untracked_files {
+            writeln!(f, "{}: not in the manifest", path.display())?;
+        }
+        write!(
+            f,
+            "{} SSTables checked, {} problems found",
+            self.tables.len(),
+            self.problems().count()
+        )
+    }
+}
+
+/// Checks the SSTable at `path`: that it can be read, that every block passes its checksum,
+/// starts with the key of its index entry and that keys are strictly increasing
+pub(crate) fn verify_table(env: &dyn Env, path: &Path, options: &VerifyOptions) -> TableReport {
+    let mut report = TableReport {
+        path: path.to_path_buf(),
+        data_blocks: 0,
+        records: 0,
+        problems: vec![],
+    };
+    let table = match SSTable::from_disk(env, path) {
+        Ok(table) => table,
+        Err(err) => {
+            report.problems.push(Problem::from_error(path, err));
+            return report;
+        }
+    };
+    report.data_blocks = table.index_entries().count();
+    if !options.verify_checksums {
+        return report;
+    }
+
+    let mut last_key: Option<String> = None;
+    let mut all_read = true;
+    for (index_key, handle) in table.index_entries() {
+        let offset = Some(handle.offset as u64);
+        let block = match table.read_block(env, path, handle) {
+            Ok(block) => block,
+            Err(err) => {
+                report.problems.push(Problem::from_error(path, err));
+                all_read = false;
+                continue;
+            }
+        };
+
+        match block.records().first() {
+            None => report
+                .problems
+                .push(Problem::new(path, offset, "empty data block")),
+            Some(first) if first.get_key() != index_key => report.problems.push(Problem::new(
+                path,
+                offset,
+                format!(
+                    "index entry {:?} does not match the first key {:?} of its block",
+                    index_key,
+                    first.get_key()
+                ),
+            )),
+            Some(_) => {}
+        }
+
+        // one problem per block is enough to tell a block is out of order
+        let mut sorted = true;
+        for record in block.records() {
+            let key = record.get_key();
+            if let Some(last_key) = last_key.as_ref().filter(|last_key| key <= **last_key) {
+                if sorted {
+                    report.problems.push(Problem::new(
+                        path,
+                        offset,
+                        format!("key {:?} does not sort after {:?}", key, last_key),
+                    ));
+                }
+                sorted = false;
+            }
+            last_key = Some(key);
+        }
+        report.records += block.records().len() as u64;
+    }
+
+    // tables written before properties were introduced do not count their entries
+    let properties = table.properties();
+    if all_read && properties.format_version != 0 && properties.num_entries != report.records {
+        report.problems.push(Problem::new(
+            path,
+            None,
+            format!(
+                "properties count {} entries, but the data blocks hold {}",
+                properties.num_entries, report.records
+            ),
+        ));
+    }
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::env::{FaultInjectionEnv, MemEnv};
+    use crate::storage::record::Record;
+    use crate::storage::sstable::TableOptions;
+
+    fn write_table(env: &dyn Env, keys: &[&str]) -> PathBuf {
+        let records = keys
+            .iter()
+            .map(|key| Record::new(key.to_string(), "value".to_owned()))
+            .collect();
+        let options = TableOptions {
+            block_size: 40,
+            ..Default::default()
+        };
+        SSTable::from_records_with_options(records, &options)
+            .write(env, Path::new("sstable"))
+            .unwrap()
+    }
+
+    #[test]
+    fn verify_intact_table() {
+        let env = MemEnv::new();
+        let path = write_table(&env, &["a", "b", "c", "d", "e"]);
+
+        let report = verify_table(&env, &path, &VerifyOptions::default());
+        assert!(report.problems.is_empty(), "{:?}", report.problems);
+        assert!(report.data_blocks > 1);
+        assert_eq!(report.records, 5);
+
+        let options = VerifyOptions::default().verify_checksums(false);
+        assert_eq!(verify_table(&env, &path, &options).records, 0);
+    }
+
+    #[test]
+    fn verify_unsorted_table() {
+        let env = MemEnv::new();
+        let path = write_table(&env, &["a", "c", "b", "d", "d"]);
+
+        let report = verify_table(&env, &path, &VerifyOptions::default());
+        let messages = report
+            .problems
+            .iter()
+            .map(|problem| problem.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                "key \"b\" does not sort after \"c\"",
+                "key \"d\" does not sort after \"d\""
+            ]
+        );
+    }
+
+    #[test]
+    fn verify_corrupt_table() {
+        let env = FaultInjectionEnv::new(Arc::new(MemEnv::new()));
+        let path = write_table(&env, &["a", "b", "c", "d", "e"]);
+        env.corrupt(&path, 3, 1).unwrap();
+
+        let report = verify_table(&env, &path, &VerifyOptions::default());
+        assert_eq!(report.problems.len(), 1);
+        assert_eq!(report.problems[0].offset, Some(0));
+        assert!(report.problems[0].message.contains("checksum mismatch"));
+        assert!(report.records > 0);
+
+        let options = VerifyOptions::default().verify_checksums(false);
+        assert!(verify_table(&env, &path, &options).problems.is_empty());
+    }
+}
@@ -0,0 +1,29 @@
+use crate::storage::record::Record;
+
+/// A group of updates that is written to the WAL and applied to the memtable together
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    pub(crate) records: Vec<Record>,
+}
+
+impl WriteBatch {
+    pub fn new() -> WriteBatch {
+        WriteBatch::default()
+    }
+
+    pub fn set(&mut self, key: String, value: String) {
+        self.records.push(Record::new(key, value));
+    }
+
+    pub fn delete(&mut self, key: String) {
+        self.records.push(Record::new_tombstone(key));
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
@@ -0,0 +1,425 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::{normalize, Env, FileLock, RandomAccessFile, WritableFile};
+use crate::error::Result;
+
+/// Wraps another `Env` and injects the faults of a failing disk or a power failure, for testing
+/// that the database recovers from them.
+///
+/// It keeps track of what has been synced in the files it wrote, so `drop_unsynced_data` can
+/// reset them to what would have survived a power failure: data appended since the last sync
+/// of a file is dropped, and files created or renamed since the last sync of their directory
+/// disappear, bringing back a file that a rename replaced. Removing a file is durable right away
+#[derive(Debug, Clone)]
+pub struct FaultInjectionEnv {
+    inner: Arc<dyn Env>,
+    state: Arc<Mutex<FaultState>>,
+}
+
+#[derive(Debug, Default)]
+struct FaultState {
+    files: BTreeMap<PathBuf, FileState>,
+    /// Operations that modify files left before they start failing, if limited
+    ops_left: Option<u64>,
+    /// Every operation fails once crashed
+    crashed: bool,
+}
+
+#[derive(Debug)]
+struct FileState {
+    len: u64,
+    synced_len: u64,
+    /// Whether the name of the file survives a crash
+    dir_synced: bool,
+    /// Contents of the file a rename replaced, which come back if the rename is lost
+    replaced: Option<Vec<u8>>,
+}
+
+fn injected(message: &str) -> crate::error::Error {
+    std::io::Error::other(format!("injected fault: {}", message)).into()
+}
+
+impl FaultState {
+    fn check(&mut self, modifies: bool) -> Result<()> {
+        if self.crashed {
+            return Err(injected("crashed"));
+        }
+        if modifies {
+            match self.ops_left {
+                Some(0) => return Err(injected("out of operations")),
+                Some(ops_left) => self.ops_left = Some(ops_left - 1),
+                None => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FaultInjectionEnv {
+    pub fn new(inner: Arc<dyn Env>) -> FaultInjectionEnv {
+        FaultInjectionEnv {
+            inner,
+            state: Arc::default(),
+        }
+    }
+
+    /// Lets `ops` more operations that modify files succeed, and fails every one after that
+    pub fn fail_after(&self, ops: u64) {
+        self.state.lock().unwrap().ops_left = Some(ops);
+    }
+
+    /// Fails every operation from now on, like a machine that lost power
+    pub fn crash(&self) {
+        self.state.lock().unwrap().crashed = true;
+    }
+
+    /// Stops injecting faults. Does not bring back data dropped by `drop_unsynced_data`
+    pub fn reset(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.ops_left = None;
+        state.crashed = false;
+    }
+
+    /// Resets the files written through this environment to what was synced. None of them may
+    /// be open for writing
+    pub fn drop_unsynced_data(&self) -> Result<()> {
+        let files = std::mem::take(&mut self.state.lock().unwrap().files);
+        for (path, file) in files {
+            if !file.dir_synced {
+                match file.replaced {
+                    Some(contents) => self.rewrite(&path, &contents)?,
+                    None if self.inner.exists(&path) => self.inner.remove_file(&path)?,
+                    None => {}
+                }
+            } else if file.synced_len < file.len {
+                let contents = self.inner.read(&path)?;
+                let synced_len = (file.synced_len as usize).min(contents.len());
+                self.rewrite(&path, &contents[..synced_len])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flips every bit of `len` bytes at `offset` of the file at `path`, which must not be open
+    /// for writing. Bytes past the end of the file are left alone
+    pub fn corrupt(&self, path: &Path, offset: u64, len: u64) -> Result<()> {
+        let mut contents = self.inner.read(path)?;
+        let start = (offset as usize).min(contents.len());
+        let end = ((offset + len) as usize).min(contents.len());
+        for byte in &mut contents[start..end] {
+            *byte ^= 0xff;
+        }
+        self.rewrite(path, &contents)
+    }
+
+    fn rewrite(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        let mut file = self.inner.create(path)?;
+        file.append(contents)?;
+        file.sync()
+    }
+
+    /// Contents of `path` that survive a crash, for a rename that replaces it
+    fn durable_contents(&self, state: &mut FaultState, path: &Path) -> Result<Option<Vec<u8>>> {
+        match state.files.get_mut(path) {
+            Some(file) if !file.dir_synced => Ok(file.replaced.take()),
+            Some(file) => {
+                let mut contents = self.inner.read(path)?;
+                contents.truncate(file.synced_len as usize);
+                Ok(Some(contents))
+            }
+            None if self.inner.exists(path) => Ok(Some(self.inner.read(path)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Env for FaultInjectionEnv {
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        self.state.lock().unwrap().check(true)?;
+        self.inner.create_dir_all(path)
+    }
+
+    fn create(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+        let mut state = self.state.lock().unwrap();
+        state.check(true)?;
+        let path = normalize(path);
+        let existed = self.inner.exists(&path);
+        let file = self.inner.create(&path)?;
+
+        let dir_synced = match state.files.get(&path) {
+            Some(file) => file.dir_synced,
+            None => existed,
+        };
+        state.files.insert(
+            path.clone(),
+            FileState {
+                len: 0,
+                synced_len: 0,
+                dir_synced,
+                replaced: None,
+            },
+        );
+        Ok(Box::new(FaultWritableFile {
+            inner: file,
+            path,
+            state: self.state.clone(),
+        }))
+    }
+
+    fn open_append(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+        let mut state = self.state.lock().unwrap();
+        state.check(true)?;
+        let path = normalize(path);
+        let file = self.inner.open_append(&path)?;
+
+        if !state.files.contains_key(&path) {
+            let len = self.inner.open(&path)?.size()?;
+            state.files.insert(
+                path.clone(),
+                FileState {
+                    len,
+                    synced_len: len,
+                    dir_synced: true,
+                    replaced: None,
+                },
+            );
+        }
+        Ok(Box::new(FaultWritableFile {
+            inner: file,
+            path,
+            state: self.state.clone(),
+        }))
+    }
+
+    fn open(&self, path: &Path) -> Result<Arc<dyn RandomAccessFile>> {
+        self.state.lock().unwrap().check(false)?;
+        self.inner.open(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.inner.exists(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.check(true)?;
+        let (from, to) = (normalize(from), normalize(to));
+        let replaced = self.durable_contents(&mut state, &to)?;
+        self.inner.rename(&from, &to)?;
+
+        let (len, synced_len) = match state.files.remove(&from) {
+            Some(file) => (file.len, file.synced_len),
+            None => {
+                let len = self.inner.open(&to)?.size()?;
+                (len, len)
+            }
+        };
+        state.files.insert(
+            to,
+            FileState {
+                len,
+                synced_len,
+                dir_synced: false,
+                replaced,
+            },
+        );
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.check(true)?;
+        let path = normalize(path);
+        self.inner.remove_file(&path)?;
+        state.files.remove(&path);
+        Ok(())
+    }
+
+    fn link(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.check(true)?;
+        let to = normalize(to);
+        self.inner.link(from, &to)?;
+
+        // the contents are as durable as those of `from`, but the new name is not
+        let (len, synced_len) = match state.files.get(&normalize(from)) {
+            Some(file) => (file.len, file.synced_len),
+            None => {
+                let len = self.inner.open(&to)?.size()?;
+                (len, len)
+            }
+        };
+        state.files.insert(
+            to,
+            FileState {
+                len,
+                synced_len,
+                dir_synced: false,
+                replaced: None,
+            },
+        );
+        Ok(())
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.check(true)?;
+        let path = normalize(path);
+        self.inner.remove_dir_all(&path)?;
+        state.files.retain(|file, _| !file.starts_with(&path));
+        Ok(())
+    }
+
+    fn list(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        self.state.lock().unwrap().check(false)?;
+        self.inner.list(dir)
+    }
+
+    fn sync_dir(&self, dir: &Path) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.check(true)?;
+        self.inner.sync_dir(dir)?;
+
+        let dir = normalize(dir);
+        for (_, file) in state
+            .files
+            .iter_mut()
+            .filter(|(path, _)| path.parent() == Some(dir.as_path()))
+        {
+            file.dir_synced = true;
+            file.replaced = None;
+        }
+        Ok(())
+    }
+
+    fn lock(&self, path: &Path) -> Result<Box<dyn FileLock>> {
+        self.state.lock().unwrap().check(false)?;
+        self.inner.lock(path)
+    }
+
+    fn now(&self) -> Duration {
+        self.inner.now()
+    }
+
+    fn background_threads(&self) -> bool {
+        self.inner.background_threads()
+    }
+
+    fn run_background_work(&self) -> bool {
+        self.inner.run_background_work()
+    }
+}
+
+struct FaultWritableFile {
+    inner: Box<dyn WritableFile>,
+    path: PathBuf,
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl FaultWritableFile {
+    /// Records that everything appended so far has been synced
+    fn synced(&self) {
+        if let Some(file) = self.state.lock().unwrap().files.get_mut(&self.path) {
+            file.synced_len = file.len;
+        }
+    }
+}
+
+impl WritableFile for FaultWritableFile {
+    fn append(&mut self, data: &[u8]) -> Result<()> {
+        self.state.lock().unwrap().check(true)?;
+        self.inner.append(data)?;
+        if let Some(file) = self.state.lock().unwrap().files.get_mut(&self.path) {
+            file.len += data.len() as u64;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.state.lock().unwrap().check(true)?;
+        self.inner.flush()
+    }
+
+    fn sync_data(&mut self) -> Result<()> {
+        self.state.lock().unwrap().check(true)?;
+        self.inner.sync_data()?;
+        self.synced();
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.state.lock().unwrap().check(true)?;
+        self.inner.sync()?;
+        self.synced();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::env::MemEnv;
+    use crate::storage::fs::write_atomic;
+
+    #[test]
+    fn drop_unsynced_data() {
+        let env = FaultInjectionEnv::new(Arc::new(MemEnv::new()));
+        let dir = Path::new("db");
+        env.create_dir_all(dir).unwrap();
+
+        let mut synced = env.create(&dir.join("synced")).unwrap();
+        synced.append(b"kept").unwrap();
+        synced.sync().unwrap();
+        synced.append(b" dropped").unwrap();
+        synced.flush().unwrap();
+        drop(synced);
+        env.sync_dir(dir).unwrap();
+
+        write_atomic(&env, &dir.join("replaced"), b"old").unwrap();
+
+        let mut unlinked = env.create(&dir.join("unlinked")).unwrap();
+        unlinked.append(b"dropped").unwrap();
+        unlinked.sync().unwrap();
+        drop(unlinked);
+        env.create(&dir.join("replaced.tmp"))
+            .unwrap()
+            .sync()
+            .unwrap();
+        env.rename(&dir.join("replaced.tmp"), &dir.join("replaced"))
+            .unwrap();
+
+        env.drop_unsynced_data().unwrap();
+        assert_eq!(env.read(&dir.join("synced")).unwrap(), b"kept");
+        assert!(!env.exists(&dir.join("unlinked")));
+        assert_eq!(env.read(&dir.join("replaced")).unwrap(), b"old");
+    }
+
+    #[test]
+    fn fail_after() {
+        let env = FaultInjectionEnv::new(Arc::new(MemEnv::new()));
+        env.fail_after(2);
+        let mut file = env.create(Path::new("a")).unwrap();
+        file.append(b"a").unwrap();
+        assert!(file.sync().is_err());
+        assert!(env.read(Path::new("a")).is_ok());
+
+        env.crash();
+        assert!(env.read(Path::new("a")).is_err());
+        env.reset();
+        assert!(file.sync().is_ok());
+    }
+
+    #[test]
+    fn corrupt() {
+        let env = FaultInjectionEnv::new(Arc::new(MemEnv::new()));
+        env.create(Path::new("a"))
+            .unwrap()
+            .append(&[0, 1, 2])
+            .unwrap();
+        env.corrupt(Path::new("a"), 1, 5).unwrap();
+        assert_eq!(env.read(Path::new("a")).unwrap(), vec![0, 0xfe, 0xfd]);
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use super::{normalize, Env, FileLock, RandomAccessFile, WritableFile};
+use crate::error::{Error, Result};
+
+type FileData = Arc<RwLock<Vec<u8>>>;
+
+/// Keeps every file in memory. Clones share the same files, so a test can reopen a database
+/// by passing the same `MemEnv` to `DB::open` again
+#[derive(Debug, Default, Clone)]
+pub struct MemEnv {
+    fs: Arc<Mutex<MemFs>>,
+}
+
+#[derive(Debug, Default)]
+struct MemFs {
+    files: HashMap<PathBuf, FileData>,
+    dirs: BTreeSet<PathBuf>,
+    locks: HashSet<PathBuf>,
+}
+
+impl MemEnv {
+    pub fn new() -> MemEnv {
+        MemEnv::default()
+    }
+}
+
+fn not_found(path: &Path) -> Error {
+    std::io::Error::new(
+        ErrorKind::NotFound,
+        format!("{}: no such file or directory", path.display()),
+    )
+    .into()
+}
+
+impl MemFs {
+    fn is_dir(&self, path: &Path) -> bool {
+        path.as_os_str().is_empty() || self.dirs.contains(path)
+    }
+
+    fn check_parent(&self, path: &Path) -> Result<()> {
+        match path.parent() {
+            Some(parent) if !self.is_dir(parent) => Err(not_found(parent)),
+            _ => Ok(()),
+        }
+    }
+
+    fn file(&self, path: &Path) -> Result<FileData> {
+        self.files.get(path).cloned().ok_or_else(|| not_found(path))
+    }
+}
+
+impl Env for MemEnv {
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        let path = normalize(path);
+        let mut fs = self.fs.lock().unwrap();
+        for dir in path.ancestors() {
+            if fs.files.contains_key(dir) {
+                return Err(std::io::Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("{} is a file", dir.display()),
+                )
+                .into());
+            }
+            if !dir.as_os_str().is_empty() {
+                fs.dirs.insert(dir.to_path_buf());
+            }
+        }
+        Ok(())
+    }
+
+    fn create(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+        let path = normalize(path);
+        let mut fs = self.fs.lock().unwrap();
+        fs.check_parent(&path)?;
+        let data = FileData::default();
+        fs.files.insert(path, data.clone());
+        Ok(Box::new(MemWritableFile(data)))
+    }
+
+    fn open_append(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+        let data = self.fs.lock().unwrap().file(&normalize(path))?;
+        Ok(Box::new(MemWritableFile(data)))
+    }
+
+    fn open(&self, path: &Path) -> Result<Arc<dyn RandomAccessFile>> {
+        let data = self.fs.lock().unwrap().file(&normalize(path))?;
+        Ok(Arc::new(MemRandomAccessFile(data)))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        let path = normalize(path);
+        let fs = self.fs.lock().unwrap();
+        fs.files.contains_key(&path) || fs.is_dir(&path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let (from, to) = (normalize(from), normalize(to));
+        let mut fs = self.fs.lock().unwrap();
+        fs.check_parent(&to)?;
+        let data = fs.files.remove(&from).ok_or_else(|| not_found(&from))?;
+        fs.files.insert(to, data);
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        let path = normalize(path);
+        let mut fs = self.fs.lock().unwrap();
+        fs.files.remove(&path).ok_or_else(|| not_found(&path))?;
+        Ok(())
+    }
+
+    fn link(&self, from: &Path, to: &Path) -> Result<()> {
+        let (from, to) = (normalize(from), normalize(to));
+        let mut fs = self.fs.lock().unwrap();
+        fs.check_parent(&to)?;
+        if fs.files.contains_key(&to) || fs.is_dir(&to) {
+            return Err(std::io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{} already exists", to.display()),
+            )
+            .into());
+        }
+        let data = fs.file(&from)?;
+        fs.files.insert(to, data);
+        Ok(())
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        let path = normalize(path);
+        let mut fs = self.fs.lock().unwrap();
+        if !fs.dirs.contains(&path) {
+            return Err(not_found(&path));
+        }
+        fs.files.retain(|file, _| !file.starts_with(&path));
+        fs.dirs.retain(|dir| !dir.starts_with(&path));
+        Ok(())
+    }
+
+    fn list(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        let normalized = normalize(dir);
+        let fs = self.fs.lock().unwrap();
+        if !fs.is_dir(&normalized) {
+            return Err(not_found(dir));
+        }
+        let mut paths = fs
+            .files
+            .keys()
+            .chain(fs.dirs.iter())
+            .filter(|path| path.parent() == Some(normalized.as_path()))
+            .map(|path| dir.join(path.file_name().unwrap()))
+            .collect::<Vec<_>>();
+        paths.sort();
+        Ok(paths)
+    }
+
+    fn sync_dir(&self, dir: &Path) -> Result<()> {
+        let dir = normalize(dir);
+        if self.fs.lock().unwrap().is_dir(&dir) {
+            Ok(())
+        } else {
+            Err(not_found(&dir))
+        }
+    }
+
+    fn lock(&self, path: &Path) -> Result<Box<dyn FileLock>> {
+        let path = normalize(path);
+        let mut fs = self.fs.lock().unwrap();
+        fs.check_parent(&path)?;
+        if !fs.locks.insert(path.clone()) {
+            return Err(Error::Busy(format!(
+                "{} is locked by another process",
+                path.display()
+            )));
+        }
+        fs.files.entry(path.clone()).or_default();
+        Ok(Box::new(MemFileLock {
+            fs: self.fs.clone(),
+            path,
+        }))
+    }
+}
+
+struct MemWritableFile(FileData);
+
+impl WritableFile for MemWritableFile {
+    fn append(&mut self, data: &[u8]) -> Result<()> {
+        self.0.write().unwrap().extend_from_slice(data);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn sync_data(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+struct MemRandomAccessFile(FileData);
+
+impl RandomAccessFile for MemRandomAccessFile {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let data = self.0.read().unwrap();
+        let start = (offset as usize).min(data.len());
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        Ok(len)
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.0.read().unwrap().len() as u64)
+    }
+}
+
+struct MemFileLock {
+    fs: Arc<Mutex<MemFs>>,
+    path: PathBuf,
+}
+
+impl FileLock for MemFileLock {
+    fn unlock(self: Box<Self>) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for MemFileLock {
+    fn drop(&mut self) {
+        self.fs.lock().unwrap().locks.remove(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn write_and_read() {
+        let env = MemEnv::new();
+        env.create_dir_all(Path::new("db/sstables")).unwrap();
+
+        let mut file = env.create(Path::new("./db/sstables/1.sst")).unwrap();
+        file.append(b"hello ").unwrap();
+        file.append(b"world").unwrap();
+        drop(file);
+
+        let mut file = env.open_append(Path::new("db/sstables/1.sst")).unwrap();
+        file.append(b"!").unwrap();
+
+        let path = Path::new("db/sstables/1.sst");
+        assert_eq!(env.read(path).unwrap(), b"hello world!");
+        let mut buf = [0; 5];
+        assert_eq!(env.open(path).unwrap().read_at(6, &mut buf).unwrap(), 5);
+        assert_eq!(&buf, b"world");
+        assert_eq!(env.open(path).unwrap().read_at(20, &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn create_needs_parent() {
+        let env = MemEnv::new();
+        assert!(env.create(Path::new("missing/file")).is_err());
+        assert!(env.create(Path::new("file")).is_ok());
+    }
+
+    #[test]
+    fn list_rename_remove() {
+        let env = MemEnv::new();
+        env.create_dir_all(Path::new("db/wal")).unwrap();
+        env.create(Path::new("db/b")).unwrap();
+        env.create(Path::new("db/a.tmp")).unwrap();
+        env.rename(Path::new("db/a.tmp"), Path::new("db/a"))
+            .unwrap();
+
+        let paths = env.list(Path::new("./db")).unwrap();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("./db/a"),
+                PathBuf::from("./db/b"),
+                PathBuf::from("./db/wal")
+            ]
+        );
+
+        let file = env.open(Path::new("db/a")).unwrap();
+        env.remove_file(Path::new("db/a")).unwrap();
+        assert!(!env.exists(Path::new("db/a")));
+        assert_eq!(file.size().unwrap(), 0);
+
+        env.link(Path::new("db/b"), Path::new("db/wal/c")).unwrap();
+        assert!(env.link(Path::new("db/b"), Path::new("db/wal/c")).is_err());
+        env.open_append(Path::new("db/b"))
+            .unwrap()
+            .append(b"linked")
+            .unwrap();
+        env.remove_file(Path::new("db/b")).unwrap();
+        assert_eq!(env.read(Path::new("db/wal/c")).unwrap(), b"linked");
+
+        env.remove_dir_all(Path::new("db")).unwrap();
+        assert!(!env.exists(Path::new("db/b")));
+        assert!(!env.exists(Path::new("db")));
+    }
+
+    #[test]
+    fn lock() {
+        let env = MemEnv::new();
+        let lock = env.lock(Path::new("LOCK")).unwrap();
+        assert!(matches!(env.lock(Path::new("./LOCK")), Err(Error::Busy(_))));
+        drop(lock);
+        env.lock(Path::new("LOCK")).unwrap().unlock().unwrap();
+        assert!(env.lock(Path::new("LOCK")).is_ok());
+    }
+}
@@ -0,0 +1,143 @@
+//! Every file the store reads or writes goes through an `Env`, so the storage engine can run
+//! on the local filesystem (`PosixEnv`) or entirely in memory (`MemEnv`), e.g. in tests. The
+//! `Env` also provides the clock and decides how background work is scheduled, which lets
+//! `SimEnv` replay a run exactly from a seed.
+pub mod fault;
+pub mod memory;
+pub mod posix;
+pub mod sim;
+
+use std::fmt;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::Result;
+
+pub use fault::FaultInjectionEnv;
+pub use memory::MemEnv;
+pub use posix::PosixEnv;
+pub use sim::SimEnv;
+
+pub trait Env: Send + Sync + fmt::Debug {
+    /// Creates `path` and any missing parent directories
+    fn create_dir_all(&self, path: &Path) -> Result<()>;
+
+    /// Creates a file for writing, truncating it if it exists
+    fn create(&self, path: &Path) -> Result<Box<dyn WritableFile>>;
+
+    /// Opens an existing file for appending
+    fn open_append(&self, path: &Path) -> Result<Box<dyn WritableFile>>;
+
+    /// Opens an existing file for reading at arbitrary offsets
+    fn open(&self, path: &Path) -> Result<Arc<dyn RandomAccessFile>>;
+
+    /// Returns whether a file or directory exists at `path`
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Moves `from` to `to`, replacing `to` if it exists
+    fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+
+    fn remove_file(&self, path: &Path) -> Result<()>;
+
+    /// Creates `to` as a hard link to the file `from`, so both names share its contents.
+    /// Fails if `to` exists, or, on disk, if the two are on different filesystems
+    fn link(&self, from: &Path, to: &Path) -> Result<()>;
+
+    /// Removes a directory and everything in it
+    fn remove_dir_all(&self, path: &Path) -> Result<()>;
+
+    /// Returns the paths of the files and directories directly inside `dir`, sorted by name
+    fn list(&self, dir: &Path) -> Result<Vec<PathBuf>>;
+
+    /// Makes files created in, renamed into or removed from `dir` survive a crash
+    fn sync_dir(&self, dir: &Path) -> Result<()>;
+
+    /// Takes an exclusive lock on the file at `path`, creating it if needed. The lock is held
+    /// until it is unlocked or dropped. Fails with `Error::Busy` if someone else holds it
+    fn lock(&self, path: &Path) -> Result<Box<dyn FileLock>>;
+
+    /// Time since the unix epoch
+    fn now(&self) -> Duration {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap()
+    }
+
+    /// Whether background work, like flushing memtables, runs on a thread of its own. If not,
+    /// it runs on the threads calling into the database, whenever `run_background_work` agrees
+    fn background_threads(&self) -> bool {
+        true
+    }
+
+    /// Asked by a database without background threads at every point where it could run
+    /// pending background work, e.g. after a write. Returning false postpones the work, unless
+    /// the database has to wait for it
+    fn run_background_work(&self) -> bool {
+        true
+    }
+
+    /// Reads the whole file at `path`
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        let file = self.open(path)?;
+        let mut buf = vec![0; file.size()? as usize];
+        file.read_exact_at(0, &mut buf)?;
+        Ok(buf)
+    }
+}
+
+pub trait WritableFile: Send {
+    fn append(&mut self, data: &[u8]) -> Result<()>;
+
+    /// Hands data buffered in the process to the operating system
+    fn flush(&mut self) -> Result<()>;
+
+    /// Flushes, then makes the contents durable, together with the metadata needed to read
+    /// them back (`fdatasync`)
+    fn sync_data(&mut self) -> Result<()>;
+
+    /// Flushes, then makes the contents and all metadata durable (`fsync`)
+    fn sync(&mut self) -> Result<()>;
+}
+
+pub trait RandomAccessFile: Send + Sync {
+    /// Reads up to `buf.len()` bytes at `offset`, returning how many were read. Returns 0 at
+    /// the end of the file
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize>;
+
+    fn size(&self) -> Result<u64>;
+
+    /// Fills `buf` with the bytes at `offset`, failing if the file ends before that
+    fn read_exact_at(&self, mut offset: u64, mut buf: &mut [u8]) -> Result<()> {
+        while !buf.is_empty() {
+            let read = self.read_at(offset, buf)?;
+            if read == 0 {
+                return Err(std::io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "failed to fill whole buffer",
+                )
+                .into());
+            }
+            offset += read as u64;
+            buf = &mut buf[read..];
+        }
+        Ok(())
+    }
+}
+
+/// An exclusive lock taken with `Env::lock`, released when dropped
+pub trait FileLock: Send + Sync {
+    /// Releases the lock, reporting any error dropping it would ignore
+    fn unlock(self: Box<Self>) -> Result<()>;
+}
+
+/// The environment used unless `Options::env` says otherwise
+pub fn default_env() -> Arc<dyn Env> {
+    Arc::new(PosixEnv)
+}
+
+/// Drops `.` components so `./a/b` and `a/b` name the same file
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| !matches!(component, Component::CurDir))
+        .collect()
+}
@@ -0,0 +1,189 @@
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::{Env, FileLock, RandomAccessFile, WritableFile};
+use crate::error::{Error, Result};
+
+/// Stores files on the local filesystem
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PosixEnv;
+
+impl Env for PosixEnv {
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        fs::create_dir_all(path)?;
+        Ok(())
+    }
+
+    fn create(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(path)?;
+        Ok(Box::new(PosixWritableFile(BufWriter::new(file))))
+    }
+
+    fn open_append(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(Box::new(PosixWritableFile(BufWriter::new(file))))
+    }
+
+    fn open(&self, path: &Path) -> Result<Arc<dyn RandomAccessFile>> {
+        Ok(Arc::new(PosixRandomAccessFile(File::open(path)?)))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        fs::rename(from, to)?;
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    fn link(&self, from: &Path, to: &Path) -> Result<()> {
+        fs::hard_link(from, to)?;
+        Ok(())
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        fs::remove_dir_all(path)?;
+        Ok(())
+    }
+
+    fn list(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut paths = fs::read_dir(dir)?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<Result<Vec<_>>>()?;
+        paths.sort();
+        Ok(paths)
+    }
+
+    fn sync_dir(&self, dir: &Path) -> Result<()> {
+        File::open(dir)?.sync_all()?;
+        Ok(())
+    }
+
+    fn lock(&self, path: &Path) -> Result<Box<dyn FileLock>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+        match file.try_lock() {
+            Ok(()) => Ok(Box::new(PosixFileLock(file))),
+            Err(TryLockError::WouldBlock) => Err(Error::Busy(format!(
+                "{} is locked by another process",
+                path.display()
+            ))),
+            Err(TryLockError::Error(err)) => Err(err.into()),
+        }
+    }
+}
+
+struct PosixWritableFile(BufWriter<File>);
+
+impl WritableFile for PosixWritableFile {
+    fn append(&mut self, data: &[u8]) -> Result<()> {
+        self.0.write_all(data)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.0.flush()?;
+        Ok(())
+    }
+
+    fn sync_data(&mut self) -> Result<()> {
+        self.0.flush()?;
+        self.0.get_ref().sync_data()?;
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.0.flush()?;
+        self.0.get_ref().sync_all()?;
+        Ok(())
+    }
+}
+
+struct PosixRandomAccessFile(File);
+
+impl RandomAccessFile for PosixRandomAccessFile {
+    #[cfg(unix)]
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        use std::os::unix::fs::FileExt;
+        Ok(self.0.read_at(buf, offset)?)
+    }
+
+    #[cfg(windows)]
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        use std::os::windows::fs::FileExt;
+        Ok(self.0.seek_read(buf, offset)?)
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.0.metadata()?.len())
+    }
+}
+
+struct PosixFileLock(File);
+
+impl FileLock for PosixFileLock {
+    fn unlock(self: Box<Self>) -> Result<()> {
+        self.0.unlock()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::Error;
+
+    #[test]
+    fn write_read_and_lock() {
+        let env = PosixEnv;
+        let path = PathBuf::from("./tests/env/output/write_read_and_lock");
+        env.create_dir_all(&path).unwrap();
+
+        let mut file = env.create(&path.join("a.tmp")).unwrap();
+        file.append(b"hello").unwrap();
+        file.sync().unwrap();
+        drop(file);
+        env.rename(&path.join("a.tmp"), &path.join("a")).unwrap();
+        env.sync_dir(&path).unwrap();
+
+        let mut file = env.open_append(&path.join("a")).unwrap();
+        file.append(b" world").unwrap();
+        file.flush().unwrap();
+        assert_eq!(env.read(&path.join("a")).unwrap(), b"hello world");
+        let mut buf = [0; 5];
+        env.open(&path.join("a"))
+            .unwrap()
+            .read_exact_at(6, &mut buf)
+            .unwrap();
+        assert_eq!(&buf, b"world");
+
+        let lock = env.lock(&path.join("LOCK")).unwrap();
+        assert!(matches!(env.lock(&path.join("LOCK")), Err(Error::Busy(_))));
+        lock.unlock().unwrap();
+        assert_eq!(
+            env.list(&path).unwrap(),
+            vec![path.join("LOCK"), path.join("a")]
+        );
+        env.link(&path.join("a"), &path.join("b")).unwrap();
+        assert_eq!(env.read(&path.join("b")).unwrap(), b"hello world");
+        assert!(env.link(&path.join("a"), &path.join("b")).is_err());
+
+        env.remove_dir_all(&path).unwrap();
+        assert!(!env.exists(&path));
+    }
+}
@@ -0,0 +1,249 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::{Env, FileLock, MemEnv, RandomAccessFile, WritableFile};
+use crate::error::Result;
+
+/// Time of the simulated clock when a `SimEnv` is created
+const START_TIME: Duration = Duration::from_secs(1_700_000_000);
+/// Upper bound of how far the simulated clock moves with every operation
+const MAX_TICK_MICROS: u64 = 1_000;
+
+/// Runs a database deterministically from a seed, so a failing randomized test replays exactly.
+/// Everything that would otherwise depend on the machine is drawn from a random number generator
+/// seeded with it:
+///
+/// - the clock, which only moves when the environment is used, by a random amount
+/// - when background flushes and compactions run. There are no background threads; the work
+///   runs on the thread calling into the database, at random points
+/// - which file operations fail, if `io_error_probability` is set
+///
+/// Runs replay exactly as long as the database is used from a single thread. Files are kept in
+/// memory unless another environment is passed to `with_env`, e.g. a `FaultInjectionEnv`
+#[derive(Debug, Clone)]
+pub struct SimEnv {
+    inner: Arc<dyn Env>,
+    state: Arc<Mutex<SimState>>,
+}
+
+#[derive(Debug)]
+struct SimState {
+    rng: SimRng,
+    now: Duration,
+    background_probability: f64,
+    io_error_probability: f64,
+}
+
+impl SimState {
+    /// Moves the clock, and fails an operation that modifies files with the configured
+    /// probability
+    fn tick(&mut self, modifies: bool) -> Result<()> {
+        self.now += Duration::from_micros(1 + self.rng.below(MAX_TICK_MICROS));
+        if modifies && self.rng.chance(self.io_error_probability) {
+            return Err(std::io::Error::other("simulated I/O error").into());
+        }
+        Ok(())
+    }
+}
+
+/// SplitMix64, which is small and good enough to drive a simulation. It is implemented here so
+/// the same seed keeps producing the same run, whatever the version of other crates
+#[derive(Debug)]
+struct SimRng(u64);
+
+impl SimRng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        // the 53 high bits make a uniformly distributed f64 in [0, 1)
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+}
+
+impl SimEnv {
+    /// Simulates a run with files kept in memory
+    pub fn new(seed: u64) -> SimEnv {
+        SimEnv::with_env(seed, Arc::new(MemEnv::new()))
+    }
+
+    /// Simulates a run with files stored in `inner`, which must be deterministic itself
+    pub fn with_env(seed: u64, inner: Arc<dyn Env>) -> SimEnv {
+        SimEnv {
+            inner,
+            state: Arc::new(Mutex::new(SimState {
+                rng: SimRng(seed),
+                now: START_TIME,
+                background_probability: 0.5,
+                io_error_probability: 0.0,
+            })),
+        }
+    }
+
+    /// Chance that pending background work runs when the database offers to run it
+    pub fn background_probability(self, probability: f64) -> Self {
+        self.state.lock().unwrap().background_probability = probability;
+        self
+    }
+
+    /// Chance that an operation modifying a file fails with an I/O error
+    pub fn io_error_probability(self, probability: f64) -> Self {
+        self.state.lock().unwrap().io_error_probability = probability;
+        self
+    }
+
+    fn tick(&self, modifies: bool) -> Result<()> {
+        self.state.lock().unwrap().tick(modifies)
+    }
+}
+
+impl Env for SimEnv {
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        self.tick(true)?;
+        self.inner.create_dir_all(path)
+    }
+
+    fn create(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+        self.tick(true)?;
+        Ok(Box::new(SimWritableFile {
+            inner: self.inner.create(path)?,
+            state: self.state.clone(),
+        }))
+    }
+
+    fn open_append(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+        self.tick(true)?;
+        Ok(Box::new(SimWritableFile {
+            inner: self.inner.open_append(path)?,
+            state: self.state.clone(),
+        }))
+    }
+
+    fn open(&self, path: &Path) -> Result<Arc<dyn RandomAccessFile>> {
+        self.tick(false)?;
+        self.inner.open(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.inner.exists(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.tick(true)?;
+        self.inner.rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        self.tick(true)?;
+        self.inner.remove_file(path)
+    }
+
+    fn link(&self, from: &Path, to: &Path) -> Result<()> {
+        self.tick(true)?;
+        self.inner.link(from, to)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        self.tick(true)?;
+        self.inner.remove_dir_all(path)
+    }
+
+    fn list(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        self.tick(false)?;
+        self.inner.list(dir)
+    }
+
+    fn sync_dir(&self, dir: &Path) -> Result<()> {
+        self.tick(true)?;
+        self.inner.sync_dir(dir)
+    }
+
+    fn lock(&self, path: &Path) -> Result<Box<dyn FileLock>> {
+        self.tick(false)?;
+        self.inner.lock(path)
+    }
+
+    fn now(&self) -> Duration {
+        self.state.lock().unwrap().now
+    }
+
+    fn background_threads(&self) -> bool {
+        false
+    }
+
+    fn run_background_work(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let probability = state.background_probability;
+        state.rng.chance(probability)
+    }
+}
+
+struct SimWritableFile {
+    inner: Box<dyn WritableFile>,
+    state: Arc<Mutex<SimState>>,
+}
+
+impl SimWritableFile {
+    fn tick(&self) -> Result<()> {
+        self.state.lock().unwrap().tick(true)
+    }
+}
+
+impl WritableFile for SimWritableFile {
+    fn append(&mut self, data: &[u8]) -> Result<()> {
+        self.tick()?;
+        self.inner.append(data)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.tick()?;
+        self.inner.flush()
+    }
+
+    fn sync_data(&mut self) -> Result<()> {
+        self.tick()?;
+        self.inner.sync_data()
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.tick()?;
+        self.inner.sync()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Outcomes of a few operations, which only depend on the seed
+    fn run(seed: u64) -> Vec<(Duration, bool, bool)> {
+        let env = SimEnv::new(seed).io_error_probability(0.3);
+        (0..20)
+            .map(|i| {
+                let created = env.create(Path::new(&i.to_string())).is_ok();
+                (env.now(), created, env.run_background_work())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn replays_from_seed() {
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1), run(2));
+
+        let outcomes = run(3);
+        assert!(outcomes.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(outcomes.iter().any(|(_, created, _)| !created));
+        assert!(outcomes.iter().any(|(_, created, _)| *created));
+    }
+}
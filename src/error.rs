@@ -0,0 +1,103 @@
+use std::{fmt, io, path::PathBuf};
+
+/// Errors returned by the public API of the store
+#[derive(Debug)]
+pub enum Error {
+    /// The underlying filesystem operation failed
+    Io(io::Error),
+    /// A file on disk does not contain what we expect, e.g. a checksum mismatch.
+    /// `offset` is the position in `file` where the problem was found, if known
+    Corruption {
+        file: PathBuf,
+        offset: Option<u64>,
+        message: String,
+    },
+    /// The caller passed an argument we can not act on
+    InvalidArgument(String),
+    /// A file or record that should exist is missing
+    NotFound(String),
+    /// The resource is in use, e.g. by another process
+    Busy(String),
+    /// The file uses a format or feature this build does not support
+    NotSupported(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn corruption(
+        file: impl Into<PathBuf>,
+        offset: Option<u64>,
+        message: impl Into<String>,
+    ) -> Self {
+        Error::Corruption {
+            file: file.into(),
+            offset,
+            message: message.into(),
+        }
+    }
+
+    /// Copies the error, for handing the same failure to several callers. IO errors keep
+    /// their kind and message but lose their source
+    pub(crate) fn duplicate(&self) -> Error {
+        match self {
+            Error::Io(err) => Error::Io(io::Error::new(err.kind(), err.to_string())),
+            Error::Corruption {
+                file,
+                offset,
+                message,
+            } => Error::Corruption {
+                file: file.clone(),
+                offset: *offset,
+                message: message.clone(),
+            },
+            Error::InvalidArgument(message) => Error::InvalidArgument(message.clone()),
+            Error::NotFound(message) => Error::NotFound(message.clone()),
+            Error::Busy(message) => Error::Busy(message.clone()),
+            Error::NotSupported(message) => Error::NotSupported(message.clone()),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "IO error: {}", err),
+            Error::Corruption {
+                file,
+                offset: Some(offset),
+                message,
+            } => write!(
+                f,
+                "Corruption in {} at offset {}: {}",
+                file.display(),
+                offset,
+                message
+            ),
+            Error::Corruption {
+                file,
+                offset: None,
+                message,
+            } => write!(f, "Corruption in {}: {}", file.display(), message),
+            Error::InvalidArgument(message) => write!(f, "Invalid argument: {}", message),
+            Error::NotFound(message) => write!(f, "Not found: {}", message),
+            Error::Busy(message) => write!(f, "Busy: {}", message),
+            Error::NotSupported(message) => write!(f, "Not supported: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
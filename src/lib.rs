@@ -1,3 +1,7 @@
 pub mod cli;
 pub mod db;
+pub mod env;
+pub mod error;
 pub mod storage;
+
+pub use error::{Error, Result};
@@ -1,6 +1,143 @@
-#![allow(dead_code)]
-// use rust_kv_store::TreeNode;
+use std::env;
+use std::fs::File;
+use std::io::{stdin, stdout, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
-// use rust_kv_store::storage::memtable
+use rust_kv_store::cli::repl;
+use rust_kv_store::cli::script::{self, Subcommand, EXIT_ERROR, EXIT_NOT_FOUND, EXIT_USAGE};
+use rust_kv_store::cli::sst_dump::{self, SstDump};
+use rust_kv_store::cli::wal_dump::{self, WalDump};
+use rust_kv_store::db::db::DB;
+use rust_kv_store::db::options::Options;
+use rust_kv_store::env::default_env;
+use rust_kv_store::{Error, Result};
 
-fn main() {}
+/// Name of the file in the home directory the shell keeps its history in
+const HISTORY_FILE: &str = ".rust_kv_store_history";
+
+fn main() -> ExitCode {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let result = match args.split_first() {
+        Some((tool, rest)) if tool == "sst_dump" => match SstDump::from_args(rest) {
+            Ok(dump) => dump
+                .run(default_env().as_ref(), &mut stdout())
+                .map(|()| true),
+            Err(err) => return usage(&err, sst_dump::USAGE),
+        },
+        Some((tool, rest)) if tool == "wal_dump" => match WalDump::from_args(rest) {
+            Ok(dump) => match dump.run(default_env().as_ref(), &mut stdout()) {
+                Ok(end) => return ExitCode::from(wal_dump::exit_code(&end)),
+                Err(err) => Err(err),
+            },
+            Err(err) => return usage(&err, wal_dump::USAGE),
+        },
+        Some((path, rest)) if path != "--help" && path != "-h" => {
+            match Subcommand::from_args(rest) {
+                Ok(subcommand) => run(Path::new(path), &subcommand),
+                Err(err) => return usage(&err, script::USAGE),
+            }
+        }
+        _ => {
+            eprintln!("{}", script::USAGE);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::from(EXIT_NOT_FOUND),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::from(EXIT_ERROR)
+        }
+    }
+}
+
+fn usage(err: &Error, usage: &str) -> ExitCode {
+    eprintln!("{}\n\n{}", err, usage);
+    ExitCode::from(EXIT_USAGE)
+}
+
+/// Returns `false` if a `get` found nothing
+fn run(path: &Path, subcommand: &Subcommand) -> Result<bool> {
+    if *subcommand == Subcommand::Repair {
+        println!("{}", DB::repair(path, &Options::default())?);
+        return Ok(true);
+    }
+
+    let db = if subcommand.is_read_only() {
+        DB::open_read_only(path, &Options::default(), true)?
+    } else {
+        DB::new(path)?
+    };
+
+    let mut out = stdout();
+    let found = match subcommand {
+        Subcommand::Shell => {
+            let history = env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+            repl::run(&db, history.as_deref())?;
+            true
+        }
+        Subcommand::Command(command) => script::run_command(&db, command, &mut out)?,
+        Subcommand::Dump => {
+            script::dump(&db, &mut out)?;
+            true
+        }
+        Subcommand::Load { file } => {
+            script::load(&db, &mut *input(file.as_deref())?)?;
+            true
+        }
+        Subcommand::Batch { file } => {
+            script::batch(&db, &mut *input(file.as_deref())?)?;
+            true
+        }
+        Subcommand::Export(export) => {
+            let mut out = output(export.file.as_deref())?;
+            export.run(&db, &mut *out)?;
+            out.flush()?;
+            true
+        }
+        Subcommand::Import(import) => {
+            let verb = if import.dry_run {
+                "checked"
+            } else {
+                "imported"
+            };
+            let report = |records| eprintln!("{} records {}", records, verb);
+            let mut input = input(import.file.as_deref())?;
+            let records = import.run(&db, &mut *input, &mut |records| {
+                if import.progress {
+                    report(records);
+                }
+            })?;
+            if !import.progress {
+                report(records);
+            }
+            true
+        }
+        Subcommand::Ingest { files } => {
+            db.ingest_external_files(&files.iter().map(PathBuf::from).collect::<Vec<_>>())?;
+            true
+        }
+        Subcommand::Repair => unreachable!("repair does not open the database"),
+    };
+    db.close()?;
+    Ok(found)
+}
+
+/// Creates `file` for writing, or writes to standard output if there is none
+fn output(file: Option<&str>) -> Result<Box<dyn Write>> {
+    Ok(match file {
+        Some(file) => Box::new(BufWriter::new(File::create(file)?)),
+        None => Box::new(stdout().lock()),
+    })
+}
+
+/// Opens `file` for reading, or standard input if there is none
+fn input(file: Option<&str>) -> Result<Box<dyn BufRead>> {
+    Ok(match file {
+        Some(file) => Box::new(BufReader::new(File::open(file)?)),
+        None => Box::new(stdin().lock()),
+    })
+}
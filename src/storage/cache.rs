@@ -0,0 +1,113 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// Least recently used cache, bounded by the total charge of its entries rather than their
+/// number. A capacity of 0 disables the cache.
+pub struct LruCache<K, V> {
+    capacity: usize,
+    usage: usize,
+    tick: u64,
+    entries: HashMap<K, (V, usize, u64)>,
+    // last use of each entry, oldest first
+    order: BTreeMap<u64, K>,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> LruCache<K, V> {
+    pub fn new(capacity: usize) -> LruCache<K, V> {
+        LruCache {
+            capacity,
+            usage: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        self.tick += 1;
+        let (value, _, last_use) = self.entries.get_mut(key)?;
+        self.order.remove(last_use);
+        *last_use = self.tick;
+        self.order.insert(self.tick, key.clone());
+        Some(value.clone())
+    }
+
+    /// Inserts `value`, evicting the least recently used entries until the total charge fits.
+    /// Values larger than the capacity are not cached
+    pub fn insert(&mut self, key: K, value: V, charge: usize) {
+        self.remove(&key);
+        if charge > self.capacity {
+            return;
+        }
+
+        while self.usage + charge > self.capacity {
+            let (_, oldest) = self.order.pop_first().unwrap();
+            let (_, oldest_charge, _) = self.entries.remove(&oldest).unwrap();
+            self.usage -= oldest_charge;
+        }
+
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (value, charge, self.tick));
+        self.usage += charge;
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let (value, charge, last_use) = self.entries.remove(key)?;
+        self.order.remove(&last_use);
+        self.usage -= charge;
+        Some(value)
+    }
+
+    /// Total charge of the cached entries
+    pub fn usage(&self) -> usize {
+        self.usage
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = LruCache::new(3);
+        cache.insert("a", 1, 1);
+        cache.insert("b", 2, 1);
+        cache.insert("c", 3, 1);
+        assert_eq!(cache.get(&"a"), Some(1));
+
+        cache.insert("d", 4, 1);
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!(cache.get(&"a"), Some(1));
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn charge() {
+        let mut cache = LruCache::new(10);
+        cache.insert("a", 1, 6);
+        cache.insert("b", 2, 6);
+        assert_eq!(cache.get(&"a"), None);
+        assert_eq!(cache.usage(), 6);
+
+        cache.insert("c", 3, 11);
+        assert_eq!(cache.get(&"c"), None);
+        assert_eq!(cache.get(&"b"), Some(2));
+    }
+
+    #[test]
+    fn disabled() {
+        let mut cache = LruCache::new(0);
+        cache.insert("a", 1, 1);
+        assert!(cache.is_empty());
+    }
+}
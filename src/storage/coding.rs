@@ -0,0 +1,146 @@
+//! Integer encodings shared by the WAL and SSTable formats.
+//!
+//! Files written before SSTable format version 3 (and WAL files without a header) stored
+//! lengths and offsets as 8 byte little-endian integers, which is what `usize` was on the
+//! 64-bit machines that wrote them. Newer files use LEB128 varints, which are independent of
+//! the width of `usize` and take a single byte for most lengths.
+
+const U64_BYTES: usize = (u64::BITS / 8) as usize;
+const U128_BYTES: usize = (u128::BITS / 8) as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// Lengths and offsets as 8 byte and timestamps as 16 byte little-endian integers
+    Legacy,
+    /// Lengths, offsets and timestamps as LEB128 varints
+    Varint,
+}
+
+impl Encoding {
+    pub fn put_usize(self, bytes: &mut Vec<u8>, value: usize) {
+        match self {
+            Encoding::Legacy => bytes.extend((value as u64).to_le_bytes()),
+            Encoding::Varint => put_varint(bytes, value as u128),
+        }
+    }
+
+    pub fn put_u128(self, bytes: &mut Vec<u8>, value: u128) {
+        match self {
+            Encoding::Legacy => bytes.extend(value.to_le_bytes()),
+            Encoding::Varint => put_varint(bytes, value),
+        }
+    }
+
+    /// Reads a length or offset at `offset`, advancing it past the value.
+    /// Returns `None` if `bytes` is too short or the value does not fit in a `usize`
+    pub fn get_usize(self, bytes: &[u8], offset: &mut usize) -> Option<usize> {
+        let value = match self {
+            Encoding::Legacy => {
+                let value =
+                    u64::from_le_bytes(bytes.get(*offset..*offset + U64_BYTES)?.try_into().ok()?);
+                *offset += U64_BYTES;
+                value as u128
+            }
+            Encoding::Varint => get_varint(bytes, offset)?,
+        };
+        usize::try_from(value).ok()
+    }
+
+    pub fn get_u128(self, bytes: &[u8], offset: &mut usize) -> Option<u128> {
+        match self {
+            Encoding::Legacy => {
+                let value =
+                    u128::from_le_bytes(bytes.get(*offset..*offset + U128_BYTES)?.try_into().ok()?);
+                *offset += U128_BYTES;
+                Some(value)
+            }
+            Encoding::Varint => get_varint(bytes, offset),
+        }
+    }
+
+    /// Number of bytes `put_usize` uses for `value`
+    pub fn usize_length(self, value: usize) -> usize {
+        match self {
+            Encoding::Legacy => U64_BYTES,
+            Encoding::Varint => varint_length(value as u128),
+        }
+    }
+
+    /// Number of bytes `put_u128` uses for `value`
+    pub fn u128_length(self, value: u128) -> usize {
+        match self {
+            Encoding::Legacy => U128_BYTES,
+            Encoding::Varint => varint_length(value),
+        }
+    }
+}
+
+fn put_varint(bytes: &mut Vec<u8>, mut value: u128) {
+    while value >= 0x80 {
+        bytes.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn get_varint(bytes: &[u8], offset: &mut usize) -> Option<u128> {
+    let mut value: u128 = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *bytes.get(*offset)?;
+        *offset += 1;
+        if shift >= u128::BITS {
+            return None;
+        }
+        value |= ((byte & 0x7f) as u128) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+}
+
+fn varint_length(mut value: u128) -> usize {
+    let mut length = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        length += 1;
+    }
+    length
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn varint_round_trip() {
+        for value in [0, 1, 127, 128, 300, u64::MAX as u128, u128::MAX] {
+            let mut bytes = vec![];
+            Encoding::Varint.put_u128(&mut bytes, value);
+            assert_eq!(bytes.len(), Encoding::Varint.u128_length(value));
+
+            let mut offset = 0;
+            assert_eq!(Encoding::Varint.get_u128(&bytes, &mut offset), Some(value));
+            assert_eq!(offset, bytes.len());
+        }
+    }
+
+    #[test]
+    fn legacy_round_trip() {
+        let mut bytes = vec![];
+        Encoding::Legacy.put_usize(&mut bytes, 300);
+        assert_eq!(bytes, 300u64.to_le_bytes());
+
+        let mut offset = 0;
+        assert_eq!(Encoding::Legacy.get_usize(&bytes, &mut offset), Some(300));
+        assert_eq!(offset, 8);
+    }
+
+    #[test]
+    fn truncated() {
+        let mut offset = 0;
+        assert_eq!(Encoding::Varint.get_usize(&[0x80], &mut offset), None);
+        assert_eq!(Encoding::Legacy.get_usize(&[1, 0, 0], &mut 0), None);
+    }
+}
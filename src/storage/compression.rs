@@ -0,0 +1,74 @@
+use std::fmt;
+
+use crate::error::{Error, Result};
+
+/// Codec used to compress the data blocks of an SSTable. The codec is recorded in the
+/// properties block, so tables written with different codecs can be read side by side
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Snappy,
+}
+
+impl Compression {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Snappy => "snappy",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Compression> {
+        match name {
+            "none" => Ok(Compression::None),
+            "snappy" => Ok(Compression::Snappy),
+            other => Err(Error::NotSupported(format!(
+                "unknown compression codec '{}'",
+                other
+            ))),
+        }
+    }
+
+    pub fn compress(&self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            Compression::None => bytes.to_vec(),
+            Compression::Snappy => snap::raw::Encoder::new()
+                .compress_vec(bytes)
+                .expect("snappy can compress any input"),
+        }
+    }
+
+    /// Returns `None` if `bytes` is not valid output of `compress`
+    pub fn decompress(&self, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        match self {
+            Compression::None => Some(bytes),
+            Compression::Snappy => snap::raw::Decoder::new().decompress_vec(&bytes).ok(),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let bytes = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".as_bytes();
+        for compression in [Compression::None, Compression::Snappy] {
+            let compressed = compression.compress(bytes);
+            assert_eq!(compression.decompress(compressed).unwrap(), bytes);
+            assert_eq!(
+                Compression::from_name(compression.name()).unwrap(),
+                compression
+            );
+        }
+        assert!(Compression::Snappy.compress(bytes).len() < bytes.len());
+    }
+}
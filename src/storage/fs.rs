@@ -0,0 +1,138 @@
+use std::path::{Path, PathBuf};
+
+use crate::env::Env;
+use crate::error::Result;
+
+/// Appended to the name of files that are still being written. They are renamed to their final
+/// name once complete, so a file without the suffix is never partially written
+pub const TEMP_SUFFIX: &str = ".tmp";
+
+/// Writes `bytes` to `path` such that after a crash the file either has the full contents or
+/// does not exist: the bytes go to a temporary file, which is synced and then renamed into place
+pub fn write_atomic(env: &dyn Env, path: &Path, bytes: &[u8]) -> Result<()> {
+    let temp_path = temp_path(path);
+    let mut file = env.create(&temp_path)?;
+    file.append(bytes)?;
+    file.sync()?;
+    drop(file);
+
+    env.rename(&temp_path, path)?;
+    if let Some(dir) = path.parent() {
+        env.sync_dir(dir)?;
+    }
+    Ok(())
+}
+
+/// Fails if `path` exists, so a new file never replaces one that may still be in use
+pub fn ensure_absent(env: &dyn Env, path: &Path) -> Result<()> {
+    if env.exists(path) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} already exists", path.display()),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Makes `to` a file with the contents of `from`, which must not change afterwards: a hard link
+/// if the filesystem allows it, and a copy otherwise. Like `write_atomic`, `to` only ever appears
+/// with the full contents
+pub fn link_or_copy(env: &dyn Env, from: &Path, to: &Path) -> Result<()> {
+    ensure_absent(env, to)?;
+    if env.link(from, to).is_ok() {
+        // the contents may never have been synced by whoever wrote them
+        env.open_append(to)?.sync()?;
+    } else {
+        let source = env.open(from)?;
+        let temp_path = temp_path(to);
+        let mut file = env.create(&temp_path)?;
+        let mut buffer = vec![0; COPY_CHUNK_BYTES];
+        let size = source.size()?;
+        let mut offset = 0;
+        while offset < size {
+            let chunk = &mut buffer[..(size - offset).min(COPY_CHUNK_BYTES as u64) as usize];
+            source.read_exact_at(offset, chunk)?;
+            file.append(chunk)?;
+            offset += chunk.len() as u64;
+        }
+        file.sync()?;
+        drop(file);
+        env.rename(&temp_path, to)?;
+    }
+    if let Some(dir) = to.parent() {
+        env.sync_dir(dir)?;
+    }
+    Ok(())
+}
+
+const COPY_CHUNK_BYTES: usize = 1 << 20;
+
+/// The number a database file is named after: `<number>.wal` or `<number>_<level>.ss`. Files
+/// written by older versions are named after the time they were created, in microseconds
+pub fn file_number(path: &Path) -> Option<u64> {
+    let stem = path.file_stem()?.to_str()?;
+    stem.split_once('_')
+        .map_or(stem, |(number, _)| number)
+        .parse()
+        .ok()
+}
+
+/// Removes files in `dir` left behind by a `write_atomic` that was interrupted
+pub fn remove_temp_files(env: &dyn Env, dir: &Path) -> Result<()> {
+    if !env.exists(dir) {
+        return Ok(());
+    }
+    for path in env.list(dir)? {
+        if path.to_string_lossy().ends_with(TEMP_SUFFIX) {
+            env.remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the files in `dir` with the extension `extension`, sorted by name, or nothing if
+/// `dir` does not exist
+pub fn list_files(env: &dyn Env, dir: &Path, extension: &str) -> Result<Vec<PathBuf>> {
+    if !env.exists(dir) {
+        return Ok(Vec::new());
+    }
+    Ok(env
+        .list(dir)?
+        .into_iter()
+        .filter(|path| path.extension().is_some_and(|ext| ext == extension))
+        .collect())
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(TEMP_SUFFIX);
+    PathBuf::from(temp_path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::env::MemEnv;
+
+    #[test]
+    fn write_atomic_leaves_no_temp_file() {
+        let env = MemEnv::new();
+        let path = PathBuf::from("write_atomic");
+        env.create_dir_all(&path).unwrap();
+
+        write_atomic(&env, &path.join("a"), b"hello").unwrap();
+        env.create(&path.join("b.tmp"))
+            .unwrap()
+            .append(b"interrupted")
+            .unwrap();
+        remove_temp_files(&env, &path).unwrap();
+
+        assert_eq!(env.list(&path).unwrap(), vec![path.join("a")]);
+        assert_eq!(env.read(&path.join("a")).unwrap(), b"hello");
+        assert_eq!(
+            list_files(&env, &path, "ss").unwrap(),
+            Vec::<PathBuf>::new()
+        );
+    }
+}
@@ -1,11 +1,20 @@
 #![allow(dead_code)]
 
 use std::collections::BTreeMap;
+use std::mem::size_of;
+use std::ops::RangeBounds;
 
 use super::record::Record;
 
+/// Memory used by an entry on top of its key and value: the key stored in the map, the record
+/// and roughly its share of the tree nodes
+const ENTRY_OVERHEAD: usize = size_of::<String>() + size_of::<Record>() + 2 * size_of::<usize>();
+
+#[derive(Clone)]
 pub struct MemTable {
     _storage: BTreeMap<String, Record>,
+    /// Approximate number of bytes allocated for the records, see `memory_usage`
+    memory_usage: usize,
 }
 
 impl Default for MemTable {
@@ -18,11 +27,20 @@ impl MemTable {
     pub fn new() -> MemTable {
         MemTable {
             _storage: BTreeMap::new(),
+            memory_usage: 0,
+        }
+    }
+
+    /// Inserts `record` as is, replacing any record with the same key
+    pub fn insert(&mut self, record: Record) {
+        self.memory_usage += memory_usage(&record);
+        if let Some(old_record) = self._storage.insert(record.get_key(), record) {
+            self.memory_usage -= memory_usage(&old_record);
         }
     }
 
     pub fn set(&mut self, key: String, value: String) {
-        self._storage.insert(key.clone(), Record::new(key, value));
+        self.insert(Record::new(key, value));
     }
 
     pub fn get(&self, key: &String) -> Option<String> {
@@ -33,15 +51,31 @@ impl MemTable {
         }
     }
 
+    /// Returns the record for `key`, which tells a deleted key apart from one that was never
+    /// written
+    pub fn get_record(&self, key: &String) -> Option<&Record> {
+        self._storage.get(key)
+    }
+
+    /// Returns the records with a key in `range`, tombstones included, sorted by key
+    pub fn range<R: RangeBounds<String>>(&self, range: R) -> impl Iterator<Item = &Record> {
+        self._storage.range(range).map(|(_, record)| record)
+    }
+
     pub fn delete(&mut self, key: String) {
-        self._storage
-            .insert(key.clone(), Record::new_tombstone(key));
+        self.insert(Record::new_tombstone(key));
     }
 
     pub fn len(&self) -> usize {
         self._storage.len()
     }
 
+    /// Approximate number of bytes of memory used by the keys, values and bookkeeping of the
+    /// memtable
+    pub fn approximate_memory_usage(&self) -> usize {
+        self.memory_usage
+    }
+
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
@@ -51,6 +85,11 @@ impl MemTable {
     }
 }
 
+fn memory_usage(record: &Record) -> usize {
+    // the key is stored both in the map and in the record
+    ENTRY_OVERHEAD + 2 * record.key_size() + record.value_size()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -78,4 +117,22 @@ mod test {
         mem_table.delete("a".to_string());
         assert_eq!(mem_table.get(&"a".to_string()), None);
     }
+
+    #[test]
+    fn test_memory_usage() {
+        let mut mem_table = MemTable::new();
+        mem_table.set("a".to_string(), "a".to_string());
+        let usage = mem_table.approximate_memory_usage();
+        assert_eq!(usage, ENTRY_OVERHEAD + 3);
+
+        mem_table.set("a".to_string(), "aaa".to_string());
+        assert_eq!(mem_table.approximate_memory_usage(), usage + 2);
+
+        mem_table.delete("a".to_string());
+        assert_eq!(mem_table.approximate_memory_usage(), ENTRY_OVERHEAD + 2);
+        assert!(matches!(
+            mem_table.get_record(&"a".to_string()),
+            Some(Record::Tombstone { .. })
+        ));
+    }
 }
@@ -1,4 +1,10 @@
+pub mod cache;
+pub mod coding;
+pub mod compression;
+pub mod fs;
 pub mod memtable;
+pub mod properties;
 pub mod record;
+pub mod sst_file_writer;
 pub mod sstable;
 pub mod wal;
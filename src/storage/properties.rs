@@ -0,0 +1,196 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::coding::Encoding;
+use super::compression::Compression;
+use super::record::Record;
+
+/// Summary statistics about a single SSTable, stored in the properties meta block so a
+/// table can be inspected without decoding its data blocks.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TableProperties {
+    pub num_entries: u64,
+    pub num_tombstones: u64,
+    pub num_data_blocks: u64,
+    pub raw_key_size: u64,
+    pub raw_value_size: u64,
+    pub smallest_key: String,
+    pub largest_key: String,
+    pub min_sequence: u128,
+    pub max_sequence: u128,
+    /// Microseconds since the unix epoch when the table was built
+    pub creation_time: u64,
+    pub compression: String,
+    pub format_version: u32,
+}
+
+impl TableProperties {
+    /// Collects the properties of `records`, which are expected to be sorted by key
+    pub fn from_records(records: &[Record], num_data_blocks: usize, format_version: u32) -> Self {
+        let mut properties = TableProperties::new(format_version);
+        for record in records {
+            properties.add(record);
+        }
+        properties.num_data_blocks = num_data_blocks as u64;
+        properties.largest_key = records.last().map(|r| r.get_key()).unwrap_or_default();
+        properties
+    }
+
+    /// The properties of a table without records, built now. Records are counted with `add`
+    pub fn new(format_version: u32) -> Self {
+        let creation_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_micros() as u64;
+
+        TableProperties {
+            creation_time,
+            compression: Compression::None.name().to_owned(),
+            format_version,
+            ..Default::default()
+        }
+    }
+
+    /// Counts `record`, which sorts after every record added before. The largest key is left to
+    /// the caller, so the key of every record does not have to be copied
+    pub fn add(&mut self, record: &Record) {
+        if self.num_entries == 0 {
+            self.smallest_key = record.get_key();
+            self.min_sequence = record.get_timestamp();
+        }
+        self.num_entries += 1;
+        if let Record::Tombstone { .. } = record {
+            self.num_tombstones += 1;
+        }
+        self.raw_key_size += record.key_size() as u64;
+        self.raw_value_size += record.value_size() as u64;
+        self.min_sequence = self.min_sequence.min(record.get_timestamp());
+        self.max_sequence = self.max_sequence.max(record.get_timestamp());
+    }
+
+    /// Encodes the properties as a list of `<name size><name><value size><value>` entries,
+    /// so new properties can be added without breaking older readers
+    pub fn to_bytes(&self, encoding: Encoding) -> Vec<u8> {
+        let properties: Vec<(&str, Vec<u8>)> = vec![
+            ("num_entries", self.num_entries.to_le_bytes().to_vec()),
+            ("num_tombstones", self.num_tombstones.to_le_bytes().to_vec()),
+            (
+                "num_data_blocks",
+                self.num_data_blocks.to_le_bytes().to_vec(),
+            ),
+            ("raw_key_size", self.raw_key_size.to_le_bytes().to_vec()),
+            ("raw_value_size", self.raw_value_size.to_le_bytes().to_vec()),
+            ("smallest_key", self.smallest_key.as_bytes().to_vec()),
+            ("largest_key", self.largest_key.as_bytes().to_vec()),
+            ("min_sequence", self.min_sequence.to_le_bytes().to_vec()),
+            ("max_sequence", self.max_sequence.to_le_bytes().to_vec()),
+            ("creation_time", self.creation_time.to_le_bytes().to_vec()),
+            ("compression", self.compression.as_bytes().to_vec()),
+            ("format_version", self.format_version.to_le_bytes().to_vec()),
+        ];
+
+        let mut bytes = vec![];
+        for (name, value) in properties {
+            encoding.put_usize(&mut bytes, name.len());
+            bytes.extend(name.as_bytes().to_vec());
+            encoding.put_usize(&mut bytes, value.len());
+            bytes.extend(value);
+        }
+        bytes
+    }
+
+    /// Decodes a properties block. Unknown properties are ignored.
+    /// Returns `None` if the block is truncated or a property has an unexpected size
+    pub fn from_bytes(bytes: &[u8], encoding: Encoding) -> Option<TableProperties> {
+        let mut properties = TableProperties::default();
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let name_size = encoding.get_usize(bytes, &mut offset)?;
+            let name_end = offset.checked_add(name_size)?;
+            let name = String::from_utf8_lossy(bytes.get(offset..name_end)?).into_owned();
+            offset = name_end;
+
+            let value_size = encoding.get_usize(bytes, &mut offset)?;
+            let value_end = offset.checked_add(value_size)?;
+            let value = bytes.get(offset..value_end)?;
+            offset = value_end;
+
+            match name.as_str() {
+                "num_entries" => {
+                    properties.num_entries = u64::from_le_bytes(value.try_into().ok()?)
+                }
+                "num_tombstones" => {
+                    properties.num_tombstones = u64::from_le_bytes(value.try_into().ok()?)
+                }
+                "num_data_blocks" => {
+                    properties.num_data_blocks = u64::from_le_bytes(value.try_into().ok()?)
+                }
+                "raw_key_size" => {
+                    properties.raw_key_size = u64::from_le_bytes(value.try_into().ok()?)
+                }
+                "raw_value_size" => {
+                    properties.raw_value_size = u64::from_le_bytes(value.try_into().ok()?)
+                }
+                "smallest_key" => {
+                    properties.smallest_key = String::from_utf8_lossy(value).into_owned()
+                }
+                "largest_key" => {
+                    properties.largest_key = String::from_utf8_lossy(value).into_owned()
+                }
+                "min_sequence" => {
+                    properties.min_sequence = u128::from_le_bytes(value.try_into().ok()?)
+                }
+                "max_sequence" => {
+                    properties.max_sequence = u128::from_le_bytes(value.try_into().ok()?)
+                }
+                "creation_time" => {
+                    properties.creation_time = u64::from_le_bytes(value.try_into().ok()?)
+                }
+                "compression" => {
+                    properties.compression = String::from_utf8_lossy(value).into_owned()
+                }
+                "format_version" => {
+                    properties.format_version = u32::from_le_bytes(value.try_into().ok()?)
+                }
+                _ => {}
+            }
+        }
+        Some(properties)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn from_records() {
+        let records = vec![
+            Record::new("a".to_owned(), "aa".to_owned()),
+            Record::new_tombstone("b".to_owned()),
+            Record::new("c".to_owned(), "c".to_owned()),
+        ];
+
+        let properties = TableProperties::from_records(&records, 1, 1);
+        assert_eq!(properties.num_entries, 3);
+        assert_eq!(properties.num_tombstones, 1);
+        assert_eq!(properties.raw_key_size, 3);
+        assert_eq!(properties.raw_value_size, 3);
+        assert_eq!(properties.smallest_key, "a");
+        assert_eq!(properties.largest_key, "c");
+        assert_eq!(properties.min_sequence, records[0].get_timestamp());
+        assert_eq!(properties.max_sequence, records[2].get_timestamp());
+    }
+
+    #[test]
+    fn round_trip() {
+        let records = vec![Record::new("a".to_owned(), "aa".to_owned())];
+        let properties = TableProperties::from_records(&records, 1, 1);
+
+        for encoding in [Encoding::Legacy, Encoding::Varint] {
+            assert_eq!(
+                TableProperties::from_bytes(&properties.to_bytes(encoding), encoding),
+                Some(properties.clone())
+            );
+        }
+    }
+}
@@ -1,12 +1,8 @@
-use std::{
-    fs::File,
-    io::{BufReader, Read},
-    time::{SystemTime, UNIX_EPOCH},
-};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-const USIZE_BYTES: usize = (usize::BITS / 8) as usize;
-const U128_BYTES: usize = (u128::BITS / 8) as usize;
-const U8_BYTES: usize = (u8::BITS / 8) as usize;
+use super::coding::Encoding;
+
+const U8_BYTES: usize = std::mem::size_of::<u8>();
 
 #[derive(Debug, Clone)]
 pub enum Record {
@@ -49,37 +45,60 @@ impl Record {
             Record::Value { key, .. } => key.to_string(),
         }
     }
-    fn key_size(&self) -> usize {
+
+    pub fn get_timestamp(&self) -> u128 {
+        match self {
+            Record::Tombstone { timestamp, .. } => *timestamp,
+            Record::Value { timestamp, .. } => *timestamp,
+        }
+    }
+
+    pub fn set_timestamp(&mut self, new_timestamp: u128) {
+        match self {
+            Record::Tombstone { timestamp, .. } => *timestamp = new_timestamp,
+            Record::Value { timestamp, .. } => *timestamp = new_timestamp,
+        }
+    }
+
+    pub fn key_size(&self) -> usize {
         match self {
             Record::Tombstone { key, .. } => key.len(),
             Record::Value { key, .. } => key.len(),
         }
     }
-    fn value_size(&self) -> usize {
+    pub fn value_size(&self) -> usize {
         match self {
             Record::Tombstone { .. } => 0,
             Record::Value { value, .. } => value.len(),
         }
     }
 
+    /// Number of bytes the record takes up once encoded with `as_bytes`
     pub fn size(&self) -> usize {
+        let encoding = Encoding::Varint;
+        // u8 for the tombstone flag, followed by key size, timestamp and key
+        let size = U8_BYTES
+            + encoding.usize_length(self.key_size())
+            + encoding.u128_length(self.get_timestamp())
+            + self.key_size();
         match self {
-            Record::Tombstone { .. } => {
-                self.key_size() + U8_BYTES + U128_BYTES + USIZE_BYTES // u8 for tombstone flag, u128 for timestamp and usize for key size
-            }
+            Record::Tombstone { .. } => size,
             Record::Value { .. } => {
-                self.key_size() + self.value_size() + U8_BYTES + U128_BYTES + 2 * USIZE_BYTES
-                // u8 for tombstone flag, u128 for timestamp and 2 * usize for key & value size
+                size + encoding.usize_length(self.value_size()) + self.value_size()
             }
         }
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
+        self.encode(Encoding::Varint)
+    }
+
+    pub fn encode(&self, encoding: Encoding) -> Vec<u8> {
         match self {
             Record::Tombstone { timestamp, key } => {
                 let mut bytes = vec![1];
-                bytes.extend(self.key_size().to_le_bytes().to_vec());
-                bytes.extend(timestamp.to_le_bytes().to_vec());
+                encoding.put_usize(&mut bytes, self.key_size());
+                encoding.put_u128(&mut bytes, *timestamp);
                 bytes.extend(key.as_bytes().to_vec());
                 bytes
             }
@@ -89,61 +108,35 @@ impl Record {
                 value,
             } => {
                 let mut bytes = vec![0];
-                bytes.extend(self.key_size().to_le_bytes().to_vec());
-                bytes.extend(timestamp.to_le_bytes().to_vec());
+                encoding.put_usize(&mut bytes, self.key_size());
+                encoding.put_u128(&mut bytes, *timestamp);
                 bytes.extend(key.as_bytes().to_vec());
-                bytes.extend(self.value_size().to_le_bytes().to_vec());
+                encoding.put_usize(&mut bytes, self.value_size());
                 bytes.extend(value.as_bytes().to_vec());
                 bytes
             }
         }
     }
 
-    pub fn from_reader(buf_reader: &mut BufReader<File>) -> Option<Self> {
-        let mut tombstone_buffer = [0; 1];
-        if let Err(err) = buf_reader.read_exact(&mut tombstone_buffer) {
-            println!("{}", err);
-            return None;
-        }
-
-        let is_tombstone = tombstone_buffer[0];
+    /// Decodes the record starting at `offset`, advancing it past the record.
+    /// Returns `None` if the record is truncated or malformed
+    pub fn decode(bytes: &[u8], offset: &mut usize, encoding: Encoding) -> Option<Self> {
+        let is_tombstone = *bytes.get(*offset)?;
+        *offset += U8_BYTES;
 
-        let mut key_size_buffer = [0; 8]; // usize (8 bytes on x64)
-        if let Err(err) = buf_reader.read_exact(&mut key_size_buffer) {
-            println!("{}", err);
-            return None;
-        }
-        let key_size = usize::from_le_bytes(key_size_buffer);
+        let key_size = encoding.get_usize(bytes, offset)?;
+        let timestamp = encoding.get_u128(bytes, offset)?;
 
-        let mut timestamp_buffer = [0; 16]; // u128 (16 bytes)
-        if let Err(err) = buf_reader.read_exact(&mut timestamp_buffer) {
-            println!("{}", err);
-            return None;
-        }
-        let timestamp = u128::from_le_bytes(timestamp_buffer);
-
-        let mut key_buffer = vec![0; key_size];
-        if let Err(err) = buf_reader.read_exact(&mut key_buffer) {
-            println!("{}", err);
-            return None;
-        }
-        let key = String::from_utf8_lossy(&key_buffer).into_owned();
+        let key_end = offset.checked_add(key_size)?;
+        let key = String::from_utf8_lossy(bytes.get(*offset..key_end)?).into_owned();
+        *offset = key_end;
 
         match is_tombstone {
             0 => {
-                let mut value_size_buffer = [0; 8]; // usize (8 bytes on x64)
-                if let Err(err) = buf_reader.read_exact(&mut value_size_buffer) {
-                    println!("{}", err);
-                    return None;
-                }
-                let value_size = usize::from_le_bytes(value_size_buffer);
-
-                let mut value_buffer = vec![0; value_size];
-                if let Err(err) = buf_reader.read_exact(&mut value_buffer) {
-                    println!("{}", err);
-                    return None;
-                }
-                let value = String::from_utf8_lossy(&value_buffer).into_owned();
+                let value_size = encoding.get_usize(bytes, offset)?;
+                let value_end = offset.checked_add(value_size)?;
+                let value = String::from_utf8_lossy(bytes.get(*offset..value_end)?).into_owned();
+                *offset = value_end;
 
                 Some(Record::Value {
                     timestamp,
@@ -151,9 +144,8 @@ impl Record {
                     value,
                 })
             }
-
             1 => Some(Record::Tombstone { timestamp, key }),
-            _ => unreachable!(),
+            _ => None,
         }
     }
 }
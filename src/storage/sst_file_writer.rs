@@ -0,0 +1,106 @@
+use std::path::Path;
+
+use super::fs::write_atomic;
+use super::properties::TableProperties;
+use super::record::Record;
+use super::sstable::{SSTable, TableOptions};
+use crate::env::Env;
+use crate::error::{Error, Result};
+
+/// Builds an SSTable outside of a database, to be added to one with
+/// `DB::ingest_external_files`. Keys must be added in strictly increasing order.
+///
+/// The records are kept in memory until `finish`, so bulk loads are best split over several
+/// files
+pub struct SstFileWriter {
+    options: TableOptions,
+    records: Vec<Record>,
+}
+
+impl SstFileWriter {
+    /// Use `Options::table_options` to build tables like the database would
+    pub fn new(options: TableOptions) -> SstFileWriter {
+        SstFileWriter {
+            options,
+            records: vec![],
+        }
+    }
+
+    pub fn put(&mut self, key: String, value: String) -> Result<()> {
+        self.add(Record::new(key, value))
+    }
+
+    /// Adds a tombstone, which deletes `key` from the database the table is ingested into
+    pub fn delete(&mut self, key: String) -> Result<()> {
+        self.add(Record::new_tombstone(key))
+    }
+
+    fn add(&mut self, record: Record) -> Result<()> {
+        if let Some(last) = self.records.last() {
+            if record.get_key() <= last.get_key() {
+                return Err(Error::InvalidArgument(format!(
+                    "key {:?} does not sort after {:?}",
+                    record.get_key(),
+                    last.get_key()
+                )));
+            }
+        }
+        self.records.push(record);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Writes the table to `path`, creating its directory if needed. Fails if no keys were
+    /// added
+    pub fn finish(self, env: &dyn Env, path: &Path) -> Result<TableProperties> {
+        if self.records.is_empty() {
+            return Err(Error::InvalidArgument(
+                "can not write an SSTable without keys".to_owned(),
+            ));
+        }
+        let sstable = SSTable::from_records_with_options(self.records, &self.options);
+        if let Some(dir) = path.parent() {
+            env.create_dir_all(dir)?;
+        }
+        write_atomic(env, path, &sstable.to_bytes())?;
+        Ok(sstable.properties().clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::env::MemEnv;
+
+    #[test]
+    fn write() {
+        let env = MemEnv::new();
+        let mut writer = SstFileWriter::new(TableOptions::default());
+        writer.put("a".to_owned(), "1".to_owned()).unwrap();
+        writer.delete("b".to_owned()).unwrap();
+        assert!(writer.put("b".to_owned(), "2".to_owned()).is_err());
+        assert!(writer.put("0".to_owned(), "2".to_owned()).is_err());
+        assert_eq!(writer.len(), 2);
+
+        let path = Path::new("external/bulk.sst");
+        let properties = writer.finish(&env, path).unwrap();
+        assert_eq!(properties.num_entries, 2);
+        assert_eq!(properties.num_tombstones, 1);
+        assert_eq!(properties.smallest_key, "a");
+        assert_eq!(properties.largest_key, "b");
+
+        let mut sstable = SSTable::from_external_file(&env, path).unwrap();
+        sstable.load_from_disk(&env, path).unwrap();
+        assert_eq!(sstable.into_iter().count(), 2);
+
+        let writer = SstFileWriter::new(TableOptions::default());
+        assert!(writer.finish(&env, Path::new("empty.sst")).is_err());
+    }
+}
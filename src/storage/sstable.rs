@@ -17,85 +17,108 @@
 [Footer]                               (fixed size; starts at file_size - sizeof(Footer))
 <end_of_file>
 
+[data block]
+[record 1]
+...
+[record N]
+[crc32 of the records: u32]
+
+[Footer]
+[metaindex handle: u64 offset, u64 size]
+[index handle: u64 offset, u64 size]
+[format version: u32]
+[magic number: u64]
+
+Lengths, offsets and timestamps inside blocks are varints from format version 3. Before that
+they were 8 byte (16 byte for timestamps) little-endian integers, data blocks were padded with
+zeros to BLOCKSIZE bytes and had no checksum.
+
+Format version 0, the first layout, had no properties or metaindex block, and its footer was
+just the index offset and size as 8 byte integers.
+
 */
 
 use std::{
-    fs::{create_dir_all, File, OpenOptions},
-    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    ops::{Bound, RangeBounds},
     path::{Path, PathBuf},
-    time::{SystemTime, UNIX_EPOCH},
     vec,
 };
 
+use super::coding::Encoding;
+use super::compression::Compression;
+use super::fs::{ensure_absent, write_atomic};
+use crate::env::{Env, RandomAccessFile};
+use crate::error::{Error, Result};
+
+use super::properties::TableProperties;
 use super::record::Record;
 const BLOCKSIZE: usize = 32000;
-const USIZE_BYTES: usize = (usize::BITS / 8) as usize;
-const U128_BYTES: usize = (u128::BITS / 8) as usize;
+const U64_BYTES: usize = (u64::BITS / 8) as usize;
+const U32_BYTES: usize = (u32::BITS / 8) as usize;
+/// crc32 stored after every data block
+const BLOCK_TRAILER_SIZE: usize = U32_BYTES;
+/// Version of the on-disk layout written by `SSTable::to_bytes`
+pub const FORMAT_VERSION: u32 = 3;
+/// Oldest format version that can still be read
+pub const OLDEST_SUPPORTED_FORMAT_VERSION: u32 = 0;
+/// Stored at the very end of every SSTable to tell them apart from other files
+const MAGIC_NUMBER: u64 = 0x7273_6b76_7373_7462; // "rskvsstb"
+/// Name of the properties block in the metaindex block
+const PROPERTIES_BLOCK: &str = "rust_kv_store.properties";
+
+/// Integer encoding used by the blocks of a given format version
+fn encoding(format_version: u32) -> Encoding {
+    if format_version < 3 {
+        Encoding::Legacy
+    } else {
+        Encoding::Varint
+    }
+}
 
 pub struct Block {
     records: Vec<Record>,
 }
 impl Block {
-    pub fn from_bytes(bytes: &[u8; BLOCKSIZE]) -> Block {
+    /// Returns `None` if a record in the block is truncated or malformed
+    pub fn from_bytes(bytes: &[u8], encoding: Encoding) -> Option<Block> {
         let mut offset = 0;
-        let mut entries = vec![];
-        while offset < BLOCKSIZE {
-            if offset + USIZE_BYTES >= BLOCKSIZE {
+        let mut records = vec![];
+        while offset < bytes.len() {
+            // legacy blocks are padded with \0 to a fixed size of BLOCKSIZE
+            if encoding == Encoding::Legacy && bytes[offset..].iter().all(|b| *b == 0) {
                 break;
             }
 
-            let is_tombstone = bytes[offset..offset + 1][0];
-            offset += 1;
-
-            let key_size =
-                usize::from_le_bytes(bytes[offset..offset + USIZE_BYTES].try_into().unwrap());
-            offset += USIZE_BYTES;
-
-            // we have reached the end of data in the block.
-            // The rest is padded with /0 and will have key_size 0
-            if key_size == 0 {
-                break;
-            }
+            records.push(Record::decode(bytes, &mut offset, encoding)?);
+        }
+        Some(Block { records })
+    }
 
-            let timestamp =
-                u128::from_le_bytes(bytes[offset..offset + U128_BYTES].try_into().unwrap());
-            offset += U128_BYTES;
-
-            let key = String::from_utf8_lossy(&bytes[offset..offset + key_size]).into_owned();
-            offset += key_size;
-
-            let entry = match is_tombstone {
-                1 => Record::Tombstone { timestamp, key },
-                0 => {
-                    let value_size = usize::from_le_bytes(
-                        bytes[offset..offset + USIZE_BYTES].try_into().unwrap(),
-                    );
-                    offset += USIZE_BYTES;
-
-                    let value =
-                        String::from_utf8_lossy(&bytes[offset..offset + value_size]).into_owned();
-                    offset += value_size;
-                    Record::Value {
-                        timestamp,
-                        key,
-                        value,
-                    }
-                }
-                _ => unreachable!(),
-            };
+    pub fn to_bytes(&self, encoding: Encoding) -> Vec<u8> {
+        let mut bytes: Vec<u8> = self
+            .records
+            .iter()
+            .flat_map(|r| r.encode(encoding))
+            .collect();
 
-            entries.push(entry);
+        if encoding == Encoding::Legacy {
+            bytes.resize(BLOCKSIZE, 0);
         }
-        Block { records: entries }
-    }
 
-    pub fn to_bytes(&self) -> [u8; BLOCKSIZE] {
-        let mut bytes: Vec<u8> = self.records.iter().flat_map(|r| r.as_bytes()).collect();
+        bytes
+    }
 
-        // pad bytes with 0 to a fixed size of BLOCKSIZE
-        bytes.resize(BLOCKSIZE, 0);
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
 
-        bytes.try_into().unwrap()
+    /// Returns the record for `key`, including tombstones
+    pub fn get_record(&self, key: &String) -> Option<&Record> {
+        let idx = self
+            .records
+            .binary_search_by_key(key, |e| e.get_key())
+            .ok()?;
+        self.records.get(idx)
     }
 
     pub fn get_value(&self, key: &String) -> Option<String> {
@@ -115,8 +138,7 @@ impl Block {
 
 struct IndexEntry {
     key: String,
-    offset: usize,
-    block_index: usize,
+    handle: BlockHandle,
 }
 #[derive(Default)]
 struct IndexBlock {
@@ -128,96 +150,404 @@ impl IndexBlock {
         // index = |a|b|d|f, key = c  -> 1
         match self.entries.binary_search_by_key(&key, |e| &e.key) {
             Ok(v) => v,
-            Err(v) => v - 1,
+            Err(v) => v.saturating_sub(1),
         }
     }
-    fn get_block_offset(&self, key: &String) -> usize {
-        // index = |a|b|d|f, key = c  -> 1
-        match self.entries.binary_search_by_key(&key, |e| &e.key) {
-            Ok(v) => self.entries.get(v).unwrap().offset,
-            Err(v) if v == 0 => self.entries.get(v).unwrap().offset,
-            Err(v) => self.entries.get(v - 1).unwrap().offset,
-        }
+    fn get_block_handle(&self, key: &String) -> Option<BlockHandle> {
+        self.entries
+            .get(self.get_block_index(key))
+            .map(|entry| entry.handle)
     }
     fn new() -> IndexBlock {
         IndexBlock { entries: vec![] }
     }
-    fn to_bytes(&self) -> Vec<u8> {
+    fn to_bytes(&self, encoding: Encoding) -> Vec<u8> {
         let mut bytes = vec![];
-        for index in self.entries.iter() {
-            bytes.extend(index.key.len().to_le_bytes().to_vec());
+        for (block_index, index) in self.entries.iter().enumerate() {
+            encoding.put_usize(&mut bytes, index.key.len());
             bytes.extend(index.key.as_bytes().to_vec());
-            bytes.extend(index.offset.to_le_bytes().to_vec());
-            bytes.extend(index.block_index.to_le_bytes().to_vec());
+            encoding.put_usize(&mut bytes, index.handle.offset);
+            match encoding {
+                // legacy blocks all have the same size, so the index stored the block number
+                Encoding::Legacy => encoding.put_usize(&mut bytes, block_index),
+                Encoding::Varint => encoding.put_usize(&mut bytes, index.handle.size),
+            }
         }
         bytes
     }
+
+    /// Returns `None` if the block is truncated
+    fn from_bytes(bytes: &[u8], encoding: Encoding) -> Option<IndexBlock> {
+        let mut entries = vec![];
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let key_size = encoding.get_usize(bytes, &mut offset)?;
+            let key_end = offset.checked_add(key_size)?;
+            let key = String::from_utf8_lossy(bytes.get(offset..key_end)?).into_owned();
+            offset = key_end;
+
+            let block_offset = encoding.get_usize(bytes, &mut offset)?;
+            let size = match encoding {
+                Encoding::Legacy => {
+                    encoding.get_usize(bytes, &mut offset)?;
+                    BLOCKSIZE
+                }
+                Encoding::Varint => encoding.get_usize(bytes, &mut offset)?,
+            };
+
+            entries.push(IndexEntry {
+                key,
+                handle: BlockHandle {
+                    offset: block_offset,
+                    size,
+                },
+            })
+        }
+        Some(IndexBlock { entries })
+    }
+}
+
+/// Location of a block within an SSTable file
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockHandle {
+    pub offset: usize,
+    pub size: usize,
+}
+
+impl BlockHandle {
+    fn encode(self, bytes: &mut Vec<u8>, encoding: Encoding) {
+        encoding.put_usize(bytes, self.offset);
+        encoding.put_usize(bytes, self.size);
+    }
+
+    fn decode(bytes: &[u8], offset: &mut usize, encoding: Encoding) -> Option<BlockHandle> {
+        Some(BlockHandle {
+            offset: encoding.get_usize(bytes, offset)?,
+            size: encoding.get_usize(bytes, offset)?,
+        })
+    }
+
+    fn end(self) -> Option<usize> {
+        self.offset.checked_add(self.size)
+    }
 }
 
+/// Maps the name of each meta block to its location in the file
 #[derive(Default)]
-struct Footer {
-    index_offset: usize,
-    index_size: usize,
+struct MetaIndexBlock {
+    entries: Vec<(String, BlockHandle)>,
+}
+
+impl MetaIndexBlock {
+    fn get(&self, name: &str) -> Option<BlockHandle> {
+        self.entries
+            .iter()
+            .find(|(entry_name, _)| entry_name == name)
+            .map(|(_, handle)| *handle)
+    }
+
+    fn to_bytes(&self, encoding: Encoding) -> Vec<u8> {
+        let mut bytes = vec![];
+        for (name, handle) in self.entries.iter() {
+            encoding.put_usize(&mut bytes, name.len());
+            bytes.extend(name.as_bytes().to_vec());
+            handle.encode(&mut bytes, encoding);
+        }
+        bytes
+    }
+
+    /// Returns `None` if the block is truncated
+    fn from_bytes(bytes: &[u8], encoding: Encoding) -> Option<MetaIndexBlock> {
+        let mut entries = vec![];
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let name_size = encoding.get_usize(bytes, &mut offset)?;
+            let name_end = offset.checked_add(name_size)?;
+            let name = String::from_utf8_lossy(bytes.get(offset..name_end)?).into_owned();
+            offset = name_end;
+            let handle = BlockHandle::decode(bytes, &mut offset, encoding)?;
+            entries.push((name, handle));
+        }
+        Some(MetaIndexBlock { entries })
+    }
+}
+
+/// Locations of the metaindex and index blocks, stored at the end of the file
+#[derive(Default, Debug, Clone)]
+pub struct Footer {
+    pub metaindex_handle: BlockHandle,
+    pub index_handle: BlockHandle,
+    pub format_version: u32,
 }
 
 impl Footer {
+    /// metaindex handle, index handle, format version and magic number
+    const ENCODED_LENGTH: usize = 4 * U64_BYTES + U32_BYTES + U64_BYTES;
+    /// Format version 1 stored the index and metaindex handles as 8 byte integers, without
+    /// a version or magic number
+    const LEGACY_ENCODED_LENGTH: usize = 4 * U64_BYTES;
+    /// Format version 0 only stored the index handle
+    const VERSION_0_ENCODED_LENGTH: usize = 2 * U64_BYTES;
+
     fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = vec![];
-        bytes.extend(self.index_offset.to_le_bytes().to_vec());
-        bytes.extend(self.index_size.to_le_bytes().to_vec());
+        if self.format_version == 0 {
+            for value in [self.index_handle.offset, self.index_handle.size] {
+                bytes.extend((value as u64).to_le_bytes().to_vec());
+            }
+            return bytes;
+        }
+        if self.format_version == 1 {
+            for value in [
+                self.index_handle.offset,
+                self.index_handle.size,
+                self.metaindex_handle.offset,
+                self.metaindex_handle.size,
+            ] {
+                bytes.extend((value as u64).to_le_bytes().to_vec());
+            }
+            return bytes;
+        }
+
+        for value in [
+            self.metaindex_handle.offset,
+            self.metaindex_handle.size,
+            self.index_handle.offset,
+            self.index_handle.size,
+        ] {
+            bytes.extend((value as u64).to_le_bytes().to_vec());
+        }
+        bytes.extend(self.format_version.to_le_bytes().to_vec());
+        bytes.extend(MAGIC_NUMBER.to_le_bytes().to_vec());
         bytes
     }
+
+    /// Reads the footer at the end of `file`, rejecting files that are not SSTables or are
+    /// written in a format version this build does not know about.
+    fn from_file(file: &dyn RandomAccessFile, file_path: &Path) -> Result<Footer> {
+        let file_size = file.size()? as usize;
+
+        if file_size >= Footer::ENCODED_LENGTH {
+            let mut footer_buffer = [0; Footer::ENCODED_LENGTH];
+            file.read_exact_at(
+                (file_size - Footer::ENCODED_LENGTH) as u64,
+                &mut footer_buffer,
+            )?;
+
+            let magic = u64::from_le_bytes(
+                footer_buffer[Footer::ENCODED_LENGTH - U64_BYTES..]
+                    .try_into()
+                    .unwrap(),
+            );
+            if magic == MAGIC_NUMBER {
+                let read_u64 = |i: usize| {
+                    u64::from_le_bytes(
+                        footer_buffer[i * U64_BYTES..(i + 1) * U64_BYTES]
+                            .try_into()
+                            .unwrap(),
+                    ) as usize
+                };
+                let footer = Footer {
+                    metaindex_handle: BlockHandle {
+                        offset: read_u64(0),
+                        size: read_u64(1),
+                    },
+                    index_handle: BlockHandle {
+                        offset: read_u64(2),
+                        size: read_u64(3),
+                    },
+                    format_version: u32::from_le_bytes(
+                        footer_buffer[4 * U64_BYTES..4 * U64_BYTES + U32_BYTES]
+                            .try_into()
+                            .unwrap(),
+                    ),
+                };
+
+                if !(OLDEST_SUPPORTED_FORMAT_VERSION..=FORMAT_VERSION)
+                    .contains(&footer.format_version)
+                {
+                    return Err(Error::NotSupported(format!(
+                        "{} uses SSTable format version {}, but only versions {} to {} are supported",
+                        file_path.display(),
+                        footer.format_version,
+                        OLDEST_SUPPORTED_FORMAT_VERSION,
+                        FORMAT_VERSION
+                    )));
+                }
+                if footer.is_valid(file_size - Footer::ENCODED_LENGTH) {
+                    return Ok(footer);
+                }
+                return Err(Error::corruption(
+                    file_path,
+                    Some((file_size - Footer::ENCODED_LENGTH) as u64),
+                    "footer has block handles pointing outside the file",
+                ));
+            }
+        }
+
+        // files without a magic number may have been written by format version 1, which we
+        // only accept if the block handles describe exactly the layout that version produced
+        if file_size >= Footer::LEGACY_ENCODED_LENGTH {
+            let mut footer_buffer = [0; Footer::LEGACY_ENCODED_LENGTH];
+            file.read_exact_at(
+                (file_size - Footer::LEGACY_ENCODED_LENGTH) as u64,
+                &mut footer_buffer,
+            )?;
+
+            let read_u64 = |i: usize| {
+                u64::from_le_bytes(
+                    footer_buffer[i * U64_BYTES..(i + 1) * U64_BYTES]
+                        .try_into()
+                        .unwrap(),
+                ) as usize
+            };
+            let footer = Footer {
+                index_handle: BlockHandle {
+                    offset: read_u64(0),
+                    size: read_u64(1),
+                },
+                metaindex_handle: BlockHandle {
+                    offset: read_u64(2),
+                    size: read_u64(3),
+                },
+                format_version: 1,
+            };
+            let data_size = file_size - Footer::LEGACY_ENCODED_LENGTH;
+            if footer.is_valid(data_size)
+                && footer.index_handle.offset.is_multiple_of(BLOCKSIZE)
+                && footer.metaindex_handle.end() == Some(data_size)
+            {
+                return Ok(footer);
+            }
+        }
+
+        // format version 0 ends with the index, right after the data blocks
+        if file_size >= Footer::VERSION_0_ENCODED_LENGTH {
+            let mut footer_buffer = [0; Footer::VERSION_0_ENCODED_LENGTH];
+            file.read_exact_at(
+                (file_size - Footer::VERSION_0_ENCODED_LENGTH) as u64,
+                &mut footer_buffer,
+            )?;
+
+            let read_u64 = |i: usize| {
+                u64::from_le_bytes(
+                    footer_buffer[i * U64_BYTES..(i + 1) * U64_BYTES]
+                        .try_into()
+                        .unwrap(),
+                ) as usize
+            };
+            let footer = Footer {
+                index_handle: BlockHandle {
+                    offset: read_u64(0),
+                    size: read_u64(1),
+                },
+                metaindex_handle: BlockHandle::default(),
+                format_version: 0,
+            };
+            let data_size = file_size - Footer::VERSION_0_ENCODED_LENGTH;
+            if footer.index_handle.offset.is_multiple_of(BLOCKSIZE)
+                && footer.index_handle.end() == Some(data_size)
+            {
+                return Ok(footer);
+            }
+        }
+
+        Err(Error::corruption(
+            file_path,
+            None,
+            "not an SSTable: missing magic number",
+        ))
+    }
+
+    /// Checks that the index and metaindex blocks lie within the first `data_size` bytes
+    fn is_valid(&self, data_size: usize) -> bool {
+        [self.index_handle, self.metaindex_handle]
+            .iter()
+            .all(|handle| matches!(handle.end(), Some(end) if end <= data_size))
+    }
+}
+
+/// Settings used when building a new SSTable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TableOptions {
+    /// Target size of a data block before compression
+    pub block_size: usize,
+    pub compression: Compression,
+}
+
+impl Default for TableOptions {
+    fn default() -> Self {
+        TableOptions {
+            block_size: BLOCKSIZE,
+            compression: Compression::None,
+        }
+    }
 }
 
 #[derive(Default)]
 pub struct SSTable {
     data_blocks: Vec<Block>,
     index_block: IndexBlock,
+    properties: TableProperties,
     footer: Footer,
+    compression: Compression,
     pub level: u8,
+    /// Timestamp every record is read with, in place of the one it was written with. Set for
+    /// tables that were ingested into a database as they were
+    global_sequence: Option<u128>,
 }
 
 impl SSTable {
     pub fn get_value(&self, key: &String) -> Option<String> {
         let block_idx = self.index_block.get_block_index(key);
-        self.data_blocks.get(block_idx).unwrap().get_value(key)
+        self.data_blocks.get(block_idx)?.get_value(key)
     }
 
     pub fn from_records(records: Vec<Record>) -> Self {
+        SSTable::from_records_with_options(records, &TableOptions::default())
+    }
+
+    pub fn from_records_with_options(records: Vec<Record>, options: &TableOptions) -> Self {
+        let mut properties = TableProperties::new(FORMAT_VERSION);
         let mut offset = 0;
         let mut data_blocks = vec![];
-        let mut current_block = 0;
+        let mut block_offset = 0;
         let mut block_records: Vec<Record> = vec![];
         let mut index_block = IndexBlock::new();
 
         for record in records {
-            if offset + record.size() > BLOCKSIZE {
-                let min_key = block_records.get(0).unwrap().get_key();
+            if !block_records.is_empty() && offset + record.size() > options.block_size {
+                let min_key = block_records.first().unwrap().get_key();
                 index_block.entries.push(IndexEntry {
                     key: min_key,
-                    offset: BLOCKSIZE * current_block,
-                    block_index: current_block,
+                    handle: BlockHandle {
+                        offset: block_offset,
+                        size: offset,
+                    },
                 });
 
                 let block = Block {
                     records: block_records,
                 };
                 data_blocks.push(block);
+                block_offset += offset + BLOCK_TRAILER_SIZE;
                 offset = 0;
-                current_block += 1;
                 block_records = vec![];
             }
             offset += record.size();
+            properties.add(&record);
             block_records.push(record);
         }
 
         // dump remaining records into block, with index
         if !block_records.is_empty() {
-            let min_key = block_records.get(0).unwrap().get_key();
+            let min_key = block_records.first().unwrap().get_key();
             index_block.entries.push(IndexEntry {
                 key: min_key,
-                offset: BLOCKSIZE * current_block,
-                block_index: current_block,
+                handle: BlockHandle {
+                    offset: block_offset,
+                    size: offset,
+                },
             });
 
             let block = Block {
@@ -226,149 +556,208 @@ impl SSTable {
             data_blocks.push(block);
         }
 
-        let footer = Footer {
-            index_offset: data_blocks.len() * BLOCKSIZE,
-            index_size: index_block
-                .entries
-                .iter()
-                .map(|x| x.key.len() + 3 * USIZE_BYTES)
-                .sum::<usize>(),
-        };
+        properties.num_data_blocks = data_blocks.len() as u64;
+        properties.largest_key = data_blocks
+            .last()
+            .and_then(|block| block.records.last())
+            .map(|record| record.get_key())
+            .unwrap_or_default();
+        properties.compression = options.compression.name().to_owned();
 
         SSTable {
             data_blocks,
             index_block,
-            footer,
+            properties,
+            footer: Footer {
+                format_version: FORMAT_VERSION,
+                ..Default::default()
+            },
+            compression: options.compression,
             level: 0,
+            global_sequence: None,
         }
     }
 
+    pub fn properties(&self) -> &TableProperties {
+        &self.properties
+    }
+
+    /// Version of the on-disk format this table was read from or will be written in
+    pub fn format_version(&self) -> u32 {
+        self.footer.format_version
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
+        let encoding = encoding(self.footer.format_version);
         let mut bytes = vec![];
+
+        let mut index_block = IndexBlock::new();
         for block in self.data_blocks.iter() {
-            bytes.extend(block.to_bytes());
+            let block_bytes = match encoding {
+                Encoding::Legacy => block.to_bytes(encoding),
+                Encoding::Varint => self.compression.compress(&block.to_bytes(encoding)),
+            };
+            index_block.entries.push(IndexEntry {
+                key: block.records.first().unwrap().get_key(),
+                handle: BlockHandle {
+                    offset: bytes.len(),
+                    size: block_bytes.len(),
+                },
+            });
+            bytes.extend(&block_bytes);
+            if encoding == Encoding::Varint {
+                bytes.extend(crc32fast::hash(&block_bytes).to_le_bytes());
+            }
         }
-        bytes.extend(self.index_block.to_bytes());
-        bytes.extend(self.footer.to_bytes());
 
-        bytes
-    }
-
-    pub fn write(&self, path: &Path) -> Result<PathBuf, std::io::Error> {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_micros();
-        create_dir_all(path)?;
-        let path =
-            Path::new(path).join(timestamp.to_string() + "_" + &self.level.to_string() + ".ss");
-
-        let file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(&path)
-            .unwrap();
-        let mut buf_writer = BufWriter::new(file);
-        buf_writer.write_all(&self.to_bytes())?;
-        Ok(path)
-    }
+        let index_bytes = index_block.to_bytes(encoding);
+        let index_handle = BlockHandle {
+            offset: bytes.len(),
+            size: index_bytes.len(),
+        };
+        bytes.extend(index_bytes);
 
-    /// Returns a `SSTable` with the footer and index populated.
-    /// This will not load any data blocks, as this is done when querying
-    pub fn from_disk(file_path: &Path) -> Result<SSTable, std::io::Error> {
-        // filename is <timestamp>_<level>.ss
-        // Unfortunately it's extremely ugly to extract the level this way
-        let level: u8 = file_path
-            .file_stem()
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .split_once('_')
-            .unwrap()
-            .1
-            .parse()
-            .unwrap();
+        if self.footer.format_version == 0 {
+            let footer = Footer {
+                index_handle,
+                ..Default::default()
+            };
+            bytes.extend(footer.to_bytes());
+            return bytes;
+        }
 
-        let mut file = File::open(file_path).unwrap();
-        file.seek(SeekFrom::End(-(2 * USIZE_BYTES as i64))).unwrap();
+        let properties = TableProperties {
+            format_version: self.footer.format_version,
+            ..self.properties.clone()
+        };
+        let properties_bytes = properties.to_bytes(encoding);
+        let metaindex_block = MetaIndexBlock {
+            entries: vec![(
+                PROPERTIES_BLOCK.to_owned(),
+                BlockHandle {
+                    offset: bytes.len(),
+                    size: properties_bytes.len(),
+                },
+            )],
+        };
+        bytes.extend(properties_bytes);
 
-        let mut footer_buffer = [0; 2 * USIZE_BYTES];
-        if let Err(err) = file.read_exact(&mut footer_buffer) {
-            print!("{}", err);
+        let metaindex_bytes = metaindex_block.to_bytes(encoding);
+        let metaindex_handle = BlockHandle {
+            offset: bytes.len(),
+            size: metaindex_bytes.len(),
         };
+        bytes.extend(metaindex_bytes);
+
         let footer = Footer {
-            index_offset: usize::from_le_bytes(footer_buffer[0..USIZE_BYTES].try_into().unwrap()),
-            index_size: usize::from_le_bytes(
-                footer_buffer[USIZE_BYTES..2 * USIZE_BYTES]
-                    .try_into()
-                    .unwrap(),
-            ),
+            metaindex_handle,
+            index_handle,
+            format_version: self.footer.format_version,
         };
+        bytes.extend(footer.to_bytes());
 
-        file.seek(SeekFrom::Start(footer.index_offset.try_into().unwrap()))?;
+        bytes
+    }
 
-        let mut index_buffer = vec![0; footer.index_size];
-        file.read_exact(&mut index_buffer)?;
+    /// Writes the table to a new file in the directory `path`, named after the clock of `env`.
+    /// Fails if a table of the same level was written there in the same microsecond
+    pub fn write(&mut self, env: &dyn Env, path: &Path) -> Result<PathBuf> {
+        self.write_numbered(env, path, env.now().as_micros() as u64)
+    }
 
-        let mut index_entries = vec![];
-        let mut index_offset = 0;
-        while index_offset < footer.index_size {
-            let key_size = usize::from_le_bytes(
-                index_buffer[index_offset..index_offset + USIZE_BYTES]
-                    .try_into()
-                    .unwrap(),
-            );
-            index_offset += USIZE_BYTES;
-            let key = String::from_utf8_lossy(
-                index_buffer[index_offset..index_offset + key_size]
-                    .try_into()
-                    .unwrap(),
-            )
-            .to_string();
-            index_offset += key_size;
+    /// Writes the table to the file `<number>_<level>.ss` in the directory `path`, which must
+    /// not exist yet. Its creation time is taken from the clock of `env`
+    pub fn write_numbered(&mut self, env: &dyn Env, path: &Path, number: u64) -> Result<PathBuf> {
+        self.properties.creation_time = env.now().as_micros() as u64;
+        env.create_dir_all(path)?;
+        let path = path.join(SSTable::file_name(number, self.level));
+        ensure_absent(env, &path)?;
 
-            let offset = usize::from_le_bytes(
-                index_buffer[index_offset..index_offset + USIZE_BYTES]
-                    .try_into()
-                    .unwrap(),
-            );
-            index_offset += USIZE_BYTES;
+        // never expose a partially written table under its final name
+        write_atomic(env, &path, &self.to_bytes())?;
+        Ok(path)
+    }
 
-            let block_index = usize::from_le_bytes(
-                index_buffer[index_offset..index_offset + USIZE_BYTES]
-                    .try_into()
-                    .unwrap(),
-            );
-            index_offset += USIZE_BYTES;
+    /// Name of the table file numbered `number` at `level`
+    pub fn file_name(number: u64, level: u8) -> String {
+        format!("{}_{}.ss", number, level)
+    }
 
-            index_entries.push(IndexEntry {
-                key,
-                offset,
-                block_index,
+    /// Extracts the level from a file name of the form <number>_<level>.ss
+    pub fn level_from_path(file_path: &Path) -> Result<u8> {
+        file_path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.split_once('_'))
+            .and_then(|(_, level)| level.parse().ok())
+            .ok_or_else(|| {
+                Error::InvalidArgument(format!(
+                    "{} is not named <number>_<level>.ss",
+                    file_path.display()
+                ))
             })
-        }
+    }
 
-        let index_block = IndexBlock {
-            entries: index_entries,
+    /// Returns a `SSTable` with the footer and index populated.
+    /// This will not load any data blocks, as this is done when querying
+    pub fn from_disk(env: &dyn Env, file_path: &Path) -> Result<SSTable> {
+        SSTable::open(env, file_path, SSTable::level_from_path(file_path)?)
+    }
+
+    /// Like `from_disk`, for a table that is not part of a database, which can have any name
+    pub fn from_external_file(env: &dyn Env, file_path: &Path) -> Result<SSTable> {
+        SSTable::open(env, file_path, 0)
+    }
+
+    fn open(env: &dyn Env, file_path: &Path, level: u8) -> Result<SSTable> {
+        let file = env.open(file_path)?;
+        let footer = Footer::from_file(file.as_ref(), file_path)?;
+        let encoding = encoding(footer.format_version);
+
+        let metaindex_buffer = read_block(file.as_ref(), footer.metaindex_handle)?;
+        let metaindex_block = MetaIndexBlock::from_bytes(&metaindex_buffer, encoding)
+            .ok_or_else(|| corruption(file_path, "metaindex block", footer.metaindex_handle))?;
+
+        let properties = match metaindex_block.get(PROPERTIES_BLOCK) {
+            Some(handle) => {
+                let properties_buffer = read_block(file.as_ref(), handle)?;
+                TableProperties::from_bytes(&properties_buffer, encoding)
+                    .ok_or_else(|| corruption(file_path, "properties block", handle))?
+            }
+            None => TableProperties {
+                compression: Compression::None.name().to_owned(),
+                ..Default::default()
+            },
         };
+        let compression = Compression::from_name(&properties.compression)?;
+
+        let index_buffer = read_block(file.as_ref(), footer.index_handle)?;
+        let index_block = IndexBlock::from_bytes(&index_buffer, encoding)
+            .ok_or_else(|| corruption(file_path, "index block", footer.index_handle))?;
 
         Ok(SSTable {
             data_blocks: vec![],
             index_block,
+            properties,
             footer,
+            compression,
             level,
+            global_sequence: None,
         })
     }
 
-    pub fn load_from_disk(&mut self, file_path: &Path) -> Result<(), std::io::Error> {
-        let mut file = File::open(file_path).unwrap();
-
-        for _block in 0..self.footer.index_offset / BLOCKSIZE {
-            let mut block_buffer = [0; BLOCKSIZE];
+    /// Reads every record with the timestamp `sequence`, see `DB::ingest_external_files`
+    pub fn set_global_sequence(&mut self, sequence: u128) {
+        self.global_sequence = Some(sequence);
+        self.properties.min_sequence = sequence;
+        self.properties.max_sequence = sequence;
+    }
 
-            file.read_exact(&mut block_buffer)?;
+    pub fn load_from_disk(&mut self, env: &dyn Env, file_path: &Path) -> Result<()> {
+        let file = env.open(file_path)?;
 
-            let block = Block::from_bytes(&block_buffer);
+        for entry in self.index_block.entries.iter() {
+            let block = self.read_data_block(file.as_ref(), file_path, entry.handle)?;
             self.data_blocks.push(block);
         }
 
@@ -377,21 +766,129 @@ impl SSTable {
 
     pub fn get(
         &self,
+        env: &dyn Env,
         file_path: &Path,
         input_key: &String,
-    ) -> Result<Option<String>, std::io::Error> {
-        let block_offset = self.index_block.get_block_offset(input_key);
-
-        let mut file = File::open(file_path).unwrap();
-
-        let mut block_buffer = [0; BLOCKSIZE];
-        file.seek(SeekFrom::Start(block_offset.try_into().unwrap()))?;
-        file.read_exact(&mut block_buffer)?;
+    ) -> Result<Option<String>> {
+        let block_handle = match self.block_handle(input_key) {
+            Some(handle) => handle,
+            None => return Ok(None),
+        };
 
-        let block = Block::from_bytes(&block_buffer);
+        let block = self.read_block(env, file_path, block_handle)?;
         // TODO: Result<Option<>> is kind of ugly
         Ok(block.get_value(input_key))
     }
+
+    /// Returns the location of the data block that may contain `key`
+    pub fn block_handle(&self, key: &String) -> Option<BlockHandle> {
+        self.index_block.get_block_handle(key)
+    }
+
+    pub fn footer(&self) -> &Footer {
+        &self.footer
+    }
+
+    /// Returns the first key and the location of every data block, in key order
+    pub fn index_entries(&self) -> impl Iterator<Item = (&str, BlockHandle)> {
+        self.index_block
+            .entries
+            .iter()
+            .map(|entry| (entry.key.as_str(), entry.handle))
+    }
+
+    /// Integer encoding of the blocks, which depends on the format version
+    pub fn encoding(&self) -> Encoding {
+        encoding(self.footer.format_version)
+    }
+
+    /// Returns the locations of the data blocks that may contain keys in `range`, in key order
+    pub fn block_handles<R: RangeBounds<String>>(&self, range: &R) -> Vec<BlockHandle> {
+        let first = match range.start_bound() {
+            Bound::Included(key) | Bound::Excluded(key) => self.index_block.get_block_index(key),
+            Bound::Unbounded => 0,
+        };
+        // a block holds the keys from its index key up to the index key of the next block
+        self.index_block
+            .entries
+            .iter()
+            .skip(first)
+            .take_while(|entry| match range.end_bound() {
+                Bound::Included(key) => entry.key <= *key,
+                Bound::Excluded(key) => entry.key < *key,
+                Bound::Unbounded => true,
+            })
+            .map(|entry| entry.handle)
+            .collect()
+    }
+
+    /// Reads and decodes the data block at `handle`
+    pub fn read_block(
+        &self,
+        env: &dyn Env,
+        file_path: &Path,
+        handle: BlockHandle,
+    ) -> Result<Block> {
+        let file = env.open(file_path)?;
+        self.read_data_block(file.as_ref(), file_path, handle)
+    }
+
+    /// Reads and decodes a data block, verifying its checksum
+    fn read_data_block(
+        &self,
+        file: &dyn RandomAccessFile,
+        file_path: &Path,
+        handle: BlockHandle,
+    ) -> Result<Block> {
+        let encoding = encoding(self.footer.format_version);
+        let block_buffer = match encoding {
+            Encoding::Legacy => read_block(file, handle)?,
+            Encoding::Varint => {
+                let mut buffer = read_block(
+                    file,
+                    BlockHandle {
+                        offset: handle.offset,
+                        size: handle.size + BLOCK_TRAILER_SIZE,
+                    },
+                )?;
+                let checksum =
+                    u32::from_le_bytes(buffer.split_off(handle.size)[..].try_into().unwrap());
+                if crc32fast::hash(&buffer) != checksum {
+                    return Err(corruption(
+                        file_path,
+                        "data block (checksum mismatch)",
+                        handle,
+                    ));
+                }
+                self.compression.decompress(buffer).ok_or_else(|| {
+                    corruption(file_path, "data block (decompression failed)", handle)
+                })?
+            }
+        };
+
+        let mut block = Block::from_bytes(&block_buffer, encoding)
+            .ok_or_else(|| corruption(file_path, "data block", handle))?;
+        if let Some(sequence) = self.global_sequence {
+            for record in block.records.iter_mut() {
+                record.set_timestamp(sequence);
+            }
+        }
+        Ok(block)
+    }
+}
+
+fn read_block(file: &dyn RandomAccessFile, handle: BlockHandle) -> Result<Vec<u8>> {
+    let mut buffer = vec![0; handle.size];
+    file.read_exact_at(handle.offset as u64, &mut buffer)?;
+    Ok(buffer)
+}
+
+fn corruption(file_path: &Path, block: &str, handle: BlockHandle) -> Error {
+    Error::corruption(
+        file_path,
+        Some(handle.offset as u64),
+        format!("corrupt {}", block),
+    )
 }
 
 pub struct SSTableIter {
@@ -433,7 +930,9 @@ mod test {
     use crate::storage::memtable::MemTable;
 
     use super::*;
-    use std::{fs::remove_dir_all, mem::size_of_val};
+    use crate::env::MemEnv;
+    use itertools::Itertools;
+    use std::mem::size_of_val;
 
     #[test]
     fn block_from_bytes() {
@@ -444,10 +943,12 @@ mod test {
             ],
         };
 
-        let bytes = block.to_bytes();
+        for encoding in [Encoding::Legacy, Encoding::Varint] {
+            let bytes = block.to_bytes(encoding);
 
-        let new_block = Block::from_bytes(&bytes);
-        assert_eq!(block.records, new_block.records)
+            let new_block = Block::from_bytes(&bytes, encoding).unwrap();
+            assert_eq!(block.records, new_block.records)
+        }
     }
 
     #[test]
@@ -505,21 +1006,261 @@ mod test {
             );
         }
 
-        let new_sstable = SSTable::from_records(mem_table.to_records());
+        let mut new_sstable = SSTable::from_records(mem_table.to_records());
 
-        let path = Path::new("./tests/sstable/output/get_from_disk");
-        let ss_path = new_sstable.write(path).unwrap();
+        let env = MemEnv::new();
+        let path = Path::new("get_from_disk");
+        let ss_path = new_sstable.write(&env, path).unwrap();
 
-        let ss_table = SSTable::from_disk(&ss_path).unwrap();
+        let ss_table = SSTable::from_disk(&env, &ss_path).unwrap();
         assert_eq!(
-            ss_table.get(&ss_path, &"a3000".to_owned()).unwrap(),
+            ss_table.get(&env, &ss_path, &"a3000".to_owned()).unwrap(),
             Some("aa3000".to_owned())
         );
 
         assert_eq!(
-            ss_table.get(&ss_path, &"a4001".to_owned()).unwrap(),
+            ss_table.get(&env, &ss_path, &"a4001".to_owned()).unwrap(),
+            Some("aa4001".to_owned())
+        );
+    }
+
+    #[test]
+    fn write_numbered() {
+        let env = MemEnv::new();
+        let path = Path::new("write_numbered");
+        let mut sstable = SSTable::from_records(vec![Record::new("a".to_owned(), "b".to_owned())]);
+        sstable.level = 1;
+        let ss_path = sstable.write_numbered(&env, path, 7).unwrap();
+        assert_eq!(ss_path, path.join("7_1.ss"));
+
+        // a table of the same name is never replaced
+        let mut other = SSTable::from_records(vec![Record::new("c".to_owned(), "d".to_owned())]);
+        other.level = 1;
+        assert!(other.write_numbered(&env, path, 7).is_err());
+        let ss_table = SSTable::from_disk(&env, &ss_path).unwrap();
+        assert_eq!(
+            ss_table.get(&env, &ss_path, &"a".to_owned()).unwrap(),
+            Some("b".to_owned())
+        );
+    }
+
+    #[test]
+    fn properties_from_disk() {
+        let mut mem_table = MemTable::new();
+        for i in 0..(BLOCKSIZE / 5) {
+            mem_table.set(
+                format!("{}{}", "a".to_owned(), i),
+                format!("{}{}", "aa".to_owned(), i),
+            );
+        }
+        mem_table.delete("b".to_owned());
+
+        let mut new_sstable = SSTable::from_records(mem_table.to_records());
+
+        let env = MemEnv::new();
+        let path = Path::new("properties_from_disk");
+        let ss_path = new_sstable.write(&env, path).unwrap();
+
+        let ss_table = SSTable::from_disk(&env, &ss_path).unwrap();
+        assert_eq!(ss_table.properties(), new_sstable.properties());
+        assert_eq!(
+            ss_table.properties().num_entries,
+            (BLOCKSIZE / 5 + 1) as u64
+        );
+        assert_eq!(ss_table.properties().num_tombstones, 1);
+        assert_eq!(
+            ss_table.properties().num_data_blocks,
+            new_sstable.data_blocks.len() as u64
+        );
+        assert_eq!(ss_table.properties().format_version, FORMAT_VERSION);
+    }
+
+    #[test]
+    fn from_disk_rejects_other_files() {
+        let env = MemEnv::new();
+        let path = Path::new("from_disk_rejects_other_files");
+        env.create_dir_all(path).unwrap();
+        let ss_path = path.join("1_0.ss");
+        env.create(&ss_path).unwrap().append(&[7; 100]).unwrap();
+
+        let err = SSTable::from_disk(&env, &ss_path).err().unwrap();
+        assert!(matches!(err, Error::Corruption { .. }));
+    }
+
+    #[test]
+    fn from_disk_rejects_unknown_format_version() {
+        let mut new_sstable =
+            SSTable::from_records(vec![Record::new("a".to_owned(), "aa".to_owned())]);
+        new_sstable.footer.format_version = FORMAT_VERSION + 1;
+
+        let env = MemEnv::new();
+        let path = Path::new("from_disk_rejects_unknown_format_version");
+        let ss_path = new_sstable.write(&env, path).unwrap();
+
+        let err = SSTable::from_disk(&env, &ss_path).err().unwrap();
+        assert!(matches!(err, Error::NotSupported(_)));
+    }
+
+    #[test]
+    fn from_disk_legacy_format() {
+        let env = MemEnv::new();
+        let path = Path::new("from_disk_legacy_format");
+        for format_version in OLDEST_SUPPORTED_FORMAT_VERSION..FORMAT_VERSION {
+            // legacy records are larger, so keep them within a single block
+            let mut mem_table = MemTable::new();
+            for i in 0..500 {
+                mem_table.set(
+                    format!("{}{}", "a".to_owned(), i),
+                    format!("{}{}", "aa".to_owned(), i),
+                );
+            }
+            let mut new_sstable = SSTable::from_records(mem_table.to_records());
+            new_sstable.footer.format_version = format_version;
+
+            let ss_path = new_sstable.write(&env, path).unwrap();
+
+            let ss_table = SSTable::from_disk(&env, &ss_path).unwrap();
+            assert_eq!(ss_table.format_version(), format_version);
+            assert_eq!(ss_table.properties().format_version, format_version);
+            assert_eq!(
+                ss_table.get(&env, &ss_path, &"a401".to_owned()).unwrap(),
+                Some("aa401".to_owned())
+            );
+        }
+    }
+
+    /// The bytes of an SSTable holding `records`, laid out like the first version of
+    /// `SSTable::to_bytes` did: native 8 byte integers, and a footer of the index offset and size
+    fn baseline_bytes(records: &[Record]) -> Vec<u8> {
+        let mut blocks: Vec<Vec<u8>> = vec![vec![]];
+        for record in records {
+            let mut bytes = vec![matches!(record, Record::Tombstone { .. }) as u8];
+            bytes.extend(record.key_size().to_le_bytes());
+            bytes.extend(record.get_timestamp().to_le_bytes());
+            bytes.extend(record.get_key().as_bytes());
+            if let Record::Value { value, .. } = record {
+                bytes.extend(value.len().to_le_bytes());
+                bytes.extend(value.as_bytes());
+            }
+            if blocks.last().unwrap().len() + bytes.len() > BLOCKSIZE {
+                blocks.push(vec![]);
+            }
+            blocks.last_mut().unwrap().extend(bytes);
+        }
+
+        let mut index = vec![];
+        for (block_index, block) in blocks.iter().enumerate() {
+            // the first record of a block starts with its flag and key size
+            let key_size = usize::from_le_bytes(block[1..9].try_into().unwrap());
+            index.extend(key_size.to_le_bytes());
+            index.extend(&block[25..25 + key_size]);
+            index.extend((block_index * BLOCKSIZE).to_le_bytes());
+            index.extend(block_index.to_le_bytes());
+        }
+        let mut bytes = vec![];
+        for mut block in blocks {
+            block.resize(BLOCKSIZE, 0);
+            bytes.extend(block);
+        }
+        let index_offset = bytes.len();
+        bytes.extend(&index);
+        bytes.extend(index_offset.to_le_bytes());
+        bytes.extend(index.len().to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn from_disk_format_version_0() {
+        let env = MemEnv::new();
+        let path = Path::new("from_disk_format_version_0/1700000000000000_0.ss");
+        env.create_dir_all(path.parent().unwrap()).unwrap();
+        let records = (0..2000)
+            .map(|i| match i % 10 {
+                3 => Record::new_tombstone(format!("key{:04}", i)),
+                _ => Record::new(format!("key{:04}", i), format!("value{}", i)),
+            })
+            .collect_vec();
+        env.create(path)
+            .unwrap()
+            .append(&baseline_bytes(&records))
+            .unwrap();
+
+        let mut ss_table = SSTable::from_disk(&env, path).unwrap();
+        assert_eq!(ss_table.format_version(), 0);
+        assert_eq!(ss_table.index_entries().count(), 3);
+        for (key, value) in [
+            ("key0000", Some("value0")),
+            ("key1999", Some("value1999")),
+            ("key0013", None),
+            ("key2000", None),
+            ("a", None),
+        ] {
+            assert_eq!(
+                ss_table
+                    .get(&env, path, &key.to_owned())
+                    .unwrap()
+                    .as_deref(),
+                value
+            );
+        }
+        ss_table.load_from_disk(&env, path).unwrap();
+        assert_eq!(ss_table.into_iter().collect_vec(), records);
+    }
+
+    #[test]
+    fn get_corrupt_block() {
+        let mut new_sstable =
+            SSTable::from_records(vec![Record::new("a".to_owned(), "aa".to_owned())]);
+
+        let env = MemEnv::new();
+        let path = Path::new("get_corrupt_block");
+        let ss_path = new_sstable.write(&env, path).unwrap();
+
+        let mut bytes = env.read(&ss_path).unwrap();
+        bytes[3] ^= 0xff;
+        env.create(&ss_path).unwrap().append(&bytes).unwrap();
+
+        let ss_table = SSTable::from_disk(&env, &ss_path).unwrap();
+        let err = ss_table.get(&env, &ss_path, &"a".to_owned()).err().unwrap();
+        assert!(matches!(
+            err,
+            Error::Corruption {
+                offset: Some(0),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn get_compressed() {
+        let mut mem_table = MemTable::new();
+        for i in 0..(BLOCKSIZE / 5) {
+            mem_table.set(
+                format!("{}{}", "a".to_owned(), i),
+                format!("{}{}", "aa".to_owned(), i),
+            );
+        }
+
+        let options = TableOptions {
+            block_size: 4096,
+            compression: Compression::Snappy,
+        };
+        let mut new_sstable = SSTable::from_records_with_options(mem_table.to_records(), &options);
+        let uncompressed_size = SSTable::from_records(mem_table.to_records())
+            .to_bytes()
+            .len();
+        assert!(new_sstable.to_bytes().len() < uncompressed_size);
+
+        let env = MemEnv::new();
+        let path = Path::new("get_compressed");
+        let ss_path = new_sstable.write(&env, path).unwrap();
+
+        let ss_table = SSTable::from_disk(&env, &ss_path).unwrap();
+        assert_eq!(ss_table.properties().compression, "snappy");
+        assert!(ss_table.properties().num_data_blocks > (BLOCKSIZE / 4096) as u64);
+        assert_eq!(
+            ss_table.get(&env, &ss_path, &"a4001".to_owned()).unwrap(),
             Some("aa4001".to_owned())
         );
-        remove_dir_all(path).unwrap();
     }
 }
@@ -1,119 +1,522 @@
 #![allow(dead_code)]
+/*
+
+[header]
+[magic number: u64]
+[format version: u32]
+
+[record 1]
+...
+[record N]
+
+[record]
+[length of the encoded record: u32]
+[crc32 of the encoded record: u32]
+[encoded record]
+
+WAL files written before the header was introduced contain the records back to back, encoded
+with `Encoding::Legacy` and without length or checksum.
+
+*/
+use super::coding::Encoding;
+use super::fs::ensure_absent;
 use super::memtable::MemTable;
 use super::record::Record;
+use crate::env::{Env, RandomAccessFile, WritableFile};
+use crate::error::{Error, Result};
 
-use std::fs::{create_dir_all, File, OpenOptions};
-
-use std::io::Write;
-use std::io::{self, BufReader, BufWriter};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read};
 use std::path::{Path, PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::Arc;
+use std::time::Duration;
 
 const BLOCKSIZE: u16 = 32000;
+const U64_BYTES: usize = (u64::BITS / 8) as usize;
+const U32_BYTES: usize = (u32::BITS / 8) as usize;
+const U128_BYTES: usize = (u128::BITS / 8) as usize;
+/// Stored at the start of every WAL file to tell them apart from legacy WAL files
+const MAGIC_NUMBER: u64 = 0x7273_6b76_7761_6c00; // "rskvwal\0"
+/// Version of the layout written by `WriteAheadLog`
+pub const FORMAT_VERSION: u32 = 1;
+const HEADER_SIZE: usize = U64_BYTES + U32_BYTES;
+/// length and crc32 preceding every record
+const RECORD_HEADER_SIZE: usize = 2 * U32_BYTES;
 
 struct WALBlock {
     entries: Vec<Record>,
 }
 
+/// How far each write is pushed towards the disk before it is acknowledged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncMode {
+    /// Leave records in the in-process buffer, which is written out when it fills up
+    None,
+    /// Hand every record to the operating system, which survives a crash of the process
+    #[default]
+    Flush,
+    /// Flush, then sync the record to disk, which survives a power failure. Syncs only the file
+    /// contents, and the metadata needed to read them back (`fdatasync`)
+    DataSync,
+    /// Flush, then sync the file contents and all its metadata (`fsync`)
+    Fsync,
+    /// Flush every write, but only `fsync` when the last sync is older than the interval. The
+    /// sync rides on the next write, there is no timer: a power failure loses the writes since
+    /// the last sync, which are more than an interval's worth if no write came after them
+    Periodic(Duration),
+}
+
+impl SyncMode {
+    /// Orders the modes by how much they guarantee, so a group of writes can be committed with
+    /// the strongest mode any of them asked for
+    fn strength(&self) -> u8 {
+        match self {
+            SyncMode::None => 0,
+            SyncMode::Flush => 1,
+            SyncMode::Periodic(_) => 2,
+            SyncMode::DataSync => 3,
+            SyncMode::Fsync => 4,
+        }
+    }
+
+    /// Returns the mode that guarantees at least as much as both `self` and `other`
+    pub fn max(self, other: SyncMode) -> SyncMode {
+        match (self, other) {
+            (SyncMode::Periodic(a), SyncMode::Periodic(b)) => SyncMode::Periodic(a.min(b)),
+            _ if other.strength() > self.strength() => other,
+            _ => self,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<SyncMode> {
+        match name {
+            "none" => Ok(SyncMode::None),
+            "flush" => Ok(SyncMode::Flush),
+            "datasync" => Ok(SyncMode::DataSync),
+            "fsync" => Ok(SyncMode::Fsync),
+            _ => {
+                let millis = name
+                    .strip_prefix("periodic:")
+                    .and_then(|millis| millis.parse().ok())
+                    .ok_or_else(|| {
+                        Error::InvalidArgument(format!("unknown sync mode '{}'", name))
+                    })?;
+                Ok(SyncMode::Periodic(Duration::from_millis(millis)))
+            }
+        }
+    }
+}
+
+impl fmt::Display for SyncMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncMode::None => write!(f, "none"),
+            SyncMode::Flush => write!(f, "flush"),
+            SyncMode::DataSync => write!(f, "datasync"),
+            SyncMode::Fsync => write!(f, "fsync"),
+            SyncMode::Periodic(interval) => write!(f, "periodic:{}", interval.as_millis()),
+        }
+    }
+}
+
 pub struct WriteAheadLog {
+    env: Arc<dyn Env>,
     path: PathBuf,
-    buf_writer: BufWriter<File>,
+    file: Box<dyn WritableFile>,
+    sync_mode: SyncMode,
+    /// Time of the `Env` clock at the last sync
+    last_sync: Duration,
 }
 
 impl WriteAheadLog {
-    pub fn new(path: &Path) -> io::Result<WriteAheadLog> {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_micros();
-        create_dir_all(path)?;
-        let path = Path::new(path).join(timestamp.to_string() + ".wal");
-        let file = OpenOptions::new().append(true).create(true).open(&path)?;
-        let buf_writer = BufWriter::new(file);
+    /// Creates a WAL in the directory `path`, named after the clock of `env`
+    pub fn new(env: Arc<dyn Env>, path: &Path) -> Result<WriteAheadLog> {
+        let number = env.now().as_micros() as u64;
+        WriteAheadLog::create(env, path, number)
+    }
+
+    /// Creates the WAL `<number>.wal` in the directory `path`, which must not exist yet
+    pub fn create(env: Arc<dyn Env>, path: &Path, number: u64) -> Result<WriteAheadLog> {
+        env.create_dir_all(path)?;
+        let dir = path;
+        let path = Path::new(path).join(number.to_string() + ".wal");
+        ensure_absent(env.as_ref(), &path)?;
+        let mut file = env.create(&path)?;
 
-        Ok(WriteAheadLog { path, buf_writer })
+        let mut header = MAGIC_NUMBER.to_le_bytes().to_vec();
+        header.extend(FORMAT_VERSION.to_le_bytes());
+        file.append(&header)?;
+        file.flush()?;
+        // make sure the file itself survives a crash, not only the records synced into it
+        env.sync_dir(dir)?;
+
+        Ok(WriteAheadLog {
+            last_sync: env.now(),
+            env,
+            path,
+            file,
+            sync_mode: SyncMode::default(),
+        })
     }
 
-    pub fn from_file(path: &Path) -> io::Result<WriteAheadLog> {
-        let file = OpenOptions::new().append(true).open(path)?;
-        let buf_writer = BufWriter::new(file);
+    pub fn from_file(env: Arc<dyn Env>, path: &Path) -> Result<WriteAheadLog> {
+        let file = env.open_append(path)?;
 
         Ok(WriteAheadLog {
+            last_sync: env.now(),
+            env,
             path: path.to_path_buf(),
-            buf_writer,
+            file,
+            sync_mode: SyncMode::default(),
         })
     }
 
-    pub fn set(&mut self, key: String, value: String) -> io::Result<()> {
+    pub fn set_sync_mode(&mut self, sync_mode: SyncMode) {
+        self.sync_mode = sync_mode;
+    }
+
+    pub fn sync_mode(&self) -> SyncMode {
+        self.sync_mode
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn set(&mut self, key: String, value: String) -> Result<()> {
         let entry = Record::new(key, value);
-        self.buf_writer.write_all(&entry.as_bytes()).unwrap();
-        self.buf_writer.flush().unwrap();
-        Ok(())
+        self.append(&entry)
     }
 
-    pub fn delete(&mut self, key: String) -> io::Result<()> {
+    pub fn delete(&mut self, key: String) -> Result<()> {
         let entry = Record::new_tombstone(key);
-        self.buf_writer.write_all(&entry.as_bytes()).unwrap();
-        self.buf_writer.flush().unwrap();
+        self.append(&entry)
+    }
+
+    fn append(&mut self, record: &Record) -> Result<()> {
+        self.append_batch(std::slice::from_ref(record), self.sync_mode)
+    }
+
+    /// Appends `records` and then makes them as durable as `sync_mode` asks for, so a batch
+    /// costs a single sync no matter how many records it contains
+    pub fn append_batch(&mut self, records: &[Record], sync_mode: SyncMode) -> Result<()> {
+        for record in records {
+            self.file.append(&frame(record))?;
+        }
+
+        match sync_mode {
+            SyncMode::None => {}
+            SyncMode::Flush => self.file.flush()?,
+            SyncMode::DataSync => {
+                self.file.sync_data()?;
+                self.last_sync = self.env.now();
+            }
+            SyncMode::Fsync => self.sync()?,
+            SyncMode::Periodic(interval) => {
+                self.file.flush()?;
+                if self.env.now().saturating_sub(self.last_sync) >= interval {
+                    self.sync()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Hands everything written so far to the operating system, so it can be read back
+    pub fn flush(&mut self) -> Result<()> {
+        self.file.flush()
+    }
+
+    /// Flushes and `fsync`s everything written so far
+    pub fn sync(&mut self) -> Result<()> {
+        self.file.sync()?;
+        self.last_sync = self.env.now();
         Ok(())
     }
 
-    pub fn into_memtable(self) -> MemTable {
+    pub fn into_memtable(self) -> Result<MemTable> {
         let mut mem_table = MemTable::new();
-        for wal_entry in self.into_iter() {
+        for wal_entry in self.iter()? {
             match wal_entry {
                 Record::Tombstone { .. } => {}
                 Record::Value { key, value, .. } => mem_table.set(key, value),
             }
         }
 
-        mem_table
+        Ok(mem_table)
+    }
+
+    /// Returns an iterator over the records in the log, which stops at the first record that
+    /// is truncated or fails its checksum
+    pub fn iter(&self) -> Result<WriteAheadLogIter> {
+        WriteAheadLogIter::open(self.env.as_ref(), &self.path)
+    }
+}
+
+/// Prefixes the encoded record with its length and checksum
+fn frame(record: &Record) -> Vec<u8> {
+    let record_bytes = record.as_bytes();
+    let mut bytes = Vec::with_capacity(RECORD_HEADER_SIZE + record_bytes.len());
+    bytes.extend((record_bytes.len() as u32).to_le_bytes());
+    bytes.extend(crc32fast::hash(&record_bytes).to_le_bytes());
+    bytes.extend(record_bytes);
+    bytes
+}
+
+/// Reads a `RandomAccessFile` front to back
+struct FileReader {
+    file: Arc<dyn RandomAccessFile>,
+    offset: u64,
+}
+
+impl Read for FileReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self
+            .file
+            .read_at(self.offset, buf)
+            .map_err(|err| io::Error::other(err.to_string()))?;
+        self.offset += read as u64;
+        Ok(read)
+    }
+}
+
+/// Why a `WriteAheadLogIter` stopped returning records
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalEnd {
+    /// Every record in the file was read
+    Eof,
+    /// The file ends in the middle of a record, e.g. one that was being written during a crash
+    Truncated,
+    /// A record failed its checksum or could not be decoded
+    Corrupt(String),
+    /// Reading the file failed
+    Io(String),
+}
+
+impl fmt::Display for WalEnd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalEnd::Eof => write!(f, "clean end of file"),
+            WalEnd::Truncated => write!(f, "truncated record"),
+            WalEnd::Corrupt(message) => write!(f, "corrupt record: {}", message),
+            WalEnd::Io(message) => write!(f, "read error: {}", message),
+        }
     }
 }
 
 pub struct WriteAheadLogIter {
-    buf_reader: BufReader<File>,
+    buf_reader: BufReader<FileReader>,
+    encoding: Encoding,
+    /// Size of the file when it was opened, which no record can be longer than
+    size: u64,
+    /// Position just after the last record that was read
+    offset: u64,
+    /// Set once the iterator has stopped
+    end: Option<WalEnd>,
 }
 
-impl IntoIterator for WriteAheadLog {
-    type Item = Record;
+impl WriteAheadLogIter {
+    /// Reads the WAL at `path` without opening it for writing
+    pub fn open(env: &dyn Env, path: &Path) -> Result<WriteAheadLogIter> {
+        WriteAheadLogIter::open_at(env, path, 0)
+    }
 
-    type IntoIter = WriteAheadLogIter;
+    /// Reads the WAL at `path` from `offset`, which must be 0 or an offset returned by
+    /// `WriteAheadLogIter::offset`. Used to pick up records appended since the WAL was last read
+    pub fn open_at(env: &dyn Env, path: &Path, offset: u64) -> Result<WriteAheadLogIter> {
+        let file = env.open(path)?;
+
+        // files without a header were written before records had a length and checksum
+        let mut header = [0; HEADER_SIZE];
+        let (encoding, header_size) = match file.read_exact_at(0, &mut header) {
+            Ok(())
+                if u64::from_le_bytes(header[..U64_BYTES].try_into().unwrap()) == MAGIC_NUMBER =>
+            {
+                (Encoding::Varint, HEADER_SIZE as u64)
+            }
+            _ => (Encoding::Legacy, 0),
+        };
+
+        let offset = offset.max(header_size);
+        let size = file.size()?;
+        let buf_reader = BufReader::new(FileReader { file, offset });
+
+        Ok(WriteAheadLogIter {
+            buf_reader,
+            encoding,
+            size,
+            offset,
+            end: None,
+        })
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        let file = OpenOptions::new().read(true).open(self.path).unwrap();
-        let buf_reader = BufReader::new(file);
-        WriteAheadLogIter { buf_reader }
+    /// Offset just after the last record returned. Reading stops at a record that is
+    /// truncated, e.g. because it is still being written, so reopening the WAL at this offset
+    /// returns that record once it is complete
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Why the iterator stopped, once it has returned `None`
+    pub fn end(&self) -> Option<&WalEnd> {
+        self.end.as_ref()
+    }
+
+    /// Whether the file was written before WAL files had a header and checksums
+    pub fn is_legacy(&self) -> bool {
+        self.encoding == Encoding::Legacy
+    }
+
+    /// Fails with `WalEnd::Eof` if there is nothing left to read
+    fn check_eof(&mut self) -> std::result::Result<(), WalEnd> {
+        match self.buf_reader.fill_buf() {
+            Ok([]) => Err(WalEnd::Eof),
+            Ok(_) => Ok(()),
+            Err(err) => Err(WalEnd::Io(err.to_string())),
+        }
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> std::result::Result<(), WalEnd> {
+        self.buf_reader
+            .read_exact(buf)
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::UnexpectedEof => WalEnd::Truncated,
+                _ => WalEnd::Io(err.to_string()),
+            })
+    }
+
+    /// Bytes of the file after the first `read` bytes of the current record
+    fn bytes_left(&self, read: usize) -> u64 {
+        self.size.saturating_sub(self.offset + read as u64)
+    }
+
+    fn read_record(&mut self) -> std::result::Result<Record, WalEnd> {
+        self.check_eof()?;
+        let mut header = [0; RECORD_HEADER_SIZE];
+        self.read_exact(&mut header)?;
+        let length = u32::from_le_bytes(header[..U32_BYTES].try_into().unwrap()) as usize;
+        let checksum = u32::from_le_bytes(header[U32_BYTES..].try_into().unwrap());
+        // the record is still being written, or was cut short by a crash. The checksum can not
+        // tell this apart from a garbled length, but neither is worth allocating for
+        if length as u64 > self.bytes_left(RECORD_HEADER_SIZE) {
+            return Err(WalEnd::Truncated);
+        }
+
+        let mut record_bytes = vec![0; length];
+        self.read_exact(&mut record_bytes)?;
+        if crc32fast::hash(&record_bytes) != checksum {
+            return Err(WalEnd::Corrupt("checksum mismatch".to_owned()));
+        }
+
+        let record = Record::decode(&record_bytes, &mut 0, Encoding::Varint)
+            .ok_or_else(|| WalEnd::Corrupt("malformed record".to_owned()))?;
+        self.offset += (RECORD_HEADER_SIZE + length) as u64;
+        Ok(record)
+    }
+
+    /// Legacy records have no length prefix, so read them field by field. Nor do they have a
+    /// checksum, and any file without the WAL header is read as a legacy WAL, so every size is
+    /// checked against the rest of the file before it is read
+    fn read_legacy_record(&mut self) -> std::result::Result<Record, WalEnd> {
+        self.check_eof()?;
+        // tombstone flag, key size and timestamp
+        let mut bytes = vec![0; 1 + U64_BYTES + U128_BYTES];
+        self.read_exact(&mut bytes)?;
+        if bytes[0] > 1 {
+            return Err(WalEnd::Corrupt(format!(
+                "invalid tombstone flag {}",
+                bytes[0]
+            )));
+        }
+        let key_size = u64::from_le_bytes(bytes[1..1 + U64_BYTES].try_into().unwrap());
+        self.check_legacy_size("key", bytes.len(), key_size)?;
+
+        let mut key = vec![0; key_size as usize];
+        self.read_exact(&mut key)?;
+        bytes.extend(key);
+
+        if bytes[0] == 0 {
+            let mut value_size = [0; U64_BYTES];
+            self.read_exact(&mut value_size)?;
+            self.check_legacy_size(
+                "value",
+                bytes.len() + U64_BYTES,
+                u64::from_le_bytes(value_size),
+            )?;
+            let mut value = vec![0; u64::from_le_bytes(value_size) as usize];
+            self.read_exact(&mut value)?;
+            bytes.extend(value_size);
+            bytes.extend(value);
+        }
+
+        let record = Record::decode(&bytes, &mut 0, Encoding::Legacy)
+            .ok_or_else(|| WalEnd::Corrupt("malformed record".to_owned()))?;
+        self.offset += bytes.len() as u64;
+        Ok(record)
+    }
+
+    /// Fails if the `field` of `size` bytes, after the first `read` bytes of the record, would
+    /// end past the end of the file
+    fn check_legacy_size(
+        &self,
+        field: &str,
+        read: usize,
+        size: u64,
+    ) -> std::result::Result<(), WalEnd> {
+        if size > self.bytes_left(read) {
+            return Err(WalEnd::Corrupt(format!(
+                "{} size {} is past the end of the file",
+                field, size
+            )));
+        }
+        Ok(())
     }
 }
 
 impl Iterator for WriteAheadLogIter {
     type Item = Record;
     fn next(&mut self) -> Option<Self::Item> {
-        Record::from_reader(&mut self.buf_reader)
+        if self.end.is_some() {
+            return None;
+        }
+        let record = match self.encoding {
+            Encoding::Legacy => self.read_legacy_record(),
+            Encoding::Varint => self.read_record(),
+        };
+        match record {
+            Ok(record) => Some(record),
+            Err(end) => {
+                self.end = Some(end);
+                None
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::fs::remove_dir_all;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
 
     use super::*;
+    use crate::env::MemEnv;
 
     #[test]
     fn set() {
         let path = PathBuf::from("./tests/wal/output/set");
-        let mut wal = WriteAheadLog::new(&path).unwrap();
+        let env = Arc::new(MemEnv::new());
+        let mut wal = WriteAheadLog::new(env.clone(), &path).unwrap();
         wal.set("a".to_owned(), "b".to_owned()).unwrap();
     }
 
     #[test]
     fn iterator() {
         let path = PathBuf::from("./tests/wal/output/iterator");
-        let mut wal = WriteAheadLog::new(&path).unwrap();
+        let env = Arc::new(MemEnv::new());
+        let mut wal = WriteAheadLog::new(env.clone(), &path).unwrap();
         wal.set("a".to_owned(), "b".to_owned()).unwrap();
 
-        let mut wal_iter = wal.into_iter();
+        let mut wal_iter = wal.iter().unwrap();
         match wal_iter.next() {
             Some(Record::Value { key, value, .. }) => {
                 assert_eq!(key, "a");
@@ -130,12 +533,162 @@ mod test {
     #[test]
     fn into_memtable() {
         let path = PathBuf::from("./tests/wal/output/into_memtable");
-        let mut wal = WriteAheadLog::new(&path).unwrap();
+        let env = Arc::new(MemEnv::new());
+        let mut wal = WriteAheadLog::new(env.clone(), &path).unwrap();
         wal.set("a".to_owned(), "b".to_owned()).unwrap();
 
-        let mem_table = wal.into_memtable();
+        let mem_table = wal.into_memtable().unwrap();
 
         assert_eq!(mem_table.get(&"a".to_owned()), Some("b".to_owned()));
-        remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn append_batch() {
+        let path = PathBuf::from("./tests/wal/output/append_batch");
+        let env = Arc::new(MemEnv::new());
+        let mut wal = WriteAheadLog::new(env.clone(), &path).unwrap();
+
+        let records = vec![
+            Record::new("a".to_owned(), "b".to_owned()),
+            Record::new_tombstone("c".to_owned()),
+        ];
+        for sync_mode in [
+            SyncMode::None,
+            SyncMode::DataSync,
+            SyncMode::Periodic(Duration::from_millis(10)),
+        ] {
+            wal.append_batch(&records, sync_mode).unwrap();
+        }
+        wal.sync().unwrap();
+
+        assert_eq!(wal.iter().unwrap().count(), 6);
+    }
+
+    #[test]
+    fn open_at() {
+        let path = PathBuf::from("./tests/wal/output/open_at");
+        let env = Arc::new(MemEnv::new());
+        let mut wal = WriteAheadLog::new(env.clone(), &path).unwrap();
+        wal.set("a".to_owned(), "b".to_owned()).unwrap();
+
+        let mut wal_iter = WriteAheadLogIter::open(env.as_ref(), wal.path()).unwrap();
+        assert_eq!(wal_iter.next().map(|r| r.get_key()), Some("a".to_owned()));
+        assert!(wal_iter.next().is_none());
+        let offset = wal_iter.offset();
+
+        // a record that is only partially written is picked up once it is complete
+        let record = frame(&Record::new("c".to_owned(), "d".to_owned()));
+        let mut writer = env.open_append(wal.path()).unwrap();
+        writer.append(&record[..3]).unwrap();
+        let mut wal_iter = WriteAheadLogIter::open_at(env.as_ref(), wal.path(), offset).unwrap();
+        assert!(wal_iter.next().is_none());
+        assert_eq!(wal_iter.offset(), offset);
+
+        writer.append(&record[3..]).unwrap();
+        let mut wal_iter = WriteAheadLogIter::open_at(env.as_ref(), wal.path(), offset).unwrap();
+        assert_eq!(wal_iter.next().map(|r| r.get_key()), Some("c".to_owned()));
+        assert!(wal_iter.next().is_none());
+    }
+
+    #[test]
+    fn end() {
+        let path = PathBuf::from("./tests/wal/output/end");
+        let env = Arc::new(MemEnv::new());
+        let mut wal = WriteAheadLog::new(env.clone(), &path).unwrap();
+        wal.set("a".to_owned(), "b".to_owned()).unwrap();
+        wal.set("c".to_owned(), "d".to_owned()).unwrap();
+        let ends = |env: &MemEnv| {
+            let mut wal_iter = WriteAheadLogIter::open(env, wal.path()).unwrap();
+            let count = wal_iter.by_ref().count();
+            (count, wal_iter.end().cloned().unwrap())
+        };
+        assert_eq!(ends(&env), (2, WalEnd::Eof));
+
+        let mut bytes = env.read(wal.path()).unwrap();
+        bytes.pop();
+        env.create(wal.path()).unwrap().append(&bytes).unwrap();
+        assert_eq!(ends(&env), (1, WalEnd::Truncated));
+
+        // flip a bit in the value of the first record
+        bytes[HEADER_SIZE + RECORD_HEADER_SIZE + 2] ^= 1;
+        env.create(wal.path()).unwrap().append(&bytes).unwrap();
+        assert_eq!(
+            ends(&env),
+            (0, WalEnd::Corrupt("checksum mismatch".to_owned()))
+        );
+    }
+
+    #[test]
+    fn random_bytes() {
+        let path = PathBuf::from("./tests/wal/output/random_bytes/1.wal");
+        let env = MemEnv::new();
+        env.create_dir_all(path.parent().unwrap()).unwrap();
+        let mut rng = StdRng::seed_from_u64(28);
+        for i in 0..200 {
+            let mut bytes = vec![0; rng.gen_range(0..5000)];
+            rng.fill(&mut bytes[..]);
+            // half of the files pass for a WAL with a header
+            if i % 2 == 0 && bytes.len() >= HEADER_SIZE {
+                bytes[..U64_BYTES].copy_from_slice(&MAGIC_NUMBER.to_le_bytes());
+            }
+            env.create(&path).unwrap().append(&bytes).unwrap();
+
+            let mut wal_iter = WriteAheadLogIter::open(&env, &path).unwrap();
+            wal_iter.by_ref().count();
+            assert!(wal_iter.offset() <= bytes.len() as u64);
+            match wal_iter.end().unwrap() {
+                WalEnd::Eof => assert_eq!(wal_iter.offset(), bytes.len() as u64),
+                WalEnd::Truncated | WalEnd::Corrupt(_) => {}
+                WalEnd::Io(message) => panic!("read error: {}", message),
+            }
+        }
+
+        // a legacy record with a key longer than the file
+        let mut bytes = vec![0];
+        bytes.extend(u64::MAX.to_le_bytes());
+        bytes.extend(0u128.to_le_bytes());
+        env.create(&path).unwrap().append(&bytes).unwrap();
+        let mut wal_iter = WriteAheadLogIter::open(&env, &path).unwrap();
+        assert!(wal_iter.next().is_none());
+        assert!(matches!(wal_iter.end(), Some(WalEnd::Corrupt(_))));
+    }
+
+    #[test]
+    fn sync_mode_names() {
+        for sync_mode in [
+            SyncMode::None,
+            SyncMode::Flush,
+            SyncMode::DataSync,
+            SyncMode::Fsync,
+            SyncMode::Periodic(Duration::from_millis(100)),
+        ] {
+            assert_eq!(
+                SyncMode::from_name(&sync_mode.to_string()).unwrap(),
+                sync_mode
+            );
+        }
+        assert_eq!(SyncMode::Flush.max(SyncMode::Fsync), SyncMode::Fsync);
+        assert!(SyncMode::from_name("sometimes").is_err());
+    }
+
+    #[test]
+    fn legacy_iterator() {
+        let path = PathBuf::from("./tests/wal/output/legacy_iterator");
+        let env = Arc::new(MemEnv::new());
+        env.create_dir_all(&path).unwrap();
+        let wal_path = path.join("1.wal");
+
+        let records = vec![
+            Record::new("a".to_owned(), "b".to_owned()),
+            Record::new_tombstone("c".to_owned()),
+        ];
+        let bytes: Vec<u8> = records
+            .iter()
+            .flat_map(|r| r.encode(Encoding::Legacy))
+            .collect();
+        env.create(&wal_path).unwrap().append(&bytes).unwrap();
+
+        let wal = WriteAheadLog::from_file(env, &wal_path).unwrap();
+        assert_eq!(wal.iter().unwrap().collect::<Vec<_>>(), records);
     }
 }